@@ -0,0 +1,148 @@
+use base::{
+    btree::{
+        key,
+        node::{Node, NodeType, NodeView},
+        slot::{Either, Slot},
+    },
+    catalog::{Column, Schema, Type},
+    page::{PageBuf, PAGE_SIZE},
+    table::tuple::{Tuple, TupleBuilder, Value},
+};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+
+/// Keys in the leaf, every even number below `2 * KEYS`. Just short of a split.
+const KEYS: i32 = 128;
+
+fn key(schema: &Schema, k: i32) -> Tuple {
+    key::encode(schema, &[], &k.into())
+}
+
+fn schema() -> Schema {
+    Schema::new(vec![Column {
+        name: "".into(),
+        ty: Type::Int,
+        offset: 0,
+    }])
+}
+
+fn leaf(schema: &Schema) -> PageBuf {
+    let mut buf = [0; PAGE_SIZE];
+    let mut view = NodeView::<_, i32>::new(&mut buf);
+    view.init(0, NodeType::Leaf, true, -1);
+    for k in 0..KEYS {
+        view.push(&Slot(key(schema, k * 2), Either::Value(k)))
+            .expect("leaf should have room");
+    }
+    assert!(!view.almost_full(&key(schema, KEYS)));
+
+    buf
+}
+
+fn bench_get(c: &mut Criterion) {
+    let schema = schema();
+    let buf = leaf(&schema);
+    let keys: Vec<Tuple> = (0..KEYS).map(|k| key(&schema, k * 2)).collect();
+
+    let mut group = c.benchmark_group("node_get");
+    group.bench_function("copy", |b| {
+        b.iter(|| {
+            for key in &keys {
+                let node: Node<i32> = Node::from(&buf, 0).expect("leaf is a node");
+                black_box(node.get(key).cloned());
+            }
+        })
+    });
+    group.bench_function("in_place", |b| {
+        b.iter(|| {
+            for key in &keys {
+                let view = NodeView::<_, i32>::new(&buf);
+                black_box(view.get(key));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let schema = schema();
+    let buf = leaf(&schema);
+    let key = key(&schema, KEYS);
+
+    let mut group = c.benchmark_group("node_insert");
+    group.bench_function("copy", |b| {
+        b.iter_batched(
+            || buf,
+            |buf| {
+                let mut node: Node<i32> = Node::from(&buf, 0).expect("leaf is a node");
+                node.replace(Slot(key.clone(), Either::Value(0)));
+                PageBuf::try_from(&node).expect("node should fit a page")
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("in_place", |b| {
+        b.iter_batched(
+            || buf,
+            |mut buf| {
+                NodeView::<_, i32>::new(&mut buf)
+                    .replace(Slot(key.clone(), Either::Value(0)))
+                    .expect("key should fit");
+                buf
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn internal(schema: &Schema, keys: &[Tuple]) -> PageBuf {
+    let mut buf = [0; PAGE_SIZE];
+    let mut view = NodeView::<_, i32>::new(&mut buf);
+    view.init(0, NodeType::Internal, false, -1);
+    for (i, k) in keys.iter().enumerate() {
+        view.push(&Slot(key::encode(schema, &[], k), Either::Pointer(i as i32)))
+            .expect("internal node should have room");
+    }
+
+    buf
+}
+
+/// A lookup searches one internal node per level of the tree. Keys are encoded, so both types
+/// are compared as bytes.
+fn bench_find_child(c: &mut Criterion) {
+    let int = schema();
+    let varchar: Schema = [("", Type::Varchar)].into();
+
+    let mut group = c.benchmark_group("find_child");
+    for fanout in [8, 32, 128] {
+        let ints: Vec<Tuple> = (0..fanout).map(|k| (k * 2).into()).collect();
+        let strings: Vec<Tuple> = (0..fanout)
+            .map(|k| Tuple {
+                data: TupleBuilder::new()
+                    .add(&Value::Varchar(format!("{:08}", k * 2)))
+                    .build(),
+                ..Default::default()
+            })
+            .collect();
+
+        group.throughput(Throughput::Elements(fanout as u64));
+        for (name, schema, keys) in [("int", &int, &ints), ("varchar", &varchar, &strings)] {
+            let buf = internal(schema, keys);
+            let keys: Vec<_> = keys.iter().map(|k| key::encode(schema, &[], k)).collect();
+            group.bench_with_input(BenchmarkId::new(name, fanout), &keys, |b, keys| {
+                let view = NodeView::<_, i32>::new(&buf);
+                b.iter(|| {
+                    for key in keys {
+                        black_box(view.find_child(key));
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_get, bench_insert, bench_find_child);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+use base::{
+    disk::{mapped::Mapped, Disk, FileSystem},
+    page::PAGE_SIZE,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+const FILE: &str = "bench_disk.db";
+const PAGES: i32 = 4096;
+
+fn scan(disk: &impl Disk) {
+    for page_id in 0..PAGES {
+        let page = disk.read_page(page_id).unwrap();
+        criterion::black_box(page);
+    }
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let fs = FileSystem::new(FILE).unwrap();
+    for page_id in 0..PAGES {
+        fs.write_page(page_id, &[page_id as u8; PAGE_SIZE]).unwrap();
+    }
+
+    let mut group = c.benchmark_group("scan");
+    group.throughput(Throughput::Bytes(PAGES as u64 * PAGE_SIZE as u64));
+    group.bench_function(BenchmarkId::new("file_system", PAGES), |b| b.iter(|| scan(&fs)));
+
+    let mapped = Mapped::new(FILE).unwrap();
+    group.bench_function(BenchmarkId::new("mapped", PAGES), |b| b.iter(|| scan(&mapped)));
+    group.finish();
+
+    std::fs::remove_file(FILE).unwrap();
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
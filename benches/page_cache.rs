@@ -0,0 +1,59 @@
+use std::thread;
+
+use base::{
+    disk::Memory,
+    page::PageId,
+    page_cache::{PageCache, SharedPageCache},
+    replacer::LRU,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const FRAMES: usize = 1024;
+const FETCHES: usize = 10_000;
+
+fn cache(shards: usize) -> SharedPageCache<Memory> {
+    let pc = PageCache::with_capacity(Memory::new(), LRU::with_shards(2, shards), 0, FRAMES);
+    for _ in 0..FRAMES {
+        pc.new_page().expect("cache should have free frames");
+    }
+
+    pc
+}
+
+/// Every thread fetches `FETCHES` random resident pages, so only the lookup and pinning is
+/// measured.
+fn fetch(pc: &SharedPageCache<Memory>, threads: usize) {
+    thread::scope(|s| {
+        for t in 0..threads {
+            s.spawn(move || {
+                let mut rng = StdRng::seed_from_u64(t as u64);
+                for _ in 0..FETCHES {
+                    let page = pc
+                        .fetch_page(rng.gen_range(0..FRAMES as PageId))
+                        .expect("page should be resident");
+                    drop(page.read());
+                }
+            });
+        }
+    });
+}
+
+fn bench_fetch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fetch_resident");
+    for shards in [1, 16] {
+        let pc = cache(shards);
+        for threads in [1, 2, 4, 8] {
+            group.throughput(Throughput::Elements((FETCHES * threads) as u64));
+            group.bench_with_input(
+                BenchmarkId::new(format!("shards_{shards}"), threads),
+                &threads,
+                |b, threads| b.iter(|| fetch(&pc, *threads)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_fetch);
+criterion_main!(benches);
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use base::{
+    page::PageId,
+    page_cache::FrameId,
+    replacer::{ARCReplacer, AccessType, ClockReplacer, LRUKReplacer, Replacer, TwoQReplacer},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const FRAMES: usize = 256;
+
+type NewReplacer = fn() -> Box<dyn Replacer>;
+
+/// Page ids to replay, one per line in the file at `REPLACER_TRACE`. Defaults to a skewed
+/// workload over a hot set interrupted by sequential scans.
+fn trace() -> Vec<PageId> {
+    if let Ok(path) = std::env::var("REPLACER_TRACE") {
+        let trace = std::fs::read_to_string(path).expect("could not read trace");
+        return trace
+            .lines()
+            .map(|l| l.trim().parse().expect("trace lines should be page ids"))
+            .collect();
+    }
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut trace = Vec::new();
+    for round in 0..20 {
+        for _ in 0..2000 {
+            // 80% of accesses go to 20% of a set slightly larger than the cache
+            let page_id = if rng.gen_bool(0.8) {
+                rng.gen_range(0..FRAMES as PageId / 5)
+            } else {
+                rng.gen_range(0..FRAMES as PageId * 2)
+            };
+            trace.push(page_id);
+        }
+
+        let start = 10_000 + round * 1000;
+        trace.extend(start..start + 1000);
+    }
+
+    trace
+}
+
+/// Replays `trace` against a cache of `FRAMES` frames, returning the number of hits.
+fn replay(replacer: &mut dyn Replacer, trace: &[PageId]) -> usize {
+    let mut resident: HashMap<PageId, FrameId> = HashMap::new();
+    let mut frames: Vec<Option<PageId>> = vec![None; FRAMES];
+    let mut free: Vec<FrameId> = (0..FRAMES).collect();
+    let mut hits = 0;
+
+    for &page_id in trace {
+        let i = match resident.get(&page_id) {
+            Some(i) => {
+                hits += 1;
+                *i
+            }
+            None => {
+                let i = match free.pop() {
+                    Some(i) => i,
+                    None => replacer.evict().expect("nothing is pinned"),
+                };
+                if let Some(old) = frames[i].replace(page_id) {
+                    resident.remove(&old);
+                }
+                replacer.remove(i);
+                resident.insert(page_id, i);
+
+                i
+            }
+        };
+
+        replacer.record_access(i, page_id, AccessType::Get);
+        replacer.pin(i);
+        replacer.unpin(i);
+    }
+
+    hits
+}
+
+fn bench_replacers(c: &mut Criterion) {
+    let trace = trace();
+
+    let mut group = c.benchmark_group("replay");
+    let policies: [(&str, NewReplacer); 4] = [
+        ("lru_k", || Box::new(LRUKReplacer::new(2))),
+        ("clock", || Box::new(ClockReplacer::default())),
+        ("2q", || Box::new(TwoQReplacer::default())),
+        ("arc", || Box::new(ARCReplacer::default())),
+    ];
+    for (name, new) in policies {
+        let hits = replay(new().as_mut(), &trace);
+        println!(
+            "{name}: {hits}/{} hits ({:.1}%)",
+            trace.len(),
+            hits as f64 * 100.0 / trace.len() as f64
+        );
+
+        group.bench_function(BenchmarkId::new(name, trace.len()), |b| {
+            b.iter(|| replay(new().as_mut(), &trace))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_replacers);
+criterion_main!(benches);
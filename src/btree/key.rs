@@ -0,0 +1,272 @@
+use bytes::{BufMut, BytesMut};
+
+use crate::{
+    catalog::{Schema, Type},
+    error::Error,
+    page::PageId,
+    table::tuple::{Tuple, TupleBuilder, Value},
+};
+
+/*
+    Keys are encoded so comparing their bytes orders them the same as comparing the tuples:
+
+    Integers: big endian with the sign bit flipped
+    Bool: 0 or 1
+    Varchar: the bytes with 0x00 escaped as 0x00 0xFF, terminated by 0x00 0x00 so that a prefix
+    sorts first
+
+    Every byte of a descending column is inverted.
+*/
+
+const ESCAPE: u8 = 0xFF;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+fn descending(order: &[Order], i: usize) -> bool {
+    order.get(i) == Some(&Order::Desc)
+}
+
+/// Encodes `tuple` as a key. Columns missing from `order` are ascending.
+pub fn encode(schema: &Schema, order: &[Order], tuple: &Tuple) -> Tuple {
+    let data = &tuple.data[..];
+    let mut ret = BytesMut::with_capacity(tuple.size() + 2);
+
+    for (i, column) in schema.iter().enumerate() {
+        let start = ret.len();
+        match column.ty {
+            Type::Bool => ret.put_u8(data[column.offset]),
+            Type::TinyInt | Type::Int | Type::BigInt => {
+                ret.put(&data[column.offset..column.offset + column.size()]);
+                ret[start] ^= 0x80;
+            }
+            Type::Varchar => {
+                let at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]) as usize;
+                let (offset, size) = (at(column.offset), at(column.offset + 2));
+
+                for &b in &data[offset..offset + size] {
+                    ret.put_u8(b);
+                    if b == 0 {
+                        ret.put_u8(ESCAPE);
+                    }
+                }
+                ret.put_u16(0);
+            }
+        }
+
+        if descending(order, i) {
+            ret[start..].iter_mut().for_each(|b| *b = !*b);
+        }
+    }
+
+    Tuple {
+        rid: tuple.rid,
+        data: ret,
+    }
+}
+
+/// Decodes a key written by `encode` back into a tuple laid out by `schema`. Keys from `page_id`
+/// that `encode` couldn't have written are corrupted.
+pub fn decode(
+    schema: &Schema,
+    order: &[Order],
+    key: &Tuple,
+    page_id: PageId,
+) -> crate::Result<Tuple> {
+    let mut tuple = TupleBuilder::new();
+
+    let mut pos = 0;
+    for (i, column) in schema.iter().enumerate() {
+        let mask = if descending(order, i) { 0xFF } else { 0 };
+        let mut next = || {
+            let b = key.data.get(pos).ok_or(Error::Corrupted { page_id })?;
+            pos += 1;
+            Ok::<_, Error>(b ^ mask)
+        };
+
+        let value = match column.ty {
+            Type::Bool => Value::Bool(next()? > 0),
+            Type::TinyInt => Value::TinyInt((next()? ^ 0x80) as i8),
+            Type::Int => {
+                let mut buf = [0; 4];
+                for b in &mut buf {
+                    *b = next()?;
+                }
+                buf[0] ^= 0x80;
+                Value::Int(i32::from_be_bytes(buf))
+            }
+            Type::BigInt => {
+                let mut buf = [0; 8];
+                for b in &mut buf {
+                    *b = next()?;
+                }
+                buf[0] ^= 0x80;
+                Value::BigInt(i64::from_be_bytes(buf))
+            }
+            Type::Varchar => {
+                let mut str = Vec::new();
+                loop {
+                    match next()? {
+                        0 => match next()? {
+                            0 => break,
+                            ESCAPE => str.push(0),
+                            _ => return Err(Error::Corrupted { page_id }),
+                        },
+                        b => str.push(b),
+                    }
+                }
+                Value::Varchar(String::from_utf8(str).map_err(|_| Error::Corrupted { page_id })?)
+            }
+        };
+        tuple = tuple.add(&value);
+    }
+    if pos != key.size() {
+        return Err(Error::Corrupted { page_id });
+    }
+
+    Ok(Tuple {
+        rid: key.rid,
+        data: tuple.build(),
+    })
+}
+
+/// Smallest key that sorts after `key`.
+pub fn successor(key: &Tuple) -> Tuple {
+    let mut data = BytesMut::with_capacity(key.size() + 1);
+    data.put(&key.data[..]);
+    data.put_u8(0);
+
+    Tuple { rid: key.rid, data }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        btree::key::{decode, encode, successor, Order},
+        catalog::{Schema, Type},
+        error::Error,
+        table::tuple::{Tuple, TupleBuilder, Value},
+    };
+
+    fn tuple(values: &[Value]) -> Tuple {
+        Tuple {
+            data: values
+                .iter()
+                .fold(TupleBuilder::new(), |b, v| b.add(v))
+                .build(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_key_order() -> crate::Result<()> {
+        let schema: Schema = [
+            ("a", Type::Int),
+            ("b", Type::Varchar),
+            ("c", Type::TinyInt),
+            ("d", Type::Bool),
+            ("e", Type::BigInt),
+        ]
+        .into();
+
+        let mut tuples = Vec::new();
+        for a in [i32::MIN, -1, 0, 1, i32::MAX] {
+            for b in ["", "\0", "\0\0", "\0a", "a", "a\0", "ab", "b"] {
+                for c in [i8::MIN, -1, 0, i8::MAX] {
+                    for d in [false, true] {
+                        for e in [i64::MIN, 0, i64::MAX] {
+                            tuples.push(tuple(&[
+                                Value::Int(a),
+                                Value::Varchar(b.into()),
+                                Value::TinyInt(c),
+                                Value::Bool(d),
+                                Value::BigInt(e),
+                            ]));
+                        }
+                    }
+                }
+            }
+        }
+
+        for order in [
+            vec![],
+            vec![Order::Desc],
+            vec![
+                Order::Asc,
+                Order::Desc,
+                Order::Desc,
+                Order::Asc,
+                Order::Desc,
+            ],
+        ] {
+            let flip = |i: usize, ord: std::cmp::Ordering| match order.get(i) {
+                Some(Order::Desc) => ord.reverse(),
+                _ => ord,
+            };
+
+            let keys: Vec<_> = tuples.iter().map(|t| encode(&schema, &order, t)).collect();
+            for (t, k) in tuples.iter().zip(&keys) {
+                assert!(decode(&schema, &order, k, 0)? == *t);
+            }
+
+            // Compare the encoded bytes against the tuples column by column
+            for (i, a) in tuples.iter().enumerate().step_by(7) {
+                for (j, b) in tuples.iter().enumerate() {
+                    let want = schema
+                        .iter()
+                        .enumerate()
+                        .map(|(c, col)| flip(c, a.get_value(col).cmp(&b.get_value(col))))
+                        .find(|ord| ord.is_ne())
+                        .unwrap_or(std::cmp::Ordering::Equal);
+                    assert!(keys[i].data.cmp(&keys[j].data) == want, "{a:?} {b:?}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_corrupted() {
+        let schema: Schema = [("a", Type::Int), ("b", Type::Varchar)].into();
+        let key = encode(&schema, &[], &tuple(&[Value::Int(1), Value::Varchar("a".into())]));
+        let want = Err(Error::Corrupted { page_id: 3 });
+
+        // Cut short
+        let mut short = key.clone();
+        short.data.truncate(key.size() - 1);
+        assert!(decode(&schema, &[], &short, 3) == want);
+
+        // Not utf8
+        let mut invalid = key.clone();
+        invalid.data[4] = 0xC0;
+        assert!(decode(&schema, &[], &invalid, 3) == want);
+
+        // 0x00 that's neither escaped nor the terminator
+        let mut escape = key.clone();
+        escape.data[4] = 0;
+        escape.data[5] = 1;
+        assert!(decode(&schema, &[], &escape, 3) == want);
+
+        // Bytes after the last column
+        let mut long = key.clone();
+        long.data.extend_from_slice(&[0]);
+        assert!(decode(&schema, &[], &long, 3) == want);
+    }
+
+    #[test]
+    fn test_key_successor() {
+        let schema: Schema = [("a", Type::Varchar)].into();
+
+        let a = encode(&schema, &[], &tuple(&[Value::Varchar("a".into())]));
+        let a0 = encode(&schema, &[], &tuple(&[Value::Varchar("a\0".into())]));
+        let b = encode(&schema, &[], &tuple(&[Value::Varchar("b".into())]));
+
+        let next = successor(&a);
+        assert!(a.data < next.data && next.data < a0.data && next.data < b.data);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod key;
 pub mod node;
 pub mod slot;
 
@@ -5,22 +6,26 @@ use std::marker::PhantomData;
 
 use crate::{
     btree::{
-        node::{Node, NodeType},
+        key::Order,
+        node::{Node, NodeType, NodeView},
         slot::{Either, Slot},
     },
     catalog::Schema,
     disk::{Disk, FileSystem},
-    page::{PageBuf, PageId, PageReadGuard, PageWriteGuard},
-    page_cache::SharedPageCache,
+    error::Error,
+    page::{self, PageBuf, PageId, PageReadGuard, PageType, SegmentId},
+    page_cache::{guard::PinWrite, Pin, SharedPageCache},
+    replacer::AccessType,
     storable::Storable,
-    table::tuple::{Comparand, Tuple},
-    writep,
+    table::tuple::Tuple,
 };
 
 pub struct BTree<'s, V, D: Disk = FileSystem> {
     root: PageId,
     pc: SharedPageCache<D>,
+    segment: SegmentId,
     schema: &'s Schema,
+    order: Vec<Order>,
     _data: PhantomData<V>,
 }
 
@@ -30,80 +35,129 @@ where
     D: Disk,
 {
     pub fn new(pc: SharedPageCache<D>, schema: &'s Schema) -> Self {
+        Self::in_segment(pc, 0, schema)
+    }
+
+    /// Creates an empty tree with all of its pages in `segment`.
+    pub fn in_segment(pc: SharedPageCache<D>, segment: SegmentId, schema: &'s Schema) -> Self {
         Self {
             root: -1,
             pc,
+            segment,
             schema,
+            order: Vec::new(),
             _data: PhantomData,
         }
     }
 
     pub fn new_with_root(pc: SharedPageCache<D>, root: PageId, schema: &'s Schema) -> Self {
+        let segment = if root == -1 { 0 } else { page::segment_of(root) };
+
         Self {
             root,
             pc,
+            segment,
             schema,
+            order: Vec::new(),
             _data: PhantomData,
         }
     }
 
+    /// Sets the order of the key columns, which are ascending by default. A tree has to be opened
+    /// with the order it was built with.
+    pub fn with_order(mut self, order: &[Order]) -> Self {
+        self.order = order.to_vec();
+        self
+    }
+
     pub fn root(&self) -> PageId {
         self.root
     }
 
+    /// Keys are stored encoded so that nodes can compare them as bytes.
+    fn encode(&self, key: &Tuple) -> Tuple {
+        key::encode(self.schema, &self.order, key)
+    }
+
+    fn decode(&self, key: &Tuple, page_id: PageId) -> crate::Result<Tuple> {
+        key::decode(self.schema, &self.order, key, page_id)
+    }
+
+    /// Views the node in `pin`'s page, checking its cells the first time it's used after being
+    /// read from disk.
+    fn view<'a>(pin: &Pin, buf: &'a PageBuf) -> crate::Result<NodeView<&'a PageBuf, V>> {
+        let node = NodeView::load(buf, pin.id)?;
+        if !pin.is_checked() {
+            node.check(pin.id)?;
+            pin.set_checked();
+        }
+
+        Ok(node)
+    }
+
     // TODO: One thread could split the root whilst another holds a pin to the root. Should double
     // check is_root
     pub fn insert(&mut self, key: &Tuple, value: &V) -> crate::Result<()> {
-        let pin;
+        if key.size() > Node::<V>::max_key_size() {
+            return Err(Error::KeyTooLarge { size: key.size() });
+        }
+        // Escaping can make varchar keys longer
+        let key = &self.encode(key);
+        if key.size() > Node::<V>::max_key_size() {
+            return Err(Error::KeyTooLarge { size: key.size() });
+        }
+
         let rpage = match self.root {
             -1 => {
-                pin = self.pc.new_page()?;
-                let node: Node<V> = Node::new(pin.id, NodeType::Leaf, true, &self.schema);
-                let mut page = pin.write();
-                writep!(page, &PageBuf::from(&node));
+                let pin = self.pc.new_page_in(self.segment)?;
+                pin.set_type(PageType::BTreeLeaf);
+                let node: Node<V> = Node::new(pin.id, NodeType::Leaf, true);
+                let mut page = pin.write_owned();
+                page.data = PageBuf::try_from(&node)?;
                 page
             }
-            id => {
-                pin = self.pc.fetch_page(id)?;
-                pin.write()
-            }
+            id => self.pc.fetch_page_with(id, AccessType::Get)?.write_owned(),
         };
-        self.root = rpage.id;
+        self.root = rpage.id();
 
-        if let Some((s, os)) = self._insert(None, rpage, key, value)? {
-            let new_root_page = self.pc.new_page()?;
-            let mut new_root = Node::new(new_root_page.id, NodeType::Internal, true, &self.schema);
+        if let Some((s, os)) = self._insert(rpage, key, value)? {
+            let new_root_page = self.pc.new_page_in(self.segment)?;
+            new_root_page.set_type(PageType::BTreeInternal);
+            let mut new_root = Node::new(new_root_page.id, NodeType::Internal, true);
             self.root = new_root.id;
 
             new_root.insert(s);
             new_root.insert(os);
 
-            let mut w = new_root_page.write();
-            writep!(w, &PageBuf::from(&new_root));
+            new_root_page.write_owned().data = PageBuf::try_from(&new_root)?;
         }
 
         Ok(())
     }
 
     // TODO: Duplicate code for find and insert
-    fn _insert<'a>(
-        &'a self,
-        mut prev_page: Option<&'a PageWriteGuard<'a>>,
-        mut page: PageWriteGuard<'a>,
+    fn _insert(
+        &self,
+        mut page: PinWrite,
         key: &Tuple,
         value: &V,
     ) -> crate::Result<Option<(Slot<V>, Slot<V>)>> {
-        let mut node: Node<V> = Node::from(&page.data, &self.schema);
+        if !Self::view(page.pin(), &page.data)?.almost_full(key) {
+            self.insert_in_place(page, key, value)?;
+            return Ok(None);
+        }
+
+        let mut node: Node<V> = Node::from(&page.data, page.id)?;
 
-        let mut split = None;
-        if node.almost_full() {
-            let new_page = self.pc.new_page()?;
-            let mut npage = new_page.write();
-            let mut nnode = node.split(new_page.id);
+        let split = {
+            let new_page = self.pc.new_page_in(self.segment)?;
+            new_page.set_type(node.t.into());
+            let mut npage = new_page.write_owned();
+            let mut nnode = node.split(npage.id());
 
-            if Comparand(&self.schema, key) >= Comparand(&self.schema, node.last_key().unwrap()) {
+            if key.data >= node.last_key().unwrap().data {
                 // Write the node
-                writep!(page, &PageBuf::from(&node));
+                page.data = PageBuf::try_from(&node)?;
 
                 // We don't need to keep a lock on this side of the tree
                 drop(page);
@@ -116,7 +170,7 @@ where
                         None if nnode.t == NodeType::Internal => {
                             // Bump the last node if no pointer found
                             let Slot(_, v) = nnode.pop_last().unwrap();
-                            nnode.insert(Slot(key.next(&self.schema), v));
+                            nnode.insert(Slot(key::successor(key), v));
 
                             match nnode.find_child(&key) {
                                 Some(ptr) => ptr,
@@ -126,23 +180,20 @@ where
                         None => {
                             // Reached leaf node
                             nnode.replace(Slot(key.clone(), Either::Value(value.clone())));
-                            writep!(npage, &PageBuf::from(&nnode));
+                            npage.data = PageBuf::try_from(&nnode)?;
 
                             return Ok(node.get_separators(Some(nnode)));
                         }
                     };
 
-                    let child_page = self.pc.fetch_page(ptr)?;
-                    let cpage = child_page.write();
-
-                    prev_page.take();
-                    if let Some((s, os)) = self._insert(Some(&npage), cpage, key, value)? {
+                    let cpage = self.pc.fetch_page_with(ptr, AccessType::Get)?.write_owned();
+                    if let Some((s, os)) = self._insert(cpage, key, value)? {
                         nnode.replace(s);
                         nnode.replace(os);
                     }
 
                     // Write the new node
-                    writep!(npage, &PageBuf::from(&nnode));
+                    npage.data = PageBuf::try_from(&nnode)?;
 
                     return Ok(node.get_separators(Some(nnode)));
                 }
@@ -150,10 +201,10 @@ where
 
             // Write the new node
             // Original node is written below
-            writep!(npage, &PageBuf::from(&nnode));
+            npage.data = PageBuf::try_from(&nnode)?;
 
-            split = Some(nnode)
-        }
+            Some(nnode)
+        };
 
         // Find and insert
         {
@@ -163,7 +214,7 @@ where
                 None if node.t == NodeType::Internal => {
                     // Bump the last node if no pointer found
                     let Slot(_, v) = node.pop_last().unwrap();
-                    node.insert(Slot(key.next(&self.schema), v));
+                    node.insert(Slot(key::successor(key), v));
 
                     match node.find_child(&key) {
                         Some(ptr) => ptr,
@@ -173,28 +224,60 @@ where
                 None => {
                     // Reached leaf node
                     node.replace(Slot(key.clone(), Either::Value(value.clone())));
-                    writep!(page, &PageBuf::from(&node));
+                    page.data = PageBuf::try_from(&node)?;
 
                     return Ok(node.get_separators(split));
                 }
             };
 
-            let child_page = self.pc.fetch_page(ptr)?;
-            let cpage = child_page.write();
-
-            prev_page.take();
-            if let Some((s, os)) = self._insert(Some(&page), cpage, key, value)? {
+            let cpage = self.pc.fetch_page_with(ptr, AccessType::Get)?.write_owned();
+            if let Some((s, os)) = self._insert(cpage, key, value)? {
                 node.replace(s);
                 node.replace(os);
             }
 
             // Write the original node
-            writep!(page, &PageBuf::from(&node));
+            page.data = PageBuf::try_from(&node)?;
 
             Ok(node.get_separators(split))
         }
     }
 
+    /// Inserts into a node that won't split, without copying it out of the page.
+    fn insert_in_place(&self, mut page: PinWrite, key: &Tuple, value: &V) -> crate::Result<()> {
+        let view = NodeView::<_, V>::new(&page.data);
+        let ptr = match view.find_child(key) {
+            Some(ptr) => ptr,
+            None if view.t() == NodeType::Internal => {
+                // Bump the last node if no pointer found
+                let mut view = NodeView::<_, V>::new(&mut page.data);
+                let Slot(_, v) = view.pop_last().unwrap();
+                view.insert(Slot(key::successor(key), v))?;
+
+                match view.find_child(key) {
+                    Some(ptr) => ptr,
+                    None => unreachable!(),
+                }
+            }
+            None => {
+                // Reached leaf node
+                let mut view = NodeView::new(&mut page.data);
+                view.replace(Slot(key.clone(), Either::Value(value.clone())))?;
+
+                return Ok(());
+            }
+        };
+
+        let cpage = self.pc.fetch_page_with(ptr, AccessType::Get)?.write_owned();
+        if let Some((s, os)) = self._insert(cpage, key, value)? {
+            let mut view = NodeView::new(&mut page.data);
+            view.replace(s)?;
+            view.replace(os)?;
+        }
+
+        Ok(())
+    }
+
     // TODO: return just the values instead? Less cloning
     pub fn scan(&self) -> crate::Result<Vec<(Tuple, V)>> {
         let mut ret = Vec::new();
@@ -202,69 +285,86 @@ where
             return Ok(ret);
         }
 
-        let pin = self.pc.fetch_page(self.root)?;
+        let pin = self.pc.fetch_page_with(self.root, AccessType::Get)?;
         let r = pin.read();
 
-        self._scan(None, r, &mut ret)?;
+        self._scan(None, r, &mut ret, false)?;
 
         Ok(ret)
     }
 
+    /// `sequential` is set once the scan follows the leaves' next pointers, from then on the leaf
+    /// after the one being read is read ahead.
     fn _scan<'a>(
         &'a self,
         mut prev_page: Option<PageReadGuard<'a>>,
         page: PageReadGuard<'a>,
         acc: &'a mut Vec<(Tuple, V)>,
+        sequential: bool,
     ) -> crate::Result<()> {
-        let node: Node<V> = Node::from(&page.data, &self.schema);
+        let node: Node<V> = Node::from(&page.data, page.id)?;
 
         // Find first leaf
         if node.t != NodeType::Leaf {
             let Slot(_, v) = node.first().unwrap();
             match v {
                 Either::Pointer(ptr) => {
-                    let pin = self.pc.fetch_page(*ptr)?;
+                    let pin = self.pc.fetch_page_with(*ptr, AccessType::Get)?;
                     let r = pin.read();
 
                     prev_page.take();
-                    return self._scan(Some(page), r, acc);
+                    return self._scan(Some(page), r, acc, false);
                 }
                 Either::Value(_) => unreachable!(),
             };
         }
 
-        acc.extend(node.iter().map(|Slot(k, v)| match v {
-            Either::Value(v) => (k.clone(), v.clone()),
-            Either::Pointer(_) => unreachable!(),
-        }));
+        if sequential && node.next != -1 {
+            // Read ahead is only a hint
+            let _ = self.pc.prefetch(&[node.next]);
+        }
+
+        for Slot(k, v) in node.iter() {
+            match v {
+                Either::Value(v) => acc.push((self.decode(k, page.id)?, v.clone())),
+                Either::Pointer(_) => unreachable!(),
+            }
+        }
 
         if node.next == -1 {
             return Ok(());
         }
 
-        let pin = self.pc.fetch_page(node.next)?;
+        // Leaves are scanned once, the inner nodes on the way down are accessed as usual
+        let pin = self.pc.fetch_page_with(node.next, AccessType::Scan)?;
         let r = pin.read();
 
         prev_page.take();
-        self._scan(Some(page), r, acc)
+        self._scan(Some(page), r, acc, true)
     }
 
+    /// Keys from `from` to `to` inclusive, in the order of the tree.
     pub fn range(&self, from: &Tuple, to: &Tuple) -> crate::Result<Vec<(Tuple, V)>> {
         let mut ret = Vec::new();
+        if self.root == -1 {
+            return Ok(ret);
+        }
+        let (from, to) = (&self.encode(from), &self.encode(to));
 
         let cur = match self.get_ptr(&from, self.root)? {
             Some(c) => c,
             None => return Ok(ret),
         };
 
-        let page = self.pc.fetch_page(cur)?;
+        let page = self.pc.fetch_page_with(cur, AccessType::Scan)?;
         let r = page.read();
 
-        self._range(None, r, &mut ret, from, to)?;
+        self._range(None, r, &mut ret, from, to, false)?;
 
         Ok(ret)
     }
 
+    /// See `_scan` for `sequential`.
     fn _range<'a>(
         &'a self,
         mut prev_page: Option<PageReadGuard<'a>>,
@@ -272,22 +372,27 @@ where
         acc: &'a mut Vec<(Tuple, V)>,
         from: &Tuple,
         to: &Tuple,
+        sequential: bool,
     ) -> crate::Result<()> {
-        let node = Node::from(&page.data, &self.schema);
+        let node = Node::from(&page.data, page.id)?;
         let next = node.next;
+        if sequential && next != -1 && node.last_key().is_some_and(|k| k.data <= to.data) {
+            // Read ahead is only a hint
+            let _ = self.pc.prefetch(&[next]);
+        }
+
         let len = acc.len();
-        acc.extend(
-            node.into_iter()
-                .skip_while(|Slot(k, _)| Comparand(&self.schema, k) < Comparand(&self.schema, from))
-                .take_while(|Slot(k, _)| Comparand(&self.schema, k) <= Comparand(&self.schema, to))
-                .map(|Slot(k, v)| {
-                    let v = match v {
-                        Either::Value(v) => v,
-                        _ => unreachable!(),
-                    };
-                    (k, v)
-                }),
-        );
+        let slots = node
+            .into_iter()
+            .skip_while(|Slot(k, _)| k.data < from.data)
+            .take_while(|Slot(k, _)| k.data <= to.data);
+        for Slot(k, v) in slots {
+            let v = match v {
+                Either::Value(v) => v,
+                _ => unreachable!(),
+            };
+            acc.push((self.decode(&k, page.id)?, v));
+        }
         if len == acc.len() {
             return Ok(());
         }
@@ -296,24 +401,22 @@ where
             return Ok(());
         }
 
-        let next_page = self.pc.fetch_page(next)?;
+        let next_page = self.pc.fetch_page_with(next, AccessType::Scan)?;
         let r = next_page.read();
 
         prev_page.take();
 
-        self._range(Some(page), r, acc, from, to)
+        self._range(Some(page), r, acc, from, to, true)
     }
 
     fn get_ptr(&self, key: &Tuple, ptr: PageId) -> crate::Result<Option<PageId>> {
-        assert!(ptr != -1);
-
-        let page = self.pc.fetch_page(ptr)?;
+        let page = self.pc.fetch_page_with(ptr, AccessType::Get)?;
         let r = page.read();
-        let node: Node<V> = Node::from(&r.data, &self.schema);
+        let node = Self::view(&page, &r.data)?;
 
-        match node.find_child(&key) {
+        match node.find_child(key) {
             Some(ptr) => self.get_ptr(key, ptr),
-            None if node.t == NodeType::Leaf => Ok(Some(ptr)),
+            None if node.t() == NodeType::Leaf => Ok(Some(ptr)),
             None => Ok(None),
         }
     }
@@ -324,17 +427,20 @@ where
             return Ok(None);
         }
 
-        self._get(key, self.root)
+        self._get(&self.encode(key), self.root)
     }
 
     fn _get(&self, key: &Tuple, ptr: PageId) -> crate::Result<Option<Slot<V>>> {
-        let page = self.pc.fetch_page(ptr)?;
+        let page = self.pc.fetch_page_with(ptr, AccessType::Get)?;
         let r = page.read();
-        let node = Node::from(&r.data, &self.schema);
+        let node = Self::view(&page, &r.data)?;
 
-        match node.find_child(&key) {
+        match node.find_child(key) {
             Some(ptr) => self._get(key, ptr),
-            None if node.t == NodeType::Leaf => Ok(node.get(&key).map(|s| s.clone())),
+            None if node.t() == NodeType::Leaf => match node.get(key) {
+                Some(Slot(k, v)) => Ok(Some(Slot(self.decode(&k, ptr)?, v))),
+                None => Ok(None),
+            },
             None => Ok(None),
         }
     }
@@ -344,22 +450,22 @@ where
             return Ok(false);
         }
 
-        self._delete(key, self.root)
+        self._delete(&self.encode(key), self.root)
     }
 
     fn _delete(&self, key: &Tuple, ptr: PageId) -> crate::Result<bool> {
-        let page = self.pc.fetch_page(ptr)?;
-        let mut w = page.write();
-        let mut node: Node<V> = Node::from(&w.data, &self.schema);
+        let mut w = self.pc.fetch_page_with(ptr, AccessType::Get)?.write_owned();
+        let node = Self::view(w.pin(), &w.data)?;
 
-        match node.find_child(&key) {
+        match node.find_child(key) {
             Some(ptr) => self._delete(key, ptr),
-            None if node.t == NodeType::Leaf => {
-                let rem = node.remove(&key);
-                if rem {
-                    writep!(w, &PageBuf::from(&node));
+            None if node.t() == NodeType::Leaf => {
+                // Only take the page mutably, marking it dirty, if there's something to remove
+                if node.search(&key.data).is_err() {
+                    return Ok(false);
                 }
-                Ok(rem)
+
+                Ok(NodeView::<_, V>::new(&mut w.data).remove(key))
             }
             None => Ok(false),
         }
@@ -379,7 +485,7 @@ where
     fn _print(&self, ptr: PageId) {
         let page = self.pc.fetch_page(ptr).unwrap();
         let r = page.read();
-        let node: Node<V> = Node::from(&r.data, &self.schema);
+        let node: Node<V> = Node::from(&r.data, r.id).unwrap();
 
         dbg!(&node);
 
@@ -397,7 +503,7 @@ where
 
         let page = self.pc.fetch_page(ptr)?;
         let r = page.read();
-        let node: Node<V> = Node::from(&r.data, &self.schema);
+        let node: Node<V> = Node::from(&r.data, r.id)?;
         if node.t == NodeType::Leaf {
             return Ok(ptr);
         }
@@ -422,7 +528,7 @@ where
         while cur != -1 {
             let pin = self.pc.fetch_page(cur)?;
             let page = pin.read();
-            let node: Node<V> = Node::from(&page.data, &self.schema);
+            let node: Node<V> = Node::from(&page.data, page.id)?;
 
             ret += 1;
             cur = node.next;
@@ -434,14 +540,16 @@ where
 
 #[cfg(test)]
 mod test {
+    use bytes::BytesMut;
     use rand::{seq::SliceRandom, thread_rng, Rng};
 
     use crate::{
         catalog::{Column, Type},
-        disk::Memory,
-        page::PAGE_SIZE,
-        page_cache::PageCache,
+        disk::{fault::Faulty, Memory},
+        error::Error,
+        page_cache::{PageCache, CACHE_SIZE},
         replacer::LRU,
+        table::tuple::{Comparand, TupleBuilder, Value},
     };
 
     use super::*;
@@ -464,10 +572,9 @@ mod test {
 
     #[test]
     fn test_btree_values() -> crate::Result<()> {
-        const MEMORY: usize = PAGE_SIZE * 128;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let lru = LRU::new(K);
         let pc = PageCache::new(disk, lru, 0);
 
@@ -493,6 +600,7 @@ mod test {
             let want = Some(Slot(k.clone(), Either::Value(*v)));
             assert!(want == have, "\nWant: {:?}\nHave: {:?}\n", want, have);
         }
+        assert!(pc.page_types() == [(PageType::BTreeLeaf, 1)].into());
 
         // Delete half and make sure they no longer exist in the tree
         let (first_half, second_half) = inserts.split_at(inserts.len() / 2);
@@ -544,10 +652,9 @@ mod test {
 
     #[test]
     fn test_btree_scan() -> crate::Result<()> {
-        const MEMORY: usize = PAGE_SIZE * 64;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let lru = LRU::new(K);
         let pc = PageCache::new(disk, lru, 0);
         let pc2 = pc.clone();
@@ -580,6 +687,97 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_btree_read_ahead() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        let schema = Schema::new(vec![Column {
+            name: "".into(),
+            ty: Type::Int,
+            offset: 0,
+        }]);
+        let mut btree = BTree::new(pc.clone(), &schema);
+
+        for (k, v) in inserts!(0..2000, i32) {
+            btree.insert(&k, &v)?;
+        }
+        let leaves = pc.page_types()[&PageType::BTreeLeaf] as u64;
+        assert!(leaves > 2);
+
+        // Evict the tree, leaving free frames to read ahead into
+        pc.flush_all_pages()?;
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+        pc.resize(CACHE_SIZE / 2)?;
+        pc.resize(CACHE_SIZE)?;
+
+        // Leaves after the second are read ahead through the next pointers
+        let prefetched = pc.stats().prefetched;
+        assert!(btree.scan()?.len() == 2000);
+        assert!(pc.stats().prefetched - prefetched == leaves - 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_key_order() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+
+        let schema: Schema = [("a", Type::Int), ("b", Type::Varchar)].into();
+        let order = [Order::Desc];
+        let mut btree = BTree::new(pc.clone(), &schema).with_order(&order);
+
+        let key = |a: i32, b: &str| Tuple {
+            data: TupleBuilder::new()
+                .add(&Value::Int(a))
+                .add(&Value::Varchar(b.into()))
+                .build(),
+            ..Default::default()
+        };
+
+        // Enough keys to split the root
+        let mut want = Vec::new();
+        for a in -100..100 {
+            for b in ["", "\0", "a\0", "a"] {
+                want.push((key(a, b), a * 10 + b.len() as i32));
+            }
+        }
+        want.shuffle(&mut thread_rng());
+        for (k, v) in &want {
+            btree.insert(k, v)?;
+        }
+        assert!(pc.page_types()[&PageType::BTreeInternal] >= 1);
+
+        // Descending by a, then ascending by b
+        let cmp = |(k, _): &(Tuple, i32), (k0, _): &(Tuple, i32)| {
+            let col = |k: &Tuple, i: usize| k.get_value(&schema.columns()[i]);
+            col(k0, 0).cmp(&col(k, 0)).then(col(k, 1).cmp(&col(k0, 1)))
+        };
+        want.sort_by(cmp);
+        assert!(btree.scan()? == want);
+
+        // Bounds are in the order of the tree
+        let have = btree.range(&key(2, "a"), &key(-2, "\0"))?;
+        let from = want.iter().position(|(k, _)| *k == key(2, "a")).unwrap();
+        let to = want.iter().position(|(k, _)| *k == key(-2, "\0")).unwrap();
+        assert!(have == want[from..=to]);
+
+        // Reopened with the same order
+        let btree: BTree<i32, _> =
+            BTree::new_with_root(pc.clone(), btree.root(), &schema).with_order(&order);
+        for (k, v) in &want {
+            assert!(btree.get(k)? == Some(Slot(k.clone(), Either::Value(*v))));
+        }
+        assert!(btree.delete(&key(0, "a\0"))?);
+        assert!(btree.get(&key(0, "a\0"))?.is_none());
+
+        Ok(())
+    }
+
     #[test]
     fn test_btree_range() -> crate::Result<()> {
         struct TestCase {
@@ -589,10 +787,9 @@ mod test {
             to: Tuple,
         }
 
-        const MEMORY: usize = PAGE_SIZE * 128;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let lru = LRU::new(K);
         let pc = PageCache::new(disk, lru, 0);
         let pc2 = pc.clone();
@@ -610,6 +807,12 @@ mod test {
                 from: (-100).into(),
                 to: (-50).into(),
             },
+            TestCase {
+                name: "empty tree",
+                range: 0..0,
+                from: (-100).into(),
+                to: 100.into(),
+            },
         ];
 
         let schema = Schema::new(vec![Column {
@@ -663,4 +866,167 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_btree_disk_errors() -> crate::Result<()> {
+        const K: usize = 2;
+
+        let disk = Faulty::new(Memory::new());
+        let lru = LRU::new(K);
+        let pc = PageCache::new(disk, lru, 0);
+
+        let schema = Schema::new(vec![Column {
+            name: "".into(),
+            ty: Type::Int,
+            offset: 0,
+        }]);
+        let mut btree = BTree::new(pc.clone(), &schema);
+
+        let inserts = inserts!(-50..50, i32);
+        for (k, v) in &inserts {
+            btree.insert(k, v)?;
+        }
+
+        // Evict the tree
+        pc.flush_all_pages()?;
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+
+        // Reading the root back in fails
+        pc.disk().fail_read(0);
+        let have = btree.insert(&100.into(), &110);
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        pc.disk().fail_read(0);
+        let have = btree.get(&inserts[0].0);
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        pc.disk().fail_read(0);
+        let have = btree.scan();
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        // The tree is still intact
+        for (k, v) in &inserts {
+            let have = btree.get(k)?;
+            let want = Some(Slot(k.clone(), Either::Value(*v)));
+            assert!(want == have, "\nWant: {:?}\nHave: {:?}\n", want, have);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_corrupted() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        let schema = Schema::new(vec![Column {
+            name: "".into(),
+            ty: Type::Int,
+            offset: 0,
+        }]);
+
+        // A root that was never written is all zeros
+        let page_id = pc.new_page()?.id;
+        let mut btree = BTree::<i32, _>::new_with_root(pc.clone(), page_id, &schema);
+        assert!(btree.get(&1.into()) == Err(Error::Corrupted { page_id }));
+        assert!(btree.scan() == Err(Error::Corrupted { page_id }));
+        assert!(btree.insert(&1.into(), &1) == Err(Error::Corrupted { page_id }));
+
+        // A node type that doesn't exist
+        let mut btree = BTree::new(pc.clone(), &schema);
+        for (k, v) in inserts!(-50..50, i32) {
+            btree.insert(&k, &v)?;
+        }
+        let page_id = btree.root();
+        pc.fetch_page(page_id)?.write().data[page::PAGE_HEADER_SIZE] = 7;
+        assert!(btree.get(&1.into()) == Err(Error::Corrupted { page_id }));
+        assert!(btree.range(&0.into(), &10.into()) == Err(Error::Corrupted { page_id }));
+        assert!(btree.delete(&1.into()) == Err(Error::Corrupted { page_id }));
+
+        // A pointer in a leaf, the first cell is at the end of the page. Resident pages are only
+        // checked once, so it's found when the page is read from disk again.
+        let mut btree = BTree::new(pc.clone(), &schema);
+        btree.insert(&1.into(), &1)?;
+        let page_id = btree.root();
+        let flag = page::PAGE_SIZE - Either::<i32>::SIZE;
+        pc.fetch_page(page_id)?.write().data[flag] = slot::FLAG_POINTER;
+        assert!(btree.get(&2.into()) == Ok(None));
+        pc.resize(0)?;
+        pc.resize(CACHE_SIZE)?;
+        assert!(btree.get(&2.into()) == Err(Error::Corrupted { page_id }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_key_too_large() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        let schema = Schema::new(vec![Column {
+            name: "".into(),
+            ty: Type::Int,
+            offset: 0,
+        }]);
+        let mut btree = BTree::new(pc.clone(), &schema);
+
+        let size = Node::<i32>::max_key_size() + 1;
+        let key = Tuple {
+            data: BytesMut::zeroed(size),
+            ..Default::default()
+        };
+        let have = btree.insert(&key, &1);
+        assert!(have == Err(Error::KeyTooLarge { size }), "Have: {have:?}");
+
+        // Nothing was allocated for the rejected key
+        assert!(btree.root() == -1);
+        btree.insert(&1.into(), &1)?;
+        assert!(btree.get(&1.into())?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_btree_large_keys() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        let schema: Schema = [("a", Type::Varchar)].into();
+        let mut btree = BTree::new(pc.clone(), &schema);
+
+        let key = |s: String| Tuple {
+            data: TupleBuilder::new().add(&Value::Varchar(s)).build(),
+            ..Default::default()
+        };
+
+        // Only a handful fit in a node, so the tree splits at every level
+        let len = Node::<i32>::max_key_size() - 4;
+        let mut want: Vec<_> = (0..300)
+            .map(|i| (key(format!("{i:03}").repeat(len / 3 + 1)[..len].into()), i))
+            .collect();
+        want.shuffle(&mut thread_rng());
+        for (k, v) in &want {
+            btree.insert(k, v)?;
+        }
+        assert!(pc.page_types()[&PageType::BTreeInternal] > 1);
+
+        for (k, v) in &want {
+            assert!(btree.get(k)? == Some(Slot(k.clone(), Either::Value(*v))));
+        }
+        want.sort_by_key(|(_, v)| *v);
+        assert!(btree.scan()? == want);
+
+        // Escaping counts towards the size
+        let zeros = Node::<i32>::max_key_size() / 2;
+        let have = btree.insert(&key("\0".repeat(zeros)), &0);
+        assert!(
+            have == Err(Error::KeyTooLarge {
+                size: 2 * zeros + 2
+            }),
+            "Have: {have:?}"
+        );
+
+        Ok(())
+    }
 }
@@ -1,14 +1,21 @@
-use std::ops::Range;
+use std::{
+    cmp::Ordering,
+    marker::PhantomData,
+    ops::{Deref, DerefMut, Range},
+};
 
 use bytes::BytesMut;
 
 use crate::{
-    btree::slot::Either,
-    catalog::Schema,
+    btree::{
+        key,
+        slot::{self, Either},
+    },
+    error::Error,
     get_ptr,
-    page::{PageBuf, PageId, PAGE_SIZE},
+    page::{Header, PageBuf, PageId, PageType, PAGE_HEADER_SIZE, PAGE_SIZE},
     storable::Storable,
-    table::tuple::{Comparand, Tuple},
+    table::tuple::Tuple,
 };
 
 use super::slot::Slot;
@@ -19,12 +26,14 @@ pub enum NodeType {
     Leaf,
 }
 
-impl From<u8> for NodeType {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for NodeType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, u8> {
         match value {
-            1 => NodeType::Internal,
-            2 => NodeType::Leaf,
-            _ => unreachable!("unexpected NodeType: {value}"),
+            1 => Ok(NodeType::Internal),
+            2 => Ok(NodeType::Leaf),
+            _ => Err(value),
         }
     }
 }
@@ -38,25 +47,384 @@ impl From<NodeType> for u8 {
     }
 }
 
-const NODE_TYPE: usize = 0;
-const NODE_IS_ROOT: usize = 1;
-const NODE_LEN: Range<usize> = 2..6;
-const NODE_NEXT: Range<usize> = 6..10;
-const NODE_ID: Range<usize> = 10..14;
-const NODE_VALUES_START: usize = 14;
+impl From<NodeType> for PageType {
+    fn from(value: NodeType) -> Self {
+        match value {
+            NodeType::Internal => PageType::BTreeInternal,
+            NodeType::Leaf => PageType::BTreeLeaf,
+        }
+    }
+}
+
+const NODE_TYPE: usize = PAGE_HEADER_SIZE;
+const NODE_IS_ROOT: usize = NODE_TYPE + 1;
+const NODE_LEN: Range<usize> = NODE_IS_ROOT + 1..NODE_IS_ROOT + 3;
+const NODE_CELLS_START: Range<usize> = NODE_LEN.end..NODE_LEN.end + 2;
+const NODE_NEXT: Range<usize> = NODE_CELLS_START.end..NODE_CELLS_START.end + 4;
+const NODE_ID: Range<usize> = NODE_NEXT.end..NODE_NEXT.end + 4;
+const NODE_SLOTS_START: usize = NODE_ID.end;
+
+const SLOT_SIZE: usize = 2;
+const KEY_LEN_SIZE: usize = 2;
+
+/// Room for slots and cells.
+const NODE_CAPACITY: usize = PAGE_SIZE - NODE_SLOTS_START;
+
+/*
+    Node:
+    Header | NodeType (1) | Root (1) | Len (2) | CellsStart (2) | Next (4) | PageId (4) | Slots | Free | Cells
+
+    Slot:
+    Offset of the cell (2), kept in key order so they can be binary searched. Keys are encoded by
+    `btree::key`, so they're ordered by their bytes
+
+    Cell:
+    KeyLen (2) | Key | Either
+
+    Cells are written from the end of the page. Removing a slot leaves its cell in place, the space
+    is reclaimed by compacting the cells once a new one doesn't fit.
+*/
+
+/// Bytes a slot and its cell take up.
+fn slot_size<V>(key_size: usize) -> usize {
+    SLOT_SIZE + KEY_LEN_SIZE + key_size + Either::<V>::SIZE
+}
+
+/*
+    Splitting by bytes leaves each half with at most half the capacity plus one slot. An insert then
+    adds at most three slots to a node: a bumped separator and the two separators of a split child,
+    which are up to one byte longer than a key. Keeping slots to an eighth of the capacity means
+    those always fit.
+*/
+const MAX_SLOT_SIZE: usize = NODE_CAPACITY / 8;
+
+/// Zero-copy view of a node, over either `&PageBuf` or `&mut PageBuf`. Lookups and changes that
+/// don't split the node work on the page directly, see `Node` for splits.
+pub struct NodeView<B, V> {
+    buf: B,
+    _data: PhantomData<V>,
+}
+
+impl<B, V> NodeView<B, V>
+where
+    B: Deref<Target = PageBuf>,
+    V: Storable,
+{
+    pub fn new(buf: B) -> Self {
+        Self {
+            buf,
+            _data: PhantomData,
+        }
+    }
+
+    /// Views a node read from the page `page_id`, checking its header and that the slots fit. The
+    /// cells are only checked by `check`, which is needed once after the page is read from disk.
+    pub fn load(buf: B, page_id: PageId) -> crate::Result<Self> {
+        let ret = Self::new(buf);
+        let Ok(t) = NodeType::try_from(ret.buf[NODE_TYPE]) else {
+            return Err(Error::Corrupted { page_id });
+        };
+        let cells_start = ret.u16_at(NODE_CELLS_START.start);
+        if NODE_SLOTS_START + ret.len() * SLOT_SIZE > cells_start || cells_start > PAGE_SIZE {
+            return Err(Error::Corrupted { page_id });
+        }
+        // Internal nodes always keep at least one pointer
+        if t == NodeType::Internal && ret.is_empty() {
+            return Err(Error::Corrupted { page_id });
+        }
+
+        Ok(ret)
+    }
+
+    /// Checks that every cell stays within the page and holds what the node type says it does.
+    pub fn check(&self, page_id: PageId) -> crate::Result<()> {
+        let cells_start = self.u16_at(NODE_CELLS_START.start);
+        let flag = match self.t() {
+            NodeType::Internal => slot::FLAG_POINTER,
+            NodeType::Leaf => slot::FLAG_VALUE,
+        };
+        let mut used = 0;
+        for i in 0..self.len() {
+            let cell = self.cell(i);
+            if cell < cells_start || cell + KEY_LEN_SIZE > PAGE_SIZE {
+                return Err(Error::Corrupted { page_id });
+            }
+
+            let size = self.cell_size(i);
+            if cell + size > PAGE_SIZE || self.buf[cell + size - Either::<V>::SIZE] != flag {
+                return Err(Error::Corrupted { page_id });
+            }
+            used += SLOT_SIZE + size;
+        }
+
+        match used <= NODE_CAPACITY {
+            true => Ok(()),
+            false => Err(Error::Corrupted { page_id }),
+        }
+    }
+
+    fn u16_at(&self, i: usize) -> usize {
+        u16::from_be_bytes([self.buf[i], self.buf[i + 1]]) as usize
+    }
+
+    pub fn t(&self) -> NodeType {
+        NodeType::try_from(self.buf[NODE_TYPE]).expect("node type is checked by load")
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.buf[NODE_IS_ROOT] > 0
+    }
+
+    pub fn next(&self) -> PageId {
+        PageId::from_be_bytes(self.buf[NODE_NEXT].try_into().unwrap())
+    }
+
+    pub fn id(&self) -> PageId {
+        PageId::from_be_bytes(self.buf[NODE_ID].try_into().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.u16_at(NODE_LEN.start)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn cell(&self, i: usize) -> usize {
+        self.u16_at(NODE_SLOTS_START + i * SLOT_SIZE)
+    }
+
+    fn cell_size(&self, i: usize) -> usize {
+        KEY_LEN_SIZE + self.u16_at(self.cell(i)) + Either::<V>::SIZE
+    }
+
+    /// Room between the slots and the cells.
+    fn free(&self) -> usize {
+        self.u16_at(NODE_CELLS_START.start) - (NODE_SLOTS_START + self.len() * SLOT_SIZE)
+    }
+
+    /// Whether `size` bytes fit, compacting the cells if needed.
+    fn has_room(&self, size: usize) -> bool {
+        if self.free() >= size {
+            return true;
+        }
+
+        let used: usize = (0..self.len()).map(|i| SLOT_SIZE + self.cell_size(i)).sum();
+        NODE_CAPACITY - used >= size
+    }
+
+    pub fn key(&self, i: usize) -> &[u8] {
+        let cell = self.cell(i);
+        let len = self.u16_at(cell);
+
+        &self.buf[cell + KEY_LEN_SIZE..cell + KEY_LEN_SIZE + len]
+    }
+
+    pub fn value(&self, i: usize) -> Either<V> {
+        let cell = self.cell(i);
+        let start = cell + KEY_LEN_SIZE + self.u16_at(cell);
+
+        Either::from(&self.buf[start..start + Either::<V>::SIZE])
+    }
+
+    /// Copies out the slot at `i`.
+    pub fn slot(&self, i: usize) -> Slot<V> {
+        let key = Tuple {
+            data: BytesMut::from(self.key(i)),
+            ..Default::default()
+        };
+
+        Slot(key, self.value(i))
+    }
+
+    /// Binary searches the slots for `key`. `Err` holds the index it would be inserted at.
+    pub fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.key(mid).cmp(key) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+
+        Err(lo)
+    }
+
+    /// Returns `None` if node is a leaf or if no keys were matched and the next key is invalid
+    pub fn find_child(&self, key: &Tuple) -> Option<PageId> {
+        if self.t() == NodeType::Leaf {
+            return None;
+        }
+
+        // First slot with a key greater than `key`
+        let i = match self.search(&key.data) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        if i < self.len() {
+            return match self.value(i) {
+                Either::Pointer(ptr) => Some(ptr),
+                Either::Value(_) => unreachable!(),
+            };
+        }
+
+        match self.next() {
+            -1 => None,
+            ptr => Some(ptr),
+        }
+    }
+
+    pub fn get(&self, key: &Tuple) -> Option<Slot<V>> {
+        self.search(&key.data).ok().map(|i| self.slot(i))
+    }
+
+    /// Whether inserting `key` could overflow the node, so it has to be split first.
+    pub fn almost_full(&self, key: &Tuple) -> bool {
+        let size = match self.t() {
+            NodeType::Leaf => slot_size::<V>(key.size()),
+            // The separators that can be added on the way back up
+            NodeType::Internal => 3 * MAX_SLOT_SIZE,
+        };
+
+        !self.has_room(size)
+    }
+}
+
+impl<B, V> NodeView<B, V>
+where
+    B: DerefMut<Target = PageBuf>,
+    V: Storable,
+{
+    fn set_u16(&mut self, i: usize, value: usize) {
+        self.buf[i..i + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    /// Formats the page as an empty node.
+    pub fn init(&mut self, id: PageId, t: NodeType, is_root: bool, next: PageId) {
+        Header::from(&mut *self.buf).init(t.into());
+        self.buf[NODE_TYPE] = u8::from(t);
+        self.buf[NODE_IS_ROOT] = is_root as u8;
+        self.set_u16(NODE_LEN.start, 0);
+        self.set_u16(NODE_CELLS_START.start, PAGE_SIZE);
+        self.buf[NODE_NEXT].copy_from_slice(&next.to_be_bytes());
+        self.buf[NODE_ID].copy_from_slice(&id.to_be_bytes());
+    }
+
+    fn insert_at(&mut self, i: usize, Slot(k, v): &Slot<V>) -> crate::Result<()> {
+        let size = KEY_LEN_SIZE + k.size() + Either::<V>::SIZE;
+        if !self.has_room(SLOT_SIZE + size) {
+            return Err(Error::KeyTooLarge { size: k.size() });
+        }
+        if self.free() < SLOT_SIZE + size {
+            self.compact();
+        }
+
+        let cell = self.u16_at(NODE_CELLS_START.start) - size;
+        self.set_u16(cell, k.size());
+        self.buf[cell + KEY_LEN_SIZE..cell + KEY_LEN_SIZE + k.size()].copy_from_slice(&k.data);
+        v.write_to(&mut self.buf[..], cell + KEY_LEN_SIZE + k.size());
+        self.set_u16(NODE_CELLS_START.start, cell);
+
+        let len = self.len();
+        let slot = NODE_SLOTS_START + i * SLOT_SIZE;
+        self.buf
+            .copy_within(slot..NODE_SLOTS_START + len * SLOT_SIZE, slot + SLOT_SIZE);
+        self.set_u16(slot, cell);
+        self.set_u16(NODE_LEN.start, len + 1);
+
+        Ok(())
+    }
+
+    fn remove_at(&mut self, i: usize) -> Slot<V> {
+        let ret = self.slot(i);
+
+        let len = self.len();
+        let slot = NODE_SLOTS_START + i * SLOT_SIZE;
+        self.buf
+            .copy_within(slot + SLOT_SIZE..NODE_SLOTS_START + len * SLOT_SIZE, slot);
+        self.set_u16(NODE_LEN.start, len - 1);
+
+        ret
+    }
+
+    /// Rewrites the cells back to back at the end of the page, dropping the removed ones.
+    fn compact(&mut self) {
+        let cells: Vec<Vec<u8>> = (0..self.len())
+            .map(|i| self.buf[self.cell(i)..self.cell(i) + self.cell_size(i)].to_vec())
+            .collect();
+
+        let mut end = PAGE_SIZE;
+        for (i, cell) in cells.iter().enumerate() {
+            end -= cell.len();
+            self.buf[end..end + cell.len()].copy_from_slice(cell);
+            self.set_u16(NODE_SLOTS_START + i * SLOT_SIZE, end);
+        }
+        self.set_u16(NODE_CELLS_START.start, end);
+    }
+
+    /// Appends a slot without checking the order of the keys.
+    pub fn push(&mut self, slot: &Slot<V>) -> crate::Result<()> {
+        self.insert_at(self.len(), slot)
+    }
+
+    /// Returns `false` if the key is already in the node.
+    pub fn insert(&mut self, slot: Slot<V>) -> crate::Result<bool> {
+        match self.search(&slot.0.data) {
+            Ok(_) => Ok(false),
+            Err(i) => {
+                self.insert_at(i, &slot)?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn replace(&mut self, slot: Slot<V>) -> crate::Result<Option<Slot<V>>> {
+        match self.search(&slot.0.data) {
+            Ok(i) => {
+                // Same key, so the new slot takes the room of the old one
+                let ret = self.remove_at(i);
+                self.insert_at(i, &slot)?;
+                Ok(Some(ret))
+            }
+            Err(i) => {
+                self.insert_at(i, &slot)?;
+                Ok(None)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Tuple) -> bool {
+        match self.search(&key.data) {
+            Ok(i) => {
+                self.remove_at(i);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn pop_last(&mut self) -> Option<Slot<V>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(self.remove_at(self.len() - 1))
+    }
+}
 
-// | NodeType (1) | Root (1) | Len (4) | Max (4) | Next (4) | PageId (4) | Values
 #[derive(Clone, Debug)]
-pub struct Node<'s, V> {
+pub struct Node<V> {
     pub t: NodeType,
     pub is_root: bool,
     pub next: PageId,
     pub id: PageId,
     values: Vec<Slot<V>>,
-    schema: &'s Schema,
 }
 
-impl<'s, V> PartialEq for Node<'s, V>
+impl<V> PartialEq for Node<V>
 where
     V: PartialEq,
 {
@@ -90,7 +458,7 @@ where
         for (i, Slot(k, v)) in self.values.iter().enumerate() {
             let Slot(k0, v0) = &other.values[i];
 
-            if Comparand(&self.schema, k) != Comparand(&self.schema, k0) {
+            if k.data != k0.data {
                 return false;
             }
 
@@ -103,92 +471,76 @@ where
     }
 }
 
-impl<'s, V> From<&Node<'s, V>> for PageBuf
+impl<V> TryFrom<&Node<V>> for PageBuf
 where
     V: Storable,
 {
-    fn from(node: &Node<V>) -> Self {
-        let mut ret: PageBuf = [0; PAGE_SIZE];
-
-        ret[NODE_TYPE] = u8::from(node.t);
-        ret[NODE_IS_ROOT] = node.is_root as u8;
-        ret[NODE_LEN].copy_from_slice(&(node.values.len() as u32).to_be_bytes());
-        ret[NODE_NEXT].copy_from_slice(&node.next.to_be_bytes());
-        ret[NODE_ID].copy_from_slice(&node.id.to_be_bytes());
+    type Error = Error;
 
-        let mut from = NODE_VALUES_START;
-        for value in &node.values {
-            let size = Either::<V>::SIZE + value.0.size();
-            let slot = BytesMut::from(value);
-            ret[from..from + size].copy_from_slice(&slot);
-            from += size;
-        }
+    fn try_from(node: &Node<V>) -> crate::Result<Self> {
+        let mut ret: PageBuf = [0; PAGE_SIZE];
 
-        if ret == [0; 4096] {
-            panic!("PageBuf::from(Node) produced an empty buffer");
+        let mut view = NodeView::new(&mut ret);
+        view.init(node.id, node.t, node.is_root, node.next);
+        for slot in &node.values {
+            view.push(slot)?;
         }
 
-        ret
+        Ok(ret)
     }
 }
 
-impl<'s, V> From<Node<'s, V>> for PageBuf
-where
-    V: Copy + Storable,
-{
-    fn from(node: Node<V>) -> Self {
-        PageBuf::from(&node)
-    }
-}
-
-impl<'s, V> Node<'s, V>
+impl<V> Node<V>
 where
     V: Storable,
 {
-    pub fn new(id: PageId, t: NodeType, is_root: bool, schema: &'s Schema) -> Self {
+    pub fn new(id: PageId, t: NodeType, is_root: bool) -> Self {
         Self {
             t,
             is_root,
             next: -1,
             id,
             values: Vec::new(),
-            schema,
         }
     }
 
-    pub fn from(buf: &PageBuf, schema: &'s Schema) -> Self {
-        let t = NodeType::from(buf[NODE_TYPE]);
-        let is_root = buf[NODE_IS_ROOT] > 0;
-        let len = u32::from_be_bytes(buf[NODE_LEN].try_into().unwrap());
-        let next = PageId::from_be_bytes(buf[NODE_NEXT].try_into().unwrap());
-        let id = PageId::from_be_bytes(buf[NODE_ID].try_into().unwrap());
+    /// Largest encoded key, see `MAX_SLOT_SIZE`.
+    pub fn max_key_size() -> usize {
+        // Separators can be a byte longer than the key
+        MAX_SLOT_SIZE - slot_size::<V>(0) - 1
+    }
 
-        let mut values = Vec::new();
-        let mut left = &buf[NODE_VALUES_START..];
-        let mut rem = len;
-        while rem > 0 {
-            let tuple = Tuple::from(left, schema);
-            let slot_size = tuple.size() + Either::<V>::SIZE;
-            let either = Either::from(&left[tuple.size()..slot_size]);
-            values.push(Slot(tuple, either));
-            left = &left[slot_size..];
-            rem -= 1;
-        }
+    /// Copies the node out of the page `page_id`, for changes that split it.
+    pub fn from(buf: &PageBuf, page_id: PageId) -> crate::Result<Self> {
+        let view = NodeView::load(buf, page_id)?;
+        view.check(page_id)?;
+
+        Ok(Self {
+            t: view.t(),
+            is_root: view.is_root(),
+            next: view.next(),
+            id: view.id(),
+            values: (0..view.len()).map(|i| view.slot(i)).collect(),
+        })
+    }
 
-        Self {
-            t,
-            is_root,
-            next,
-            id,
-            values,
-            schema,
+    /// Split out half of self's values, by size, into a new node.
+    pub fn split(&mut self, id: PageId) -> Node<V> {
+        let total: usize = self.values.iter().map(|s| slot_size::<V>(s.0.size())).sum();
+
+        // Most values that fit in half of the bytes, but at least one
+        let mut at = 0;
+        let mut size = 0;
+        for Slot(k, _) in &self.values {
+            size += slot_size::<V>(k.size());
+            if size > total / 2 {
+                break;
+            }
+            at += 1;
         }
-    }
 
-    /// Split out half of self's values into a new node.
-    pub fn split(&mut self, id: PageId) -> Node<'s, V> {
         // All values in the greater half end up in `rest`
-        let rest = self.values.split_off(self.values.len() / 2);
+        let rest = self.values.split_off(at.clamp(1, self.values.len() - 1));
         self.is_root = false;
 
         let mut new = Node {
@@ -197,7 +549,6 @@ where
             next: -1,
             id,
             values: rest,
-            schema: self.schema,
         };
 
         if self.t == NodeType::Leaf {
@@ -216,27 +567,33 @@ where
     /// Using last values for separators
     fn get_separator(self) -> Slot<V> {
         let Slot(k, _) = self.values.last().expect("there should be a last slot");
-        let k = if self.t == NodeType::Leaf { k.next(self.schema) } else { k.clone() };
+        let k = if self.t == NodeType::Leaf { key::successor(k) } else { k.clone() };
         Slot(k, Either::Pointer(self.id))
     }
 
+    /// Binary searches the values for `key`. `Err` holds the index it would be inserted at.
+    fn search(&self, key: &Tuple) -> Result<usize, usize> {
+        self.values
+            .binary_search_by(|Slot(k, _)| k.data[..].cmp(&key.data[..]))
+    }
+
     /// Returns `None` if node is a leaf or if no keys were matched and the next key is invalid
     pub fn find_child(&self, key: &Tuple) -> Option<PageId> {
         if self.t == NodeType::Leaf {
             return None;
         }
 
-        match self
-            .values
-            .iter()
-            .find(|&s| Comparand(&self.schema, key) < Comparand(&self.schema, &s.0))
-            .map(|s| get_ptr!(s))
-        {
+        // First slot with a key greater than `key`
+        let i = match self.search(key) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        };
+        match self.values.get(i) {
+            Some(s) => Some(get_ptr!(s)),
             None => match self.next {
                 -1 => None,
                 ptr => Some(ptr),
             },
-            ptr => ptr,
         }
     }
 
@@ -253,49 +610,29 @@ where
         self.values.last().map(|s| &s.0)
     }
 
-    #[inline]
-    pub fn almost_full(&self) -> bool {
-        // TODO: Needs to take into account varchar
-        // VALUES_START + self.size >= PAGE_SIZE / 2
-        self.values.len() * self.schema.size() >= PAGE_SIZE / 2
-    }
-
     pub fn insert(&mut self, slot: Slot<V>) -> bool {
-        let mut i = self.values.len();
-        for (j, Slot(k, _)) in self.values.iter().enumerate() {
-            if Comparand(&self.schema, k) == Comparand(&self.schema, &slot.0) {
-                // Duplicate key
-                return false;
-            }
-
-            if Comparand(&self.schema, k) > Comparand(&self.schema, &slot.0) {
-                i = j;
-                break;
+        match self.search(&slot.0) {
+            // Duplicate key
+            Ok(_) => false,
+            Err(i) => {
+                self.values.insert(i, slot);
+                true
             }
         }
-
-        self.values.insert(i, slot);
-        true
     }
 
-    // TODO: unit tests
     // TODO: Accept ref to key and value separately - less cloning
     pub fn replace(&mut self, mut slot: Slot<V>) -> Option<Slot<V>> {
-        let mut i = self.values.len();
-        for (j, Slot(k, _)) in self.values.iter().enumerate() {
-            if Comparand(&self.schema, k) == Comparand(&self.schema, &slot.0) {
-                std::mem::swap(&mut self.values[j], &mut slot);
-                return Some(slot);
+        match self.search(&slot.0) {
+            Ok(i) => {
+                std::mem::swap(&mut self.values[i], &mut slot);
+                Some(slot)
             }
-
-            if Comparand(&self.schema, k) > Comparand(&self.schema, &slot.0) {
-                i = j;
-                break;
+            Err(i) => {
+                self.values.insert(i, slot);
+                None
             }
         }
-
-        self.values.insert(i, slot);
-        None
     }
 
     pub fn pop_last(&mut self) -> Option<Slot<V>> {
@@ -315,23 +652,16 @@ where
     }
 
     pub fn get(&self, key: &Tuple) -> Option<&Slot<V>> {
-        self.values
-            .iter()
-            .find(|Slot(k, _)| Comparand(self.schema, k) == Comparand(self.schema, &key))
+        self.search(key).ok().map(|i| &self.values[i])
     }
 
     pub fn remove(&mut self, key: &Tuple) -> bool {
-        if let Some(i) = self
-            .values
-            .iter()
-            .enumerate()
-            .find(|(_, Slot(k, _))| Comparand(self.schema, k) == Comparand(self.schema, &key))
-            .map(|(i, _)| i)
-        {
-            self.values.remove(i);
-            true
-        } else {
-            false
+        match self.search(key) {
+            Ok(i) => {
+                self.values.remove(i);
+                true
+            }
+            Err(_) => false,
         }
     }
 }
@@ -340,19 +670,13 @@ where
 mod test {
     use crate::{
         btree::slot::Either,
-        catalog::{Column, Type},
+        catalog::{Column, Schema, Type},
     };
 
     use super::*;
 
     #[test]
-    fn test_from() {
-        let schema = Schema::new(vec![Column {
-            name: "".into(),
-            ty: Type::Int,
-            offset: 0,
-        }]);
-
+    fn test_from() -> crate::Result<()> {
         let node = Node {
             t: NodeType::Leaf,
             is_root: true,
@@ -360,34 +684,29 @@ mod test {
             id: 0,
             values: vec![
                 Slot(10.into(), Either::Value(20)),
-                Slot(0.into(), Either::Pointer(1)),
+                Slot(0.into(), Either::Value(1)),
                 Slot(20.into(), Either::Value(30)),
-                Slot(1.into(), Either::Pointer(2)),
+                Slot(1.into(), Either::Value(2)),
                 Slot(30.into(), Either::Value(40)),
-                Slot(2.into(), Either::Pointer(3)),
+                Slot(2.into(), Either::Value(3)),
                 Slot(40.into(), Either::Value(50)),
-                Slot(3.into(), Either::Pointer(4)),
+                Slot(3.into(), Either::Value(4)),
                 Slot(50.into(), Either::Value(60)),
-                Slot(4.into(), Either::Pointer(5)),
+                Slot(4.into(), Either::Value(5)),
             ],
-            schema: &schema,
         };
 
-        let bytes = PageBuf::from(&node);
+        let bytes = PageBuf::try_from(&node)?;
 
-        let node2: Node<i32> = Node::from(&bytes, &schema);
+        let node2: Node<i32> = Node::from(&bytes, node.id)?;
 
         assert_eq!(node, node2);
+
+        Ok(())
     }
 
     #[test]
     fn test_split() {
-        let schema = Schema::new(vec![Column {
-            name: "".into(),
-            ty: Type::Int,
-            offset: 0,
-        }]);
-
         let mut node = Node {
             t: NodeType::Leaf,
             is_root: true,
@@ -406,7 +725,6 @@ mod test {
                 Slot(100.into(), Either::Value(10)),
                 Slot(110.into(), Either::Value(11)),
             ],
-            schema: &schema,
         };
 
         let new = node.split(1);
@@ -423,7 +741,6 @@ mod test {
                 Slot(40.into(), Either::Value(4)),
                 Slot(50.into(), Either::Value(5)),
             ],
-            schema: &schema,
         };
 
         assert!(node == expected, "\nExpected: {:?}\n    Node: {:?}\n", expected, node);
@@ -441,7 +758,6 @@ mod test {
                 Slot(100.into(), Either::Value(10)),
                 Slot(110.into(), Either::Value(11)),
             ],
-            schema: &schema,
         };
 
         assert!(new == expected_new, "\nExpected: {:?}\n    Node: {:?}\n", expected_new, new);
@@ -449,12 +765,6 @@ mod test {
 
     #[test]
     fn test_get_separators_leaf() {
-        let schema = Schema::new(vec![Column {
-            name: "".into(),
-            ty: Type::Int,
-            offset: 0,
-        }]);
-
         let node = Node {
             t: NodeType::Leaf,
             is_root: false,
@@ -467,7 +777,6 @@ mod test {
                 Slot(40.into(), Either::Value(4)),
                 Slot(50.into(), Either::Value(5)),
             ],
-            schema: &schema,
         };
 
         let other = Node {
@@ -483,24 +792,20 @@ mod test {
                 Slot(100.into(), Either::Value(10)),
                 Slot(110.into(), Either::Value(11)),
             ],
-            schema: &schema,
         };
 
         let Some(slots) = node.get_separators(Some(other)) else {
             panic!("expected separators")
         };
-        let expected = (Slot(51.into(), Either::Pointer(0)), Slot(111.into(), Either::Pointer(1)));
+        let expected = (
+            Slot(key::successor(&50.into()), Either::Pointer(0)),
+            Slot(key::successor(&110.into()), Either::Pointer(1)),
+        );
         assert!(slots == expected);
     }
 
     #[test]
     fn test_get_separators_internal() {
-        let schema = Schema::new(vec![Column {
-            name: "".into(),
-            ty: Type::Int,
-            offset: 0,
-        }]);
-
         let node: Node<i32> = Node {
             t: NodeType::Internal,
             is_root: false,
@@ -513,7 +818,6 @@ mod test {
                 Slot(40.into(), Either::Pointer(4)),
                 Slot(50.into(), Either::Pointer(5)),
             ],
-            schema: &schema,
         };
 
         let other = Node {
@@ -529,7 +833,6 @@ mod test {
                 Slot(100.into(), Either::Pointer(10)),
                 Slot(110.into(), Either::Pointer(11)),
             ],
-            schema: &schema,
         };
 
         let Some(slots) = node.get_separators(Some(other)) else {
@@ -541,12 +844,6 @@ mod test {
 
     #[test]
     fn test_find_child() {
-        let schema = Schema::new(vec![Column {
-            name: "".into(),
-            ty: Type::Int,
-            offset: 0,
-        }]);
-
         let node: Node<i32> = Node {
             t: NodeType::Internal,
             is_root: false,
@@ -559,7 +856,6 @@ mod test {
                 Slot(40.into(), Either::Pointer(4)),
                 Slot(50.into(), Either::Pointer(5)),
             ],
-            schema: &schema,
         };
 
         let a = node.find_child(&25.into());
@@ -589,19 +885,12 @@ mod test {
 
     #[test]
     fn test_values() {
-        let schema = Schema::new(vec![Column {
-            name: "".into(),
-            ty: Type::Int,
-            offset: 0,
-        }]);
-
         let mut node: Node<i32> = Node {
             t: NodeType::Internal,
             is_root: false,
             next: 1,
             id: 0,
             values: vec![],
-            schema: &schema,
         };
 
         // Insert
@@ -612,7 +901,8 @@ mod test {
             node.insert(slot.clone());
         }
 
-        want.sort_by(|Slot(k, _), Slot(k0, _)| Comparand(&schema, k).cmp(&Comparand(&schema, k0)));
+        // Nodes order keys by their bytes
+        want.sort_by(|Slot(k, _), Slot(k0, _)| k.data.cmp(&k0.data));
 
         assert_eq!(want, node.values);
 
@@ -649,4 +939,138 @@ mod test {
 
         // Replace
     }
+
+    #[test]
+    fn test_view() -> crate::Result<()> {
+        let schema = Schema::new(vec![Column {
+            name: "".into(),
+            ty: Type::Int,
+            offset: 0,
+        }]);
+
+        let mut buf: PageBuf = [0; PAGE_SIZE];
+        let mut view = NodeView::<_, i32>::new(&mut buf);
+        view.init(3, NodeType::Leaf, true, -1);
+
+        let key = |k: i32| key::encode(&schema, &[], &k.into());
+
+        // Insert
+        let mut want: Vec<_> = inserts!(-50..50, i32)
+            .into_iter()
+            .map(|Slot::<i32>(k, _)| Slot(key::encode(&schema, &[], &k), Either::Value(0)))
+            .collect();
+        for slot in &want {
+            assert!(view.insert(slot.clone())?);
+        }
+        assert!(!view.insert(want[0].clone())?);
+        want.sort_by(|Slot(k, _), Slot(k0, _)| k.data.cmp(&k0.data));
+
+        let have: Vec<_> = (0..view.len()).map(|i| view.slot(i)).collect();
+        assert!(want == have);
+
+        // Replace, the old cells are reclaimed once the page fills up
+        for i in 0..1000 {
+            let old = view.replace(Slot(key(7), Either::Value(i)))?;
+            assert!(old.is_some());
+        }
+        assert!(view.len() == 100);
+        assert!(view.get(&key(7)) == Some(Slot(key(7), Either::Value(999))));
+        assert!(view.replace(Slot(key(50), Either::Value(1)))?.is_none());
+
+        // Remove
+        assert!(view.remove(&key(7)));
+        assert!(!view.remove(&key(7)));
+        assert!(view.get(&key(7)).is_none());
+        assert!(view.pop_last() == Some(Slot(key(50), Either::Value(1))));
+        assert!(view.len() == 99);
+
+        // Owned nodes read and write the same layout
+        let node: Node<i32> = Node::from(&buf, 3)?;
+        assert!(node.id == 3 && node.is_root && node.t == NodeType::Leaf);
+        assert!(node.values.len() == 99 && node.get(&key(7)).is_none());
+        assert!(Node::from(&PageBuf::try_from(&node)?, 3)? == node);
+
+        // Internal nodes
+        let node: Node<i32> = Node {
+            t: NodeType::Internal,
+            is_root: false,
+            next: 1,
+            id: 0,
+            values: vec![
+                Slot(10.into(), Either::Pointer(1)),
+                Slot(20.into(), Either::Pointer(2)),
+                Slot(30.into(), Either::Pointer(3)),
+                Slot(40.into(), Either::Pointer(4)),
+                Slot(50.into(), Either::Pointer(5)),
+            ],
+        };
+        let buf = PageBuf::try_from(&node)?;
+        let view = NodeView::<_, i32>::new(&buf);
+        assert!(view.find_child(&25.into()) == Some(3));
+        assert!(view.find_child(&30.into()) == Some(4));
+        assert!(view.find_child(&60.into()) == Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_view_full() -> crate::Result<()> {
+        let mut buf: PageBuf = [0; PAGE_SIZE];
+        let mut view = NodeView::<_, i32>::new(&mut buf);
+        view.init(0, NodeType::Leaf, true, -1);
+
+        let key = |k: u8| Tuple {
+            data: BytesMut::from(&[k; 600][..]),
+            ..Default::default()
+        };
+
+        // Full nodes return an error instead of overflowing
+        let mut k = 0;
+        while !view.almost_full(&key(k)) {
+            assert!(view.insert(Slot(key(k), Either::Value(k as i32)))?);
+            k += 1;
+        }
+        assert!(k == 6);
+        let have = view.insert(Slot(key(k), Either::Value(0)));
+        assert!(have == Err(Error::KeyTooLarge { size: 600 }), "Have: {have:?}");
+        assert!(view.len() == 6);
+
+        // Replacing reuses the room of the old slot
+        assert!(view.replace(Slot(key(0), Either::Value(1)))?.is_some());
+        assert!(view.get(&key(0)) == Some(Slot(key(0), Either::Value(1))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_view_corrupted() -> crate::Result<()> {
+        let mut buf: PageBuf = [0; PAGE_SIZE];
+        let mut view = NodeView::<_, i32>::new(&mut buf);
+        view.init(3, NodeType::Leaf, true, -1);
+        for k in 0..10 {
+            view.push(&Slot(k.into(), Either::Value(k)))?;
+        }
+        NodeView::<_, i32>::load(&buf, 3)?.check(3)?;
+
+        let corrupt = |f: &dyn Fn(&mut PageBuf)| {
+            let mut buf = buf;
+            f(&mut buf);
+            NodeView::<_, i32>::load(&buf, 3)
+                .and_then(|view| view.check(3))
+                .err()
+        };
+        let want = Some(Error::Corrupted { page_id: 3 });
+
+        // Slot pointing past the end of the page
+        assert!(corrupt(&|b| b[NODE_SLOTS_START..NODE_SLOTS_START + 2].fill(0xFF)) == want);
+        // Key running past the end of the page, the first cell is written last
+        let cell = PAGE_SIZE - slot_size::<i32>(4) + SLOT_SIZE;
+        assert!(corrupt(&|b| b[cell..cell + KEY_LEN_SIZE].fill(0xFF)) == want);
+        // More slots than there's room for
+        assert!(corrupt(&|b| b[NODE_LEN].fill(0xFF)) == want);
+        // A value in an internal node
+        assert!(corrupt(&|b| b[NODE_TYPE] = u8::from(NodeType::Internal)) == want);
+
+        Ok(())
+    }
 }
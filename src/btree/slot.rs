@@ -30,10 +30,32 @@ macro_rules! get_value {
     };
 }
 
+/// Flags written before the value or pointer.
+pub const FLAG_VALUE: u8 = 0;
+pub const FLAG_POINTER: u8 = 1;
+
 impl<V> Either<V> {
     pub const SIZE: usize = 1 + size_of::<V>();
 }
 
+impl<V> Either<V>
+where
+    V: Storable,
+{
+    pub fn write_to(&self, dst: &mut [u8], pos: usize) {
+        match self {
+            Either::Value(v) => {
+                dst[pos] = FLAG_VALUE;
+                v.write_to(dst, pos + 1);
+            }
+            Either::Pointer(p) => {
+                dst[pos] = FLAG_POINTER;
+                p.write_to(dst, pos + 1);
+            }
+        }
+    }
+}
+
 impl<V> From<&[u8]> for Either<V>
 where
     V: Storable,
@@ -44,12 +66,12 @@ where
         let either = value[0];
         let value = &value[1..];
         match either {
-            0 => {
+            FLAG_VALUE => {
                 let value = V::from_bytes(value);
                 Either::Value(value)
             }
-            1 => {
-                let b: [u8; 4] = value.try_into().unwrap();
+            FLAG_POINTER => {
+                let b: [u8; 4] = value[..4].try_into().unwrap();
                 let ptr = i32::from_be_bytes(b);
                 Either::Pointer(ptr)
             }
@@ -64,16 +86,7 @@ where
 {
     fn from(value: &Either<V>) -> Self {
         let mut ret = BytesMut::zeroed(Either::<V>::SIZE);
-        match value {
-            Either::Value(v) => {
-                ret[0] = 0;
-                v.write_to(&mut ret, 1);
-            }
-            Either::Pointer(p) => {
-                ret[0] = 1;
-                p.write_to(&mut ret, 1);
-            }
-        }
+        value.write_to(&mut ret, 0);
 
         ret
     }
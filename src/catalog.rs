@@ -8,7 +8,8 @@ use std::{
 use crate::{
     btree::BTree,
     disk::{Disk, FileSystem},
-    page::PageId,
+    error::Error,
+    page::{PageId, SegmentId},
     page_cache::SharedPageCache,
     table::{
         list::List as Table,
@@ -149,6 +150,7 @@ pub struct TableInfo<D: Disk = FileSystem> {
     name: String,
     schema: Schema,
     oid: OId,
+    segment: SegmentId,
     table: Table<D>,
 }
 
@@ -169,6 +171,7 @@ pub struct IndexInfo {
     schema: Schema,
     oid: OId,
     index_ty: IndexType,
+    segment: SegmentId,
     root: PageId,
 }
 
@@ -205,11 +208,14 @@ impl<D: Disk> Catalog<D> {
         }
 
         let oid = self.next_table_oid.fetch_add(1, Relaxed);
+        // Each table and index gets its own segment, segment 0 is shared by everything else
+        let segment = self.pc.allocate_segment()?;
         let info = TableInfo {
             name: name.into(),
             schema,
             oid,
-            table: Table::default(self.pc.clone())?,
+            segment,
+            table: Table::in_segment(self.pc.clone(), segment)?,
         };
 
         self.table_names.insert(name.into(), oid);
@@ -231,6 +237,31 @@ impl<D: Disk> Catalog<D> {
         self.table_names.keys().collect()
     }
 
+    /// Drops the table and its indexes, deleting their segments. Returns `false` if the table
+    /// doesn't exist.
+    pub fn drop_table(&mut self, name: &str) -> crate::Result<bool> {
+        let Some(oid) = self.table_names.remove(name) else {
+            return Ok(false);
+        };
+        let info = self
+            .tables
+            .remove(&oid)
+            .expect("table names and oids are in sync");
+
+        let mut segments = vec![info.segment];
+        for (_, index_oid) in self.index_names.remove(name).unwrap_or_default() {
+            if let Some(index) = self.indexes.remove(&index_oid) {
+                segments.push(index.segment);
+            }
+        }
+
+        for segment in segments {
+            self.pc.drop_segment(segment)?;
+        }
+
+        Ok(true)
+    }
+
     pub fn create_index(
         &mut self,
         index_name: &str,
@@ -238,17 +269,31 @@ impl<D: Disk> Catalog<D> {
         index_ty: IndexType,
         schema: &Schema,
         key: &[&str],
-    ) -> Option<&IndexInfo> {
-        // TODO: verify key schema against table schema
+    ) -> crate::Result<Option<&IndexInfo>> {
+        if matches!(index_ty, IndexType::HashTable) {
+            return Err(Error::Unsupported("hash table indexes"));
+        }
 
         if self.index_names.contains_key(index_name) {
-            return None;
+            return Ok(None);
         }
 
-        let indexed_table = self.index_names.get_mut(table_name)?;
+        let Some(indexed_table) = self.index_names.get_mut(table_name) else {
+            return Ok(None);
+        };
         if indexed_table.contains_key(index_name) {
             // Index with name already exists
-            return None;
+            return Ok(None);
+        }
+
+        let info = &self.tables[&self.table_names[table_name]];
+        if *schema != info.schema {
+            return Err(Error::SchemaMismatch(format!("schema doesn't match table {table_name}")));
+        }
+        if let Some(column) = key.iter().find(|&&k| !schema.iter().any(|c| c.name == k)) {
+            return Err(Error::SchemaMismatch(format!(
+                "table {table_name} has no column {column}"
+            )));
         }
 
         // Schema for creating key tuple from table tuple (offsets could be sparse)
@@ -257,22 +302,26 @@ impl<D: Disk> Catalog<D> {
         // Correct offsets for the index so they are read/written correctly
         let index_schema = tuple_schema.compact();
 
-        let root;
-        match index_ty {
-            IndexType::HashTable => todo!(),
-            IndexType::BTree => {
-                let mut btree = BTree::<RId, _>::new(self.pc.clone(), &index_schema);
-                let info = self.tables.get(&self.table_names[table_name])?;
-                for result in info.table.iter().expect("todo") {
-                    // Remove columns from the tuple to match schema
-                    let (_, Tuple { rid, data }) = result.expect("todo");
-                    let tuple = Tuple::from(&data, &tuple_schema);
-                    btree.insert(&tuple, &rid).expect("todo");
+        let segment = self.pc.allocate_segment()?;
+        let mut btree = BTree::<RId, _>::in_segment(self.pc.clone(), segment, &index_schema);
+        let built = info.table.iter().and_then(|iter| {
+            for result in iter {
+                // Remove columns from the tuple to match schema
+                let (_, Tuple { rid, data }) = result?;
+                let tuple = Tuple::from(&data, &tuple_schema);
+                if btree.get(&tuple)?.is_some() {
+                    return Err(Error::DuplicateKey);
                 }
-
-                root = btree.root();
+                btree.insert(&tuple, &rid)?;
             }
-        };
+
+            Ok(())
+        });
+        if let Err(e) = built {
+            self.pc.drop_segment(segment)?;
+            return Err(e);
+        }
+        let root = btree.root();
 
         let oid = self.next_index_oid.fetch_add(1, Relaxed);
         indexed_table.insert(index_name.into(), oid);
@@ -284,12 +333,12 @@ impl<D: Disk> Catalog<D> {
                 schema: index_schema,
                 oid,
                 index_ty,
+                segment,
                 root,
             },
         );
-        indexed_table.insert(index_name.into(), oid);
 
-        self.indexes.get(&oid)
+        Ok(self.indexes.get(&oid))
     }
 
     pub fn get_index(&self, table_name: &str, index_name: &str) -> Option<&IndexInfo> {
@@ -313,8 +362,9 @@ mod test {
     use crate::{
         btree::BTree,
         catalog::{Catalog, IndexType, Schema, Type},
-        disk::Memory,
-        page::PAGE_SIZE,
+        disk::{Disk, Memory},
+        error::Error,
+        page::segment_page_id,
         page_cache::PageCache,
         replacer::LRU,
         table::tuple::{RId, Tuple, TupleBuilder, TupleMeta, Value},
@@ -322,9 +372,8 @@ mod test {
 
     #[test]
     fn test_btree_index() -> crate::Result<()> {
-        const MEMORY: usize = PAGE_SIZE * 32;
         const K: usize = 2;
-        let memory = Memory::new::<MEMORY>();
+        let memory = Memory::new();
         let replacer = LRU::new(K);
         let pc = PageCache::new(memory, replacer, 0);
 
@@ -365,7 +414,7 @@ mod test {
                         ..Default::default()
                     },
                     RId {
-                        page_id: 0,
+                        page_id: segment_page_id(1, 0)?,
                         slot_id: 0,
                     },
                 ),
@@ -378,7 +427,7 @@ mod test {
                         ..Default::default()
                     },
                     RId {
-                        page_id: 0,
+                        page_id: segment_page_id(1, 0)?,
                         slot_id: 1,
                     },
                 ),
@@ -408,7 +457,13 @@ mod test {
 
             let index_schema = schema.filter(key).compact();
 
-            catalog.create_index(INDEX_A, TABLE_A, IndexType::BTree, &schema, &["col_a", "col_c"]);
+            catalog.create_index(
+                INDEX_A,
+                TABLE_A,
+                IndexType::BTree,
+                &schema,
+                &["col_a", "col_c"],
+            )?;
             let index = catalog
                 .get_index(TABLE_A, INDEX_A)
                 .expect("index_a should exist");
@@ -420,4 +475,100 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_drop_table() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        let schema: Schema = [("col_a", Type::Int), ("col_b", Type::BigInt)].into();
+
+        let mut catalog = Catalog::new(pc.clone());
+        for name in ["table_a", "table_b"] {
+            let info = catalog
+                .create_table(name, schema.clone())?
+                .expect("table should be created");
+            for i in 0..100 {
+                let tuple = TupleBuilder::new()
+                    .add(&Value::Int(i))
+                    .add(&Value::BigInt(i as i64))
+                    .build();
+                info.table.insert(&tuple, &TupleMeta { deleted: false })?;
+            }
+        }
+        catalog.create_index("index_a", "table_a", IndexType::BTree, &schema, &["col_a"])?;
+        pc.flush_all_pages()?;
+
+        let table_a = catalog.get_table_by_name("table_a").unwrap().segment;
+        let table_b = catalog.get_table_by_name("table_b").unwrap().segment;
+        let index_a = catalog.get_index("table_a", "index_a").unwrap().segment;
+        assert!(pc.disk().segment_len(table_a).unwrap() > 0);
+        assert!(pc.disk().segment_len(index_a).unwrap() > 0);
+
+        assert!(catalog.drop_table("table_a")?);
+        assert!(!catalog.drop_table("table_a")?);
+        assert!(catalog.get_table_by_name("table_a").is_none());
+        assert!(catalog.get_index("table_a", "index_a").is_none());
+        assert!(pc.disk().segment_len(table_a).unwrap() == 0);
+        assert!(pc.disk().segment_len(index_a).unwrap() == 0);
+
+        // Other tables are untouched
+        assert!(pc.disk().segment_len(table_b).unwrap() > 0);
+        let info = catalog.get_table_by_name("table_b").unwrap();
+        assert!(info.table.iter()?.count() == 100);
+
+        // A new catalog doesn't hand out segments that are or were in use
+        let mut catalog = Catalog::new(pc.clone());
+        let info = catalog
+            .create_table("table_c", schema.clone())?
+            .expect("table should be created");
+        assert!(![table_a, table_b, index_a].contains(&info.segment));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_index_errors() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        let schema: Schema = [("col_a", Type::Int), ("col_b", Type::BigInt)].into();
+
+        let mut catalog = Catalog::new(pc.clone());
+        let info = catalog
+            .create_table("table_a", schema.clone())?
+            .expect("table should be created");
+        for i in 0..10 {
+            let tuple = TupleBuilder::new()
+                .add(&Value::Int(i))
+                .add(&Value::BigInt(i as i64 % 2))
+                .build();
+            info.table.insert(&tuple, &TupleMeta { deleted: false })?;
+        }
+
+        let have =
+            catalog.create_index("index_a", "table_a", IndexType::BTree, &schema, &["col_c"]);
+        assert!(matches!(have, Err(Error::SchemaMismatch(_))), "Have: {:?}", have.err());
+
+        let other: Schema = [("col_a", Type::Int)].into();
+        let have = catalog.create_index("index_a", "table_a", IndexType::BTree, &other, &["col_a"]);
+        assert!(matches!(have, Err(Error::SchemaMismatch(_))), "Have: {:?}", have.err());
+
+        let have =
+            catalog.create_index("index_a", "table_a", IndexType::HashTable, &schema, &["col_a"]);
+        assert!(matches!(have, Err(Error::Unsupported(_))), "Have: {:?}", have.err());
+
+        // col_b only has two distinct values
+        let have =
+            catalog.create_index("index_b", "table_a", IndexType::BTree, &schema, &["col_b"]);
+        assert!(matches!(have, Err(Error::DuplicateKey)), "Have: {:?}", have.err());
+        assert!(catalog.get_index("table_a", "index_b").is_none());
+
+        let have =
+            catalog.create_index("index_a", "table_a", IndexType::BTree, &schema, &["col_a"]);
+        assert!(have?.is_some());
+        let have =
+            catalog.create_index("index_a", "missing", IndexType::BTree, &schema, &["col_a"]);
+        assert!(have?.is_none());
+
+        Ok(())
+    }
 }
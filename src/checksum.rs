@@ -0,0 +1,52 @@
+/// CRC-32C (Castagnoli), reflected
+const POLY: u32 = 0x82f6_3b78;
+
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0;
+    for b in data {
+        crc = TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::crc32c;
+
+    #[test]
+    fn test_crc32c() {
+        let tcs: [(&[u8], u32); 4] = [
+            (b"", 0x0000_0000),
+            (b"a", 0xc1d0_4330),
+            (b"123456789", 0xe306_9283),
+            (&[0; 32], 0x8a91_36aa),
+        ];
+
+        for (data, want) in tcs {
+            let have = crc32c(data);
+            assert!(want == have, "Want: {want:#x}, Have: {have:#x}");
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io, mem,
+    ops::Range,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use nix::{sys::uio, unistd};
+
+use crate::{
+    disk::{lz, Disk},
+    error::LockExt,
+    page::{self, PageBuf, PageId, SegmentId, PAGE_SIZE},
+};
+
+/*
+    Extent:
+    PageId | Seq | Capacity | Len | Data
+
+    The file is a sequence of extents. A page's latest version is the extent with the highest
+    sequence number for its page id, any others are free to be reused. Dropping a segment rewrites
+    the page id of every extent that held one of its pages as `FREE`, so no older version of a
+    dropped page comes back on reopen.
+*/
+
+const EXTENT_PAGE_ID: Range<usize> = 0..4;
+const EXTENT_SEQ: Range<usize> = 4..12;
+const EXTENT_CAPACITY: Range<usize> = 12..14;
+const EXTENT_LEN: Range<usize> = 14..16;
+const EXTENT_HEADER_SIZE: usize = 16;
+
+/// Page id of an extent that doesn't hold a page
+const FREE: PageId = -1;
+
+/// Extents are rounded up to this size so that pages can grow a little in place
+const EXTENT_ALIGN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Extent {
+    offset: u64,
+    /// Number of bytes available for data, excluding the header
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct State {
+    map: HashMap<PageId, Extent>,
+    free: Vec<Extent>,
+    /// Freed since the last sync. They're only reused once the extents that replaced them are
+    /// durable, otherwise a crash could leave a page's only copy overwritten.
+    pending: Vec<Extent>,
+    end: u64,
+    next_seq: u64,
+}
+
+/// Disk that compresses pages into variable sized extents in a single file.
+pub struct Compressed {
+    file: File,
+    state: Mutex<State>,
+}
+
+impl Disk for Compressed {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        let mut ret = [0; PAGE_SIZE];
+        let buf = loop {
+            // Copied out so the lock isn't held while reading
+            let Some(extent) = self.state().map.get(&page_id).copied() else {
+                return Ok(ret);
+            };
+
+            let mut buf = vec![0; EXTENT_HEADER_SIZE + extent.capacity];
+            uio::pread(self.file.as_raw_fd(), &mut buf, extent.offset as i64)?;
+
+            // Otherwise the extent was freed and reused while it was being read
+            if buf[EXTENT_PAGE_ID] == page_id.to_be_bytes() {
+                break buf;
+            }
+        };
+
+        let len = u16::from_be_bytes(buf[EXTENT_LEN].try_into().unwrap()) as usize;
+        let data = buf
+            .get(EXTENT_HEADER_SIZE..EXTENT_HEADER_SIZE + len)
+            .ok_or_else(|| invalid(page_id))?;
+        if len == PAGE_SIZE {
+            // Stored uncompressed
+            ret.copy_from_slice(data);
+        } else if lz::decompress(data, &mut ret) != Some(PAGE_SIZE) {
+            return Err(invalid(page_id));
+        }
+
+        Ok(ret)
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        let mut compressed = lz::compress(data);
+        if compressed.len() >= PAGE_SIZE {
+            compressed = data.to_vec();
+        }
+        let len = compressed.len();
+
+        let mut state = self.state();
+        let extent = match state.map.get(&page_id) {
+            Some(extent) if extent.capacity >= len => *extent,
+            current => {
+                let current = current.copied();
+                let extent = state.allocate(len);
+                if let Some(current) = current {
+                    state.pending.push(current);
+                }
+
+                extent
+            }
+        };
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+
+        let mut buf = vec![0; EXTENT_HEADER_SIZE + len];
+        buf[EXTENT_PAGE_ID].copy_from_slice(&page_id.to_be_bytes());
+        buf[EXTENT_SEQ].copy_from_slice(&seq.to_be_bytes());
+        buf[EXTENT_CAPACITY].copy_from_slice(&(extent.capacity as u16).to_be_bytes());
+        buf[EXTENT_LEN].copy_from_slice(&(len as u16).to_be_bytes());
+        buf[EXTENT_HEADER_SIZE..].copy_from_slice(&compressed);
+
+        uio::pwrite(self.file.as_raw_fd(), &buf, extent.offset as i64)?;
+        state.map.insert(page_id, extent);
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        // Taken first so only extents freed by writes the sync covers are released
+        let pending = mem::take(&mut self.state().pending);
+        if let Err(e) = unistd::fdatasync(self.file.as_raw_fd()) {
+            self.state().pending.extend(pending);
+            return Err(e.into());
+        }
+        self.state().free.extend(pending);
+
+        Ok(())
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        let state = self.state();
+        let len = state
+            .map
+            .keys()
+            .filter(|id| page::segment_of(**id) == segment)
+            .map(|id| page::segment_offset(*id) + 1)
+            .max();
+
+        Ok(len.unwrap_or(0))
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        let mut state = self.state();
+
+        // Older versions of the pages are in free extents the map doesn't know about, so every
+        // header is checked
+        let mut offset = 0;
+        while offset < state.end {
+            let mut header = [0; EXTENT_HEADER_SIZE];
+            uio::pread(self.file.as_raw_fd(), &mut header, offset as i64)?;
+            let page_id = PageId::from_be_bytes(header[EXTENT_PAGE_ID].try_into().unwrap());
+            let capacity = u16::from_be_bytes(header[EXTENT_CAPACITY].try_into().unwrap()) as usize;
+
+            if page_id != FREE && page::segment_of(page_id) == segment {
+                uio::pwrite(self.file.as_raw_fd(), &FREE.to_be_bytes(), offset as i64)?;
+
+                let extent = Extent { offset, capacity };
+                if state.map.get(&page_id) == Some(&extent) {
+                    state.map.remove(&page_id);
+                    state.pending.push(extent);
+                }
+            }
+
+            offset += (EXTENT_HEADER_SIZE + capacity) as u64;
+        }
+
+        Ok(())
+    }
+}
+
+impl State {
+    fn allocate(&mut self, len: usize) -> Extent {
+        if let Some(i) = self.free.iter().position(|e| e.capacity >= len) {
+            return self.free.swap_remove(i);
+        }
+
+        let size = (EXTENT_HEADER_SIZE + len).next_multiple_of(EXTENT_ALIGN);
+        let extent = Extent {
+            offset: self.end,
+            capacity: size - EXTENT_HEADER_SIZE,
+        };
+        self.end += size as u64;
+
+        extent
+    }
+}
+
+impl Compressed {
+    pub fn new(file: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(file)?;
+
+        let state = Self::scan(&file)?;
+
+        Ok(Self {
+            file,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Rebuilds the extent map from the headers in the file.
+    fn scan(file: &File) -> io::Result<State> {
+        let size = file.metadata()?.len();
+        let mut latest: HashMap<PageId, (u64, Extent)> = HashMap::new();
+        let mut state = State::default();
+
+        let mut offset = 0;
+        while offset + EXTENT_HEADER_SIZE as u64 <= size {
+            let mut header = [0; EXTENT_HEADER_SIZE];
+            uio::pread(file.as_raw_fd(), &mut header, offset as i64)?;
+
+            let capacity = u16::from_be_bytes(header[EXTENT_CAPACITY].try_into().unwrap()) as usize;
+            if capacity == 0 {
+                // Torn append at the end of the file
+                break;
+            }
+
+            let page_id = PageId::from_be_bytes(header[EXTENT_PAGE_ID].try_into().unwrap());
+            let seq = u64::from_be_bytes(header[EXTENT_SEQ].try_into().unwrap());
+            let extent = Extent { offset, capacity };
+            state.next_seq = state.next_seq.max(seq + 1);
+
+            match latest.get(&page_id) {
+                _ if page_id == FREE => state.free.push(extent),
+                Some((s, _)) if *s > seq => state.free.push(extent),
+                Some((_, old)) => {
+                    state.free.push(*old);
+                    latest.insert(page_id, (seq, extent));
+                }
+                None => {
+                    latest.insert(page_id, (seq, extent));
+                }
+            }
+
+            offset += (EXTENT_HEADER_SIZE + capacity) as u64;
+        }
+
+        state.end = offset;
+        state.map = latest.into_iter().map(|(id, (_, e))| (id, e)).collect();
+
+        Ok(state)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unpoisoned()
+    }
+
+    /// Size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.state().end
+    }
+}
+
+fn invalid(page_id: PageId) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("page {page_id} could not be decompressed"))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::RngCore;
+
+    use crate::{
+        disk::{compressed::Compressed, Disk},
+        page::{self, PAGE_HEADER_SIZE, PAGE_SIZE},
+        page_cache::PageCache,
+        replacer::LRU,
+        test::CleanUp,
+        writep,
+    };
+
+    #[test]
+    fn test_compressed() -> std::io::Result<()> {
+        const FILE: &str = "test_compressed.db";
+        let _cleanup = CleanUp::file(FILE);
+
+        let disk = Compressed::new(FILE)?;
+
+        let mut random = [0; PAGE_SIZE];
+        rand::thread_rng().fill_bytes(&mut random);
+
+        let mut pages = Vec::new();
+        for i in 0..100 {
+            let mut page = [0; PAGE_SIZE];
+            for (j, chunk) in page[..1024].chunks_mut(4).enumerate() {
+                chunk.copy_from_slice(&((i + j) as i32 % 7).to_be_bytes());
+            }
+            pages.push(page);
+        }
+
+        for (i, page) in pages.iter().enumerate() {
+            disk.write_page(i as i32, page)?;
+        }
+        disk.write_page(1000, &random)?;
+        disk.sync()?;
+
+        assert!(disk.size() < (PAGE_SIZE * 20) as u64, "101 pages took up {} bytes", disk.size());
+
+        assert!(disk.read_page(500)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(1000)? == random);
+        for (i, want) in pages.iter().enumerate() {
+            let have = disk.read_page(i as i32)?;
+            assert!(*want == have, "page {i} did not read back");
+        }
+
+        // Grow a page out of its extent, the old extent gets reused by the next page that fits
+        // once the new one is synced
+        disk.write_page(0, &random)?;
+        let size = disk.size();
+        disk.write_page(2000, &pages[0])?;
+        assert!(disk.size() > size, "the freed extent was reused before a sync");
+        disk.sync()?;
+        let size = disk.size();
+        disk.write_page(3000, &pages[0])?;
+        assert!(disk.size() == size, "expected the freed extent to be reused");
+
+        // Reopen and make sure the latest version of each page is read
+        drop(disk);
+        let disk = Compressed::new(FILE)?;
+        assert!(disk.read_page(0)? == random);
+        assert!(disk.read_page(1000)? == random);
+        assert!(disk.read_page(2000)? == pages[0]);
+        for (i, want) in pages.iter().enumerate().skip(1) {
+            let have = disk.read_page(i as i32)?;
+            assert!(*want == have, "page {i} did not read back after reopening");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_drop_segment() -> crate::Result<()> {
+        const FILE: &str = "test_compressed_drop_segment.db";
+        let _cleanup = CleanUp::file(FILE);
+
+        let disk = Compressed::new(FILE)?;
+
+        let mut random = [0; PAGE_SIZE];
+        rand::thread_rng().fill_bytes(&mut random);
+        let mut small = [0; PAGE_SIZE];
+        small[0] = 1;
+
+        let (a, b) = (page::segment_page_id(0, 1)?, page::segment_page_id(1, 1)?);
+        disk.write_page(a, &small)?;
+        disk.write_page(page::segment_page_id(1, 0)?, &random)?;
+        // Leave an older version of b in a free extent
+        disk.write_page(b, &small)?;
+        disk.write_page(b, &random)?;
+
+        disk.drop_segment(1)?;
+        assert!(disk.segment_len(1)? == 0);
+        assert!(disk.read_page(b)? == [0; PAGE_SIZE]);
+
+        // The dropped extents get reused after a sync
+        disk.sync()?;
+        let size = disk.size();
+        disk.write_page(page::segment_page_id(2, 0)?, &random)?;
+        assert!(disk.size() == size, "expected a dropped extent to be reused");
+
+        // Neither version of b comes back, pages written after the drop do
+        let c = page::segment_page_id(1, 2)?;
+        disk.write_page(c, &small)?;
+        drop(disk);
+        let disk = Compressed::new(FILE)?;
+        assert!(disk.read_page(b)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(a)? == small);
+        assert!(disk.read_page(c)? == small);
+        assert!(disk.segment_len(1)? == 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_page_cache() -> crate::Result<()> {
+        const FILE: &str = "test_compressed_page_cache.db";
+        const K: usize = 2;
+        let _cleanup = CleanUp::file(FILE);
+
+        let disk = Compressed::new(FILE).expect("could not open file");
+        let pc = PageCache::new(disk, LRU::new(K), 0);
+        for _ in 0..10 {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+        pc.flush_all_pages()?;
+        drop(pc);
+
+        let disk = Compressed::new(FILE).expect("could not open file");
+        let pc = PageCache::new(disk, LRU::new(K), 10);
+        for id in 0..10 {
+            let page = pc.fetch_page(id)?;
+            let r = page.read();
+            assert!(r.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == id.to_be_bytes());
+        }
+
+        Ok(())
+    }
+}
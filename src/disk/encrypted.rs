@@ -0,0 +1,468 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io,
+    ops::Range,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use nix::{sys::uio, unistd};
+
+use crate::{
+    disk::Disk,
+    error::LockExt,
+    page::{PageBuf, PageId, SegmentId, PAGE_SIZE, SEGMENT_PAGES},
+};
+
+/*
+    Slot:
+    Counter | Tag | Ciphertext
+
+    Each page is stored in a fixed size slot. The nonce is the page id followed by the counter of
+    the write, and the page id is authenticated so pages can't be swapped around in the file.
+
+    Page map (<file>.pages):
+    Counter | Tag | Ciphertext
+
+    Bitmap of the pages that have been written, saved on sync and encrypted like a page with id
+    `MAP_ID`. A zeroed slot only reads back as an empty page if the map doesn't have it, so pages
+    can't be rolled back to empty. Rolling back the file together with its map isn't detected.
+*/
+
+const SLOT_COUNTER: Range<usize> = 0..8;
+const SLOT_TAG: Range<usize> = 8..24;
+const SLOT_DATA: Range<usize> = 24..24 + PAGE_SIZE;
+const SLOT_SIZE: usize = SLOT_DATA.end;
+
+const MAP_ID: PageId = -1;
+
+pub const KEY_SIZE: usize = 32;
+
+/// Writes lost in a crash may have used counters after the highest one found on disk, so a
+/// reopened file starts this far past it.
+const COUNTER_GAP: u64 = 1 << 32;
+
+#[derive(Default)]
+struct PageMap {
+    /// Bit per page, set once the page has been written
+    written: Vec<u8>,
+    /// Changed since the map was last saved
+    changed: bool,
+}
+
+impl PageMap {
+    fn has(&self, page_id: PageId) -> bool {
+        let i = page_id as usize;
+        self.written
+            .get(i / 8)
+            .is_some_and(|b| b >> (i % 8) & 1 == 1)
+    }
+
+    fn set(&mut self, page_id: PageId, written: bool) {
+        let i = page_id as usize;
+        if self.written.len() <= i / 8 {
+            if !written {
+                return;
+            }
+            self.written.resize(i / 8 + 1, 0);
+        }
+
+        let before = self.written[i / 8];
+        if written {
+            self.written[i / 8] |= 1 << (i % 8);
+        } else {
+            self.written[i / 8] &= !(1 << (i % 8));
+        }
+        self.changed |= self.written[i / 8] != before;
+    }
+}
+
+/// Disk that encrypts and authenticates pages with ChaCha20-Poly1305.
+pub struct Encrypted {
+    file: File,
+    map_path: PathBuf,
+    cipher: ChaCha20Poly1305,
+    counter: AtomicU64,
+    map: RwLock<PageMap>,
+}
+
+impl Disk for Encrypted {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        let mut slot = [0; SLOT_SIZE];
+        let n = uio::pread(self.file.as_raw_fd(), &mut slot, offset(page_id)?)?;
+        let mut ret = [0; PAGE_SIZE];
+        if n == 0 || slot.iter().all(|b| *b == 0) {
+            if self.map().has(page_id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("page {page_id} was written but its slot is empty"),
+                ));
+            }
+
+            // Never written, any other slot has to pass authentication
+            return Ok(ret);
+        }
+        let counter = u64::from_be_bytes(slot[SLOT_COUNTER].try_into().unwrap());
+
+        ret.copy_from_slice(&slot[SLOT_DATA]);
+        let tag = Tag::from_slice(&slot[SLOT_TAG]);
+        self.decrypt(page_id, counter, &mut ret, tag)?;
+
+        Ok(ret)
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        let offset = offset(page_id)?;
+        let counter = self.counter.fetch_add(1, Relaxed);
+
+        let mut slot = [0; SLOT_SIZE];
+        slot[SLOT_DATA].copy_from_slice(data);
+        let tag = self.encrypt(page_id, counter, &mut slot[SLOT_DATA])?;
+        slot[SLOT_COUNTER].copy_from_slice(&counter.to_be_bytes());
+        slot[SLOT_TAG].copy_from_slice(&tag);
+
+        uio::pwrite(self.file.as_raw_fd(), &slot, offset)?;
+        if !self.map().has(page_id) {
+            self.map_mut().set(page_id, true);
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        // Taken before syncing so the saved map only has pages whose writes are durable
+        let written = {
+            let mut map = self.map_mut();
+            let changed = std::mem::take(&mut map.changed);
+            changed.then(|| map.written.clone())
+        };
+
+        let ret = unistd::fdatasync(self.file.as_raw_fd())
+            .map_err(io::Error::from)
+            .and_then(|_| match &written {
+                Some(written) => self.write_map(written),
+                None => Ok(()),
+            });
+        if ret.is_err() && written.is_some() {
+            self.map_mut().changed = true;
+        }
+
+        ret
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        super::segment_len(&self.file, SLOT_SIZE, segment)
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        let first = i64::from(segment) * i64::from(SEGMENT_PAGES);
+        {
+            let mut map = self.map_mut();
+            for page_id in first..first + i64::from(SEGMENT_PAGES) {
+                map.set(page_id as PageId, false);
+            }
+        }
+
+        // Zeroed slots read back as pages that were never written, once the map no longer has them
+        self.sync()?;
+        super::punch_hole(
+            &self.file,
+            first * SLOT_SIZE as i64,
+            i64::from(SEGMENT_PAGES) * SLOT_SIZE as i64,
+        )
+    }
+}
+
+impl Encrypted {
+    /// Opens `file` and its page map, creating both if `file` is empty.
+    pub fn new(file: impl AsRef<Path>, key: &[u8; KEY_SIZE]) -> io::Result<Self> {
+        let mut map_path = OsString::from(file.as_ref());
+        map_path.push(".pages");
+        let map_path = PathBuf::from(map_path);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(file)?;
+
+        let mut ret = Self {
+            file,
+            map_path,
+            cipher: ChaCha20Poly1305::new(key.into()),
+            counter: AtomicU64::new(0),
+            map: RwLock::default(),
+        };
+
+        // Pages written after the map was last saved are found in the file
+        let mut highest = 0;
+        let mut map = PageMap::default();
+        let slots = ret.file.metadata()?.len() / SLOT_SIZE as u64;
+        for i in 0..slots {
+            let mut counter = [0; 8];
+            uio::pread(ret.file.as_raw_fd(), &mut counter, (i * SLOT_SIZE as u64) as i64)?;
+            let counter = u64::from_be_bytes(counter);
+            if counter != 0 {
+                map.set(i as PageId, true);
+            }
+            highest = highest.max(counter);
+        }
+
+        match fs::read(&ret.map_path) {
+            Ok(mut buf) if buf.len() >= SLOT_TAG.end => {
+                let counter = u64::from_be_bytes(buf[SLOT_COUNTER].try_into().unwrap());
+                let tag = *Tag::from_slice(&buf[SLOT_TAG]);
+                ret.decrypt(MAP_ID, counter, &mut buf[SLOT_TAG.end..], &tag)?;
+                let saved = &buf[SLOT_TAG.end..];
+                if map.written.len() < saved.len() {
+                    map.written.resize(saved.len(), 0);
+                }
+                for (have, saved) in map.written.iter_mut().zip(saved) {
+                    *have |= saved;
+                }
+                highest = highest.max(counter);
+            }
+            Ok(_) => return Err(invalid_map(&ret.map_path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && slots == 0 => {
+                // New file, save an empty map so a missing one can't pass for a new file
+                ret.write_map(&[])?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(invalid_map(&ret.map_path))
+            }
+            Err(e) => return Err(e),
+        }
+
+        // Counter 0 marks a slot that was never written
+        let counter = if highest == 0 { 1 } else { highest + COUNTER_GAP };
+        *ret.counter.get_mut() = counter;
+        map.changed = false;
+        *ret.map.get_mut().unpoisoned() = map;
+
+        Ok(ret)
+    }
+
+    /// Opens `file` with the key stored in `keyfile`, see `read_keyfile`.
+    pub fn with_keyfile(file: impl AsRef<Path>, keyfile: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(file, &read_keyfile(keyfile)?)
+    }
+
+    fn map(&self) -> RwLockReadGuard<'_, PageMap> {
+        self.map.read().unpoisoned()
+    }
+
+    fn map_mut(&self) -> RwLockWriteGuard<'_, PageMap> {
+        self.map.write().unpoisoned()
+    }
+
+    fn encrypt(&self, page_id: PageId, counter: u64, data: &mut [u8]) -> io::Result<Tag> {
+        self.cipher
+            .encrypt_in_place_detached(&nonce(page_id, counter), &page_id.to_be_bytes(), data)
+            .map_err(|_| io::Error::other(format!("page {page_id} could not be encrypted")))
+    }
+
+    fn decrypt(&self, page_id: PageId, counter: u64, data: &mut [u8], tag: &Tag) -> io::Result<()> {
+        self.cipher
+            .decrypt_in_place_detached(&nonce(page_id, counter), &page_id.to_be_bytes(), data, tag)
+            .map_err(|_| {
+                // Either the key is wrong or the slot was modified
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("page {page_id} failed authentication"),
+                )
+            })
+    }
+
+    fn write_map(&self, written: &[u8]) -> io::Result<()> {
+        let counter = self.counter.fetch_add(1, Relaxed);
+        let mut buf = vec![0; SLOT_TAG.end];
+        buf.extend_from_slice(written);
+        let tag = self.encrypt(MAP_ID, counter, &mut buf[SLOT_TAG.end..])?;
+        buf[SLOT_COUNTER].copy_from_slice(&counter.to_be_bytes());
+        buf[SLOT_TAG].copy_from_slice(&tag);
+
+        // Write a new copy and swap it in so a crash can't leave a partial map
+        let mut tmp = OsString::from(&self.map_path);
+        tmp.push(".tmp");
+        fs::write(&tmp, buf)?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(&tmp, &self.map_path)?;
+        let dir = match self.map_path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()
+    }
+}
+
+fn invalid_map(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("page map {path:?} is missing or truncated"))
+}
+
+/// Reads a key from a file containing either the raw 32 bytes or 64 hex characters.
+pub fn read_keyfile(path: impl AsRef<Path>) -> io::Result<[u8; KEY_SIZE]> {
+    let contents = fs::read(path)?;
+    if let Ok(key) = contents.as_slice().try_into() {
+        return Ok(key);
+    }
+
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("keyfile must contain {KEY_SIZE} bytes or {} hex characters", KEY_SIZE * 2),
+        )
+    };
+
+    let hex = contents.trim_ascii();
+    if hex.len() != KEY_SIZE * 2 {
+        return Err(invalid());
+    }
+
+    let mut key = [0; KEY_SIZE];
+    for (b, pair) in key.iter_mut().zip(hex.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *b = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+
+    Ok(key)
+}
+
+fn offset(page_id: PageId) -> io::Result<i64> {
+    if page_id < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid page id {page_id}"),
+        ));
+    }
+
+    Ok(page_id as i64 * SLOT_SIZE as i64)
+}
+
+fn nonce(page_id: PageId, counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[..4].copy_from_slice(&page_id.to_be_bytes());
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+
+    nonce.into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use crate::{
+        disk::{
+            encrypted::{read_keyfile, Encrypted, SLOT_COUNTER, SLOT_DATA, SLOT_SIZE},
+            Disk,
+        },
+        page::{segment_page_id, PAGE_SIZE},
+        test::CleanUp,
+    };
+
+    #[test]
+    fn test_encrypted() -> io::Result<()> {
+        const FILE: &str = "test_encrypted.db";
+        const KEYFILE: &str = "test_encrypted.key";
+        const MAP: &str = "test_encrypted.db.pages";
+        let _cleanup = CleanUp::file(FILE);
+        let _cleanup_map = CleanUp::file(MAP);
+        let _cleanup_key = CleanUp::file(KEYFILE);
+
+        let key = [7; 32];
+        std::fs::write(KEYFILE, "07".repeat(32) + "\n")?;
+        assert!(read_keyfile(KEYFILE)? == key);
+
+        let disk = Encrypted::with_keyfile(FILE, KEYFILE)?;
+        let mut page = [0; PAGE_SIZE];
+        page[..11].copy_from_slice(b"secret data");
+        disk.write_page(0, &page)?;
+        disk.write_page(2, &page)?;
+        disk.sync()?;
+
+        assert!(disk.read_page(0)? == page);
+        assert!(disk.read_page(1)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(5)? == [0; PAGE_SIZE]);
+
+        // Nothing is stored in plain text, and the same page encrypts differently each time
+        let raw = std::fs::read(FILE)?;
+        assert!(!raw.windows(11).any(|w| w == b"secret data"));
+        assert!(raw[SLOT_DATA] != raw[SLOT_SIZE * 2..][SLOT_DATA]);
+
+        // Reopen with the right key
+        drop(disk);
+        let disk = Encrypted::new(FILE, &key)?;
+        assert!(disk.read_page(2)? == page);
+
+        // Wrong key
+        let err = Encrypted::new(FILE, &[8; 32]).err().unwrap();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+
+        // Tampered page
+        let mut raw = std::fs::read(FILE)?;
+        raw[SLOT_DATA.start + 100] ^= 1;
+        std::fs::write(FILE, &raw)?;
+        let err = disk.read_page(0).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+
+        // Only the counter zeroed doesn't pass as a page that was never written
+        let mut zeroed = std::fs::read(FILE)?;
+        zeroed[SLOT_SIZE * 2..][SLOT_COUNTER].fill(0);
+        std::fs::write(FILE, &zeroed)?;
+        let err = disk.read_page(2).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+        std::fs::write(FILE, &raw)?;
+
+        // A zeroed slot doesn't pass as a page that was never written either, also once reopened
+        zeroed[SLOT_SIZE * 2..SLOT_SIZE * 3].fill(0);
+        std::fs::write(FILE, &zeroed)?;
+        let err = disk.read_page(2).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+        let reopened = Encrypted::new(FILE, &key)?;
+        let err = reopened.read_page(2).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+        drop(reopened);
+        std::fs::write(FILE, &raw)?;
+
+        // The map can't be removed or tampered with
+        let map = std::fs::read(MAP)?;
+        std::fs::remove_file(MAP)?;
+        let err = Encrypted::new(FILE, &key).err().unwrap();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+        let mut tampered = map.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        std::fs::write(MAP, &tampered)?;
+        let err = Encrypted::new(FILE, &key).err().unwrap();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+        std::fs::write(MAP, &map)?;
+
+        // A valid slot copied over another page
+        raw.copy_within(SLOT_SIZE * 2..SLOT_SIZE * 3, 0);
+        std::fs::write(FILE, &raw)?;
+        assert!(disk.read_page(2)? == page);
+        assert!(disk.read_page(0).is_err());
+
+        // Dropped pages read back as never written, also once reopened
+        let page_id = segment_page_id(1, 3).unwrap();
+        disk.write_page(page_id, &page)?;
+        disk.drop_segment(1)?;
+        assert!(disk.read_page(page_id)? == [0; PAGE_SIZE]);
+        drop(disk);
+        let disk = Encrypted::new(FILE, &key)?;
+        assert!(disk.read_page(page_id)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(2)? == page);
+
+        // Bad keyfile
+        std::fs::write(KEYFILE, "not a key")?;
+        let err = read_keyfile(KEYFILE).unwrap_err();
+        assert!(err.kind() == io::ErrorKind::InvalidData, "{err}");
+
+        Ok(())
+    }
+}
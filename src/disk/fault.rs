@@ -0,0 +1,234 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    disk::Disk,
+    error::LockExt,
+    page::{self, PageBuf, PageId, SegmentId},
+};
+
+#[derive(Default)]
+struct State {
+    reads: usize,
+    writes: usize,
+    fail_reads: HashSet<usize>,
+    fail_writes: HashSet<usize>,
+    /// Write number -> number of bytes of the page that make it to disk
+    torn_writes: HashMap<usize, usize>,
+    /// Writes since the last sync, these are lost on a crash
+    unsynced: HashMap<PageId, PageBuf>,
+}
+
+/// Wraps a `Disk` and fails operations on demand.
+///
+/// Reads and writes are numbered from zero in the order they reach the disk. Writes are held until
+/// `sync` so that `crash` can drop them.
+pub struct Faulty<D: Disk> {
+    inner: D,
+    state: Mutex<State>,
+}
+
+impl<D: Disk> Disk for Faulty<D> {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        let mut state = self.state();
+        let n = state.reads;
+        state.reads += 1;
+
+        if state.fail_reads.remove(&n) {
+            return Err(injected(format!("read {n} of page {page_id} failed")));
+        }
+
+        match state.unsynced.get(&page_id) {
+            Some(data) => Ok(*data),
+            None => self.inner.read_page(page_id),
+        }
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        let mut state = self.state();
+        let n = state.writes;
+        state.writes += 1;
+
+        if state.fail_writes.remove(&n) {
+            return Err(injected(format!("write {n} of page {page_id} failed")));
+        }
+
+        if let Some(len) = state.torn_writes.remove(&n) {
+            // The prefix makes it to disk as if we crashed part way through the write
+            let mut torn = match state.unsynced.remove(&page_id) {
+                Some(data) => data,
+                None => self.inner.read_page(page_id)?,
+            };
+            torn[..len].copy_from_slice(&data[..len]);
+            self.inner.write_page(page_id, &torn)?;
+
+            return Err(injected(format!("write {n} of page {page_id} was torn at {len} bytes")));
+        }
+
+        state.unsynced.insert(page_id, *data);
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut state = self.state();
+        for (page_id, data) in &state.unsynced {
+            self.inner.write_page(*page_id, data)?;
+        }
+        state.unsynced.clear();
+
+        self.inner.sync()
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        let state = self.state();
+        let unsynced = state
+            .unsynced
+            .keys()
+            .filter(|id| page::segment_of(**id) == segment)
+            .map(|id| page::segment_offset(*id) + 1)
+            .max();
+
+        Ok(self.inner.segment_len(segment)?.max(unsynced.unwrap_or(0)))
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        let mut state = self.state();
+        state
+            .unsynced
+            .retain(|id, _| page::segment_of(*id) != segment);
+
+        self.inner.drop_segment(segment)
+    }
+}
+
+impl<D: Disk> Faulty<D> {
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unpoisoned()
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Number of reads attempted so far.
+    pub fn reads(&self) -> usize {
+        self.state().reads
+    }
+
+    /// Number of writes attempted so far.
+    pub fn writes(&self) -> usize {
+        self.state().writes
+    }
+
+    /// Fail the `n`th read from now, `0` being the next read.
+    pub fn fail_read(&self, n: usize) {
+        let mut state = self.state();
+        let n = state.reads + n;
+        state.fail_reads.insert(n);
+    }
+
+    /// Fail the `n`th write from now, `0` being the next write. Nothing is written.
+    pub fn fail_write(&self, n: usize) {
+        let mut state = self.state();
+        let n = state.writes + n;
+        state.fail_writes.insert(n);
+    }
+
+    /// Fail the `n`th write from now after only the first `len` bytes of the page are written. The
+    /// torn page is persisted, it survives a crash.
+    pub fn tear_write(&self, n: usize, len: usize) {
+        let mut state = self.state();
+        let n = state.writes + n;
+        state.torn_writes.insert(n, len);
+    }
+
+    /// Simulate a crash, dropping all writes since the last sync.
+    pub fn crash(&self) {
+        self.state().unsynced.clear();
+    }
+
+    /// Flip every bit of the byte at `offset` in the stored copy of the page.
+    pub fn corrupt(&self, page_id: PageId, offset: usize) -> io::Result<()> {
+        let mut state = self.state();
+        match state.unsynced.get_mut(&page_id) {
+            Some(data) => data[offset] ^= 0xff,
+            None => {
+                let mut data = self.inner.read_page(page_id)?;
+                data[offset] ^= 0xff;
+                self.inner.write_page(page_id, &data)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn injected(msg: String) -> io::Error {
+    io::Error::other(format!("injected fault: {msg}"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        disk::{fault::Faulty, Disk, Memory},
+        page::PAGE_SIZE,
+    };
+
+    #[test]
+    fn test_faulty() -> std::io::Result<()> {
+        let disk = Faulty::new(Memory::new());
+
+        let a = [1; PAGE_SIZE];
+        let b = [2; PAGE_SIZE];
+
+        // Fail the second write only
+        disk.fail_write(1);
+        disk.write_page(0, &a)?;
+        assert!(disk.write_page(1, &b).is_err());
+        disk.write_page(1, &b)?;
+
+        // Fail the next read only
+        disk.fail_read(0);
+        assert!(disk.read_page(0).is_err());
+        assert!(disk.read_page(0)? == a);
+
+        // Synced writes survive a crash, others don't
+        disk.sync()?;
+        disk.write_page(0, &b)?;
+        assert!(disk.read_page(0)? == b);
+        disk.crash();
+        assert!(disk.read_page(0)? == a);
+        assert!(disk.read_page(1)? == b);
+
+        // Only a prefix of a torn write makes it to disk
+        disk.tear_write(0, 100);
+        assert!(disk.write_page(0, &b).is_err());
+        disk.crash();
+        let have = disk.read_page(0)?;
+        assert!(have[..100] == b[..100]);
+        assert!(have[100..] == a[100..]);
+
+        // Corruption is applied to the stored copy
+        disk.sync()?;
+        disk.corrupt(1, 10)?;
+        let have = disk.inner().read_page(1)?;
+        assert!(have[10] == !2);
+        assert!(have[..10] == b[..10] && have[11..] == b[11..]);
+
+        assert!(disk.reads() == 6);
+        assert!(disk.writes() == 5);
+
+        Ok(())
+    }
+}
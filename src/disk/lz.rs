@@ -0,0 +1,207 @@
+//! LZ4 style block compression.
+//!
+//! The compressed block is a series of sequences:
+//!
+//! | Token (1) | Literal Len (0-n) | Literals | Offset (2) | Match Len (0-n) |
+//!
+//! The high nibble of the token is the literal length and the low nibble is the match length
+//! minus `MIN_MATCH`. A nibble of 15 means the length continues in the following bytes, each
+//! adding up to 255 until one is less than 255. The last sequence only has literals.
+
+const MIN_MATCH: usize = 4;
+const HASH_LOG: u32 = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+#[inline]
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+#[inline]
+fn read_u32(src: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes(src[i..i + 4].try_into().unwrap())
+}
+
+fn write_len(dst: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        dst.push(255);
+        len -= 255;
+    }
+    dst.push(len as u8);
+}
+
+fn write_sequence(dst: &mut Vec<u8>, literals: &[u8], m: Option<(u16, usize)>) {
+    let lit_len = literals.len();
+    let match_len = m.map(|(_, len)| len - MIN_MATCH).unwrap_or(0);
+
+    let token = (lit_len.min(15) << 4) as u8 | match_len.min(15) as u8;
+    dst.push(token);
+    if lit_len >= 15 {
+        write_len(dst, lit_len - 15);
+    }
+    dst.extend_from_slice(literals);
+
+    if let Some((offset, _)) = m {
+        dst.extend_from_slice(&offset.to_le_bytes());
+        if match_len >= 15 {
+            write_len(dst, match_len - 15);
+        }
+    }
+}
+
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut dst = Vec::with_capacity(src.len() / 2);
+    // Position + 1 of the last occurrence of a hashed sequence, 0 is empty
+    let mut table = vec![0_usize; 1 << HASH_LOG];
+
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= src.len() {
+        let seq = read_u32(src, i);
+        let h = hash(seq);
+        let candidate = table[h];
+        table[h] = i + 1;
+
+        if candidate > 0 {
+            let c = candidate - 1;
+            if i - c <= MAX_OFFSET && read_u32(src, c) == seq {
+                let mut len = MIN_MATCH;
+                while i + len < src.len() && src[c + len] == src[i + len] {
+                    len += 1;
+                }
+
+                write_sequence(&mut dst, &src[anchor..i], Some(((i - c) as u16, len)));
+                i += len;
+                anchor = i;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    write_sequence(&mut dst, &src[anchor..], None);
+
+    dst
+}
+
+fn read_len(src: &[u8], pos: &mut usize, mut len: usize) -> Option<usize> {
+    if len != 15 {
+        return Some(len);
+    }
+
+    loop {
+        let b = *src.get(*pos)?;
+        *pos += 1;
+        len += b as usize;
+        if b != 255 {
+            return Some(len);
+        }
+    }
+}
+
+/// Decompresses `src` into `dst`, returning the number of bytes written. Returns `None` if `src`
+/// is malformed or doesn't fit in `dst`.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut pos = 0;
+    let mut out: usize = 0;
+
+    loop {
+        let token = *src.get(pos)?;
+        pos += 1;
+
+        let lit_len = read_len(src, &mut pos, (token >> 4) as usize)?;
+        let literals = src.get(pos..pos.checked_add(lit_len)?)?;
+        dst.get_mut(out..out.checked_add(lit_len)?)?
+            .copy_from_slice(literals);
+        pos += lit_len;
+        out += lit_len;
+
+        if pos == src.len() {
+            return Some(out);
+        }
+
+        let offset = u16::from_le_bytes(src.get(pos..pos + 2)?.try_into().unwrap()) as usize;
+        pos += 2;
+        if offset == 0 || offset > out {
+            return None;
+        }
+
+        let match_len = read_len(src, &mut pos, (token & 0xf) as usize)? + MIN_MATCH;
+        if out + match_len > dst.len() {
+            return None;
+        }
+
+        // The match can overlap with the bytes being written
+        let from = out - offset;
+        for j in 0..match_len {
+            dst[out + j] = dst[from + j];
+        }
+        out += match_len;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{Rng, RngCore};
+
+    use super::{compress, decompress};
+
+    #[test]
+    fn test_round_trip() {
+        let mut random = vec![0; 4096];
+        rand::thread_rng().fill_bytes(&mut random);
+
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(100);
+
+        let mut ints = Vec::new();
+        for i in 0..1024_i32 {
+            ints.extend_from_slice(&(i % 10).to_be_bytes());
+        }
+
+        let tcs: [(&str, Vec<u8>); 7] = [
+            ("empty", vec![]),
+            ("short", vec![1, 2, 3]),
+            ("zeroes", vec![0; 4096]),
+            ("random", random),
+            ("text", text.into_bytes()),
+            ("ints", ints),
+            (
+                "long literal run then match",
+                [(0..=255).collect::<Vec<u8>>(), vec![7; 300]].concat(),
+            ),
+        ];
+
+        for (name, want) in tcs {
+            let compressed = compress(&want);
+            let mut have = vec![0; want.len()];
+            let len = decompress(&compressed, &mut have);
+
+            assert!(len == Some(want.len()), "{name}: Want len: {}, Have: {len:?}", want.len());
+            assert!(want == have, "{name}: data did not round trip");
+        }
+
+        let zeroes = compress(&[0; 4096]);
+        assert!(zeroes.len() < 32, "zeroes compressed to {} bytes", zeroes.len());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut rng = rand::thread_rng();
+        let mut dst = vec![0; 4096];
+
+        // Garbage shouldn't panic
+        for _ in 0..1000 {
+            let len = rng.gen_range(0..64);
+            let src = (0..len).map(|_| rng.gen()).collect::<Vec<u8>>();
+            let _ = decompress(&src, &mut dst);
+        }
+
+        // Doesn't fit
+        let compressed = compress(&[1; 4096]);
+        assert!(decompress(&compressed, &mut dst[..4095]).is_none());
+
+        // Offset before the start of the output
+        assert!(decompress(&[0x10, 1, 5, 0], &mut dst).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    io,
+    path::Path,
+    sync::{RwLock, RwLockReadGuard},
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    disk::{Disk, FileSystem, SyncMode},
+    error::LockExt,
+    page::{PageBuf, PageId, SegmentId, PAGE_SIZE},
+};
+
+/// Disk that serves reads from a read only mapping of the file.
+///
+/// Writes go through `FileSystem` so they only reach the file when the page cache writes them back,
+/// the mapping can never write pages back on its own. The mapping is extended lazily when a page
+/// past the end of it is read.
+pub struct Mapped {
+    fs: FileSystem,
+    map: RwLock<Option<Mmap>>,
+}
+
+impl Disk for Mapped {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        if page_id < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid page id {page_id}"),
+            ));
+        }
+
+        let start = page_id as usize * PAGE_SIZE;
+        let end = start + PAGE_SIZE;
+
+        let map = self.map();
+        let map = match map.as_ref() {
+            Some(m) if m.len() >= end => map,
+            _ => {
+                drop(map);
+                self.remap()?;
+                self.map()
+            }
+        };
+
+        let mut ret = [0; PAGE_SIZE];
+        if let Some(m) = map.as_ref() {
+            // Past the end of the file reads back as zeroes, same as `FileSystem`
+            if start < m.len() {
+                let end = end.min(m.len());
+                ret[..end - start].copy_from_slice(&m[start..end]);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        // The mapping is shared with the OS page cache so it sees the write straight away
+        self.fs.write_page(page_id, data)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.fs.sync()
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        self.fs.segment_len(segment)
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        // The file keeps its size so the mapping stays valid, punched pages read back as zeroes
+        self.fs.drop_segment(segment)
+    }
+}
+
+impl Mapped {
+    pub fn new(file: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_mode(file, SyncMode::default())
+    }
+
+    /// `SyncMode::Direct` isn't supported, direct writes can't be kept coherent with the mapping.
+    pub fn with_mode(file: impl AsRef<Path>, mode: SyncMode) -> io::Result<Self> {
+        if mode == SyncMode::Direct {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "mapped disk does not support direct io",
+            ));
+        }
+
+        let ret = Self {
+            fs: FileSystem::with_mode(file, mode)?,
+            map: RwLock::new(None),
+        };
+        ret.remap()?;
+
+        Ok(ret)
+    }
+
+    fn map(&self) -> RwLockReadGuard<'_, Option<Mmap>> {
+        self.map.read().unpoisoned()
+    }
+
+    /// Maps the whole file again if it has grown since it was last mapped.
+    fn remap(&self) -> io::Result<()> {
+        let mut map = self.map.write().unpoisoned();
+        let len = self.fs.file.metadata()?.len() as usize;
+        if len == 0 || map.as_ref().is_some_and(|m| m.len() >= len) {
+            return Ok(());
+        }
+
+        // SAFETY: the file is only modified through `write_page`, which never truncates it. Another
+        // process truncating the file would cause reads of the truncated range to fault.
+        *map = Some(unsafe { Mmap::map(&self.fs.file)? });
+
+        Ok(())
+    }
+
+    /// Size of the mapped region in bytes.
+    pub fn mapped_len(&self) -> usize {
+        self.map().as_ref().map_or(0, |m| m.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        disk::{mapped::Mapped, Disk, FileSystem, SyncMode},
+        page::{PAGE_HEADER_SIZE, PAGE_SIZE},
+        page_cache::PageCache,
+        replacer::LRU,
+        test::CleanUp,
+        writep,
+    };
+
+    #[test]
+    fn test_mapped() -> std::io::Result<()> {
+        const FILE: &str = "test_mapped.db";
+        let _cleanup = CleanUp::file(FILE);
+
+        let disk = Mapped::new(FILE)?;
+        assert!(disk.mapped_len() == 0);
+        assert!(disk.read_page(0)? == [0; PAGE_SIZE]);
+
+        disk.write_page(0, &[1; PAGE_SIZE])?;
+        assert!(disk.read_page(0)? == [1; PAGE_SIZE]);
+        assert!(disk.mapped_len() == PAGE_SIZE);
+
+        // Overwrites are visible through the existing mapping
+        disk.write_page(0, &[2; PAGE_SIZE])?;
+        assert!(disk.read_page(0)? == [2; PAGE_SIZE]);
+
+        // Growing the file remaps
+        disk.write_page(9, &[3; PAGE_SIZE])?;
+        assert!(disk.read_page(9)? == [3; PAGE_SIZE]);
+        assert!(disk.read_page(5)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(100)? == [0; PAGE_SIZE]);
+        assert!(disk.mapped_len() == PAGE_SIZE * 10);
+
+        // Reads agree with `FileSystem`
+        let fs = FileSystem::new(FILE)?;
+        for page_id in 0..12 {
+            assert!(fs.read_page(page_id)? == disk.read_page(page_id)?);
+        }
+
+        assert!(Mapped::with_mode(FILE, SyncMode::Direct).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mapped_page_cache() -> crate::Result<()> {
+        const FILE: &str = "test_mapped_page_cache.db";
+        const K: usize = 2;
+        let _cleanup = CleanUp::file(FILE);
+
+        let disk = Mapped::with_mode(FILE, SyncMode::Fsync).expect("could not open file");
+        let pc = PageCache::new(disk, LRU::new(K), 0);
+        for _ in 0..10 {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+        pc.flush_all_pages()?;
+
+        let disk = Mapped::new(FILE).expect("could not open file");
+        let pc = PageCache::new(disk, LRU::new(K), 10);
+        for id in 0..10 {
+            let page = pc.fetch_page(id)?;
+            let r = page.read();
+            assert!(r.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == id.to_be_bytes());
+        }
+
+        Ok(())
+    }
+}
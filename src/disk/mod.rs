@@ -0,0 +1,397 @@
+pub mod compressed;
+pub mod encrypted;
+pub mod fault;
+mod lz;
+pub mod mapped;
+pub mod segmented;
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    path::Path,
+    sync::RwLock,
+};
+
+use nix::{
+    errno::Errno,
+    fcntl::{self, FallocateFlags},
+    sys::uio,
+    unistd::{self, Whence},
+};
+use std::fs::{File, OpenOptions};
+
+use crate::{
+    error::LockExt,
+    page::{self, PageBuf, PageId, SegmentId, PAGE_SIZE, SEGMENT_PAGES},
+};
+
+pub trait Disk {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf>;
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()>;
+
+    /// Barrier for all previous writes. Once this returns `Ok` the writes are durable, subject to
+    /// the guarantees of the implementation.
+    fn sync(&self) -> io::Result<()>;
+
+    /// Number of pages in `segment` up to and including the highest one written. New pages in the
+    /// segment are allocated after these.
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32>;
+
+    /// Deletes every page in `segment`, they read back as zeroes afterwards.
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("segment {segment} can't be dropped"),
+        ))
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum SyncMode {
+    /// Writes go through the OS page cache and `sync` is a no-op
+    #[default]
+    Buffered,
+    /// Writes go through the OS page cache and `sync` issues an `fdatasync`
+    Fsync,
+    /// Writes bypass the OS page cache with `O_DIRECT` and `sync` issues an `fdatasync` to flush
+    /// the device cache
+    Direct,
+}
+
+/// `O_DIRECT` requires the buffer to be aligned to the logical block size of the device
+#[repr(C, align(4096))]
+struct AlignedPageBuf(PageBuf);
+
+pub struct FileSystem {
+    file: File,
+    mode: SyncMode,
+}
+
+impl Disk for FileSystem {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        let offset = PAGE_SIZE as i64 * i64::from(page_id);
+        let fd = self.file.as_raw_fd();
+        let mut buf = AlignedPageBuf([0; PAGE_SIZE]);
+        uio::pread(fd, &mut buf.0, offset)?;
+
+        Ok(buf.0)
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        let offset = PAGE_SIZE as i64 * i64::from(page_id);
+        let fd = self.file.as_raw_fd();
+
+        match self.mode {
+            SyncMode::Direct => {
+                let buf = AlignedPageBuf(*data);
+                uio::pwrite(fd, &buf.0, offset)?;
+            }
+            SyncMode::Buffered | SyncMode::Fsync => {
+                uio::pwrite(fd, data, offset)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        match self.mode {
+            SyncMode::Buffered => Ok(()),
+            SyncMode::Fsync | SyncMode::Direct => {
+                unistd::fdatasync(self.file.as_raw_fd())?;
+                Ok(())
+            }
+        }
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        segment_len(&self.file, PAGE_SIZE, segment)
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        let start = i64::from(segment) * i64::from(SEGMENT_PAGES) * PAGE_SIZE as i64;
+        punch_hole(&self.file, start, i64::from(SEGMENT_PAGES) * PAGE_SIZE as i64)
+    }
+}
+
+impl FileSystem {
+    pub fn new(file: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_mode(file, SyncMode::default())
+    }
+
+    pub fn with_mode(file: impl AsRef<Path>, mode: SyncMode) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true);
+
+        if mode == SyncMode::Direct {
+            options.custom_flags(nix::libc::O_DIRECT);
+        }
+
+        let file = options.open(file)?;
+
+        Ok(Self { file, mode })
+    }
+
+    pub fn mode(&self) -> SyncMode {
+        self.mode
+    }
+}
+
+/// In memory disk that grows as pages are written. Page ids can be sparse, pages that were never
+/// written read back as zeroes.
+#[derive(Default)]
+pub struct Memory {
+    pages: RwLock<HashMap<PageId, Box<PageBuf>>>,
+}
+
+impl Disk for Memory {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        check_page_id(page_id)?;
+
+        match self.pages.read().unpoisoned().get(&page_id) {
+            Some(page) => Ok(**page),
+            None => Ok([0; PAGE_SIZE]),
+        }
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        check_page_id(page_id)?;
+
+        match self.pages.write().unpoisoned().entry(page_id) {
+            Entry::Occupied(mut entry) => entry.get_mut().copy_from_slice(data),
+            Entry::Vacant(entry) => {
+                entry.insert(Box::new(*data));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        let pages = self.pages.read().unpoisoned();
+        let len = pages
+            .keys()
+            .filter(|id| page::segment_of(**id) == segment)
+            .map(|id| page::segment_offset(*id) + 1)
+            .max();
+
+        Ok(len.unwrap_or(0))
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        self.pages
+            .write()
+            .unpoisoned()
+            .retain(|id, _| page::segment_of(*id) != segment);
+
+        Ok(())
+    }
+}
+
+impl Clone for Memory {
+    fn clone(&self) -> Self {
+        self.snapshot()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy of the current contents, which can be used and written to independently.
+    pub fn snapshot(&self) -> Self {
+        let pages = self.pages.read().unpoisoned().clone();
+
+        Self {
+            pages: RwLock::new(pages),
+        }
+    }
+
+    /// Size in bytes as if the pages were laid out in a file, up to and including the highest
+    /// written page.
+    pub fn size(&self) -> usize {
+        match self.pages.read().unpoisoned().keys().max() {
+            Some(max) => (*max as usize + 1) * PAGE_SIZE,
+            None => 0,
+        }
+    }
+
+    /// Number of pages that have been written.
+    pub fn len(&self) -> usize {
+        self.pages.read().unpoisoned().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn check_page_id(page_id: PageId) -> io::Result<()> {
+    if page_id < 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid page id {page_id}"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Number of pages of `segment` in a file with every page laid out by page id in `page_size`
+/// bytes. Segments that were never written are holes in the file, so this finds the end of the
+/// last data in the segment's range.
+fn segment_len(file: &File, page_size: usize, segment: SegmentId) -> io::Result<u32> {
+    let fd = file.as_raw_fd();
+    let size = (SEGMENT_PAGES as usize * page_size) as i64;
+    let start = i64::from(segment) * size;
+    let end = (start + size).min(file.metadata()?.len() as i64);
+
+    let mut pos = start;
+    let mut data_end = start;
+    while pos < end {
+        let data = match unistd::lseek(fd, pos, Whence::SeekData) {
+            Ok(data) => data,
+            // No data after `pos`
+            Err(Errno::ENXIO) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if data >= end {
+            break;
+        }
+
+        let hole = unistd::lseek(fd, data, Whence::SeekHole)?;
+        data_end = hole.min(end);
+        pos = hole;
+    }
+
+    Ok(((data_end - start) as usize).div_ceil(page_size) as u32)
+}
+
+/// Deallocates `len` bytes of `file` from `offset`, they read back as zeroes.
+fn punch_hole(file: &File, offset: i64, len: i64) -> io::Result<()> {
+    fcntl::fallocate(
+        file.as_raw_fd(),
+        FallocateFlags::FALLOC_FL_PUNCH_HOLE | FallocateFlags::FALLOC_FL_KEEP_SIZE,
+        offset,
+        len,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        disk::{Disk, FileSystem, Memory, SyncMode},
+        page::{segment_page_id, PAGE_SIZE},
+        test::CleanUp,
+    };
+
+    #[test]
+    fn test_file_system_modes() -> std::io::Result<()> {
+        let tcs = [
+            ("test_fs_buffered.db", SyncMode::Buffered),
+            ("test_fs_fsync.db", SyncMode::Fsync),
+            ("test_fs_direct.db", SyncMode::Direct),
+        ];
+
+        for (file, mode) in tcs {
+            let disk = match FileSystem::with_mode(file, mode) {
+                Ok(d) => d,
+                Err(e)
+                    if mode == SyncMode::Direct && e.raw_os_error() == Some(nix::libc::EINVAL) =>
+                {
+                    // Some filesystems (e.g. tmpfs) don't support O_DIRECT
+                    eprintln!("WARN: skipping {mode:?}, O_DIRECT is not supported");
+                    let _ = std::fs::remove_file(file);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let _cleanup = CleanUp::file(file);
+
+            let a = std::array::from_fn::<u8, PAGE_SIZE, _>(|i| i as u8);
+            let b = std::array::from_fn::<u8, PAGE_SIZE, _>(|i| (i * 3) as u8);
+            disk.write_page(0, &a)?;
+            disk.write_page(2, &b)?;
+            disk.sync()?;
+
+            assert!(disk.read_page(0)? == a, "{mode:?}: page 0 did not read back");
+            assert!(disk.read_page(1)? == [0; PAGE_SIZE], "{mode:?}: page 1 should be empty");
+            assert!(disk.read_page(2)? == b, "{mode:?}: page 2 did not read back");
+
+            // Reopen and make sure the pages were persisted
+            drop(disk);
+            let disk = FileSystem::with_mode(file, mode)?;
+            assert!(disk.read_page(0)? == a, "{mode:?}: page 0 did not persist");
+            assert!(disk.read_page(2)? == b, "{mode:?}: page 2 did not persist");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory() -> std::io::Result<()> {
+        let disk = Memory::new();
+        assert!(disk.is_empty());
+        assert!(disk.size() == 0);
+
+        let a = [1; PAGE_SIZE];
+        let b = [2; PAGE_SIZE];
+
+        // Sparse page ids
+        disk.write_page(0, &a)?;
+        disk.write_page(100_000, &b)?;
+        assert!(disk.len() == 2);
+        assert!(disk.size() == 100_001 * PAGE_SIZE);
+        assert!(disk.read_page(0)? == a);
+        assert!(disk.read_page(50)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(100_000)? == b);
+
+        assert!(disk.read_page(-1).is_err());
+        assert!(disk.write_page(-1, &a).is_err());
+
+        // Snapshots are independent of the original
+        let snapshot = disk.snapshot();
+        disk.write_page(0, &b)?;
+        snapshot.write_page(1, &b)?;
+        assert!(snapshot.read_page(0)? == a);
+        assert!(disk.read_page(0)? == b);
+        assert!(disk.read_page(1)? == [0; PAGE_SIZE]);
+        assert!(snapshot.len() == 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_segment() -> crate::Result<()> {
+        const FILE: &str = "test_drop_segment.db";
+        let _cleanup = CleanUp::file(FILE);
+
+        let fs = FileSystem::new(FILE)?;
+        let memory = Memory::new();
+        let disks: [&dyn Disk; 2] = [&fs, &memory];
+        for disk in disks {
+            disk.write_page(segment_page_id(0, 5)?, &[1; PAGE_SIZE])?;
+            disk.write_page(segment_page_id(1, 0)?, &[2; PAGE_SIZE])?;
+            disk.write_page(segment_page_id(1, 2)?, &[3; PAGE_SIZE])?;
+
+            assert!(disk.segment_len(0)? == 6);
+            assert!(disk.segment_len(1)? == 3);
+            assert!(disk.segment_len(2)? == 0);
+
+            disk.drop_segment(1)?;
+            assert!(disk.read_page(segment_page_id(1, 0)?)? == [0; PAGE_SIZE]);
+            assert!(disk.read_page(segment_page_id(1, 2)?)? == [0; PAGE_SIZE]);
+            assert!(disk.read_page(segment_page_id(0, 5)?)? == [1; PAGE_SIZE]);
+        }
+
+        Ok(())
+    }
+}
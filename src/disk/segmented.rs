@@ -0,0 +1,398 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use nix::{sys::uio, unistd};
+
+use crate::{
+    disk::Disk,
+    error::LockExt,
+    page::{self, PageBuf, PageId, SegmentId, PAGE_SIZE},
+};
+
+/*
+    Directory:
+    segments        Segment map
+    <segment>.<n>   The nth file of a segment, each holding up to `file_pages` pages
+
+    Segment map:
+    file_pages <n>
+    segment <segment> <tablespace>
+
+    Segments are stored in the directory unless the map puts them in another tablespace directory,
+    e.g. on a different disk.
+*/
+
+const SEGMENT_MAP: &str = "segments";
+
+/// 1 GiB files by default
+pub const DEFAULT_FILE_PAGES: u32 = 1 << 18;
+
+#[derive(Default)]
+struct State {
+    /// Segments stored outside of the directory
+    tablespaces: HashMap<SegmentId, PathBuf>,
+    files: HashMap<(SegmentId, u32), File>,
+    /// Files written to since the last sync
+    unsynced: HashSet<(SegmentId, u32)>,
+    /// Directories files were created in since the last sync
+    new_dirs: HashSet<PathBuf>,
+}
+
+/// Disk that stores each segment in its own files in a directory, starting a new file every
+/// `file_pages` pages.
+pub struct Segmented {
+    dir: PathBuf,
+    file_pages: u32,
+    state: RwLock<State>,
+}
+
+impl Disk for Segmented {
+    fn read_page(&self, page_id: PageId) -> io::Result<PageBuf> {
+        let (key, offset) = self.locate(page_id)?;
+        let mut ret = [0; PAGE_SIZE];
+
+        let state = self.state();
+        if let Some(file) = state.files.get(&key) {
+            uio::pread(file.as_raw_fd(), &mut ret, offset)?;
+            return Ok(ret);
+        }
+        drop(state);
+
+        let mut state = self.state_mut();
+        let path = self.path(&state, key);
+        let file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            // Nothing has been written to this part of the segment
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ret),
+            Err(e) => return Err(e),
+        };
+        uio::pread(file.as_raw_fd(), &mut ret, offset)?;
+        state.files.insert(key, file);
+
+        Ok(ret)
+    }
+
+    fn write_page(&self, page_id: PageId, data: &PageBuf) -> io::Result<()> {
+        let (key, offset) = self.locate(page_id)?;
+
+        let state = self.state();
+        if let Some(file) = state.files.get(&key) {
+            if state.unsynced.contains(&key) {
+                uio::pwrite(file.as_raw_fd(), data, offset)?;
+                return Ok(());
+            }
+        }
+        drop(state);
+
+        let mut state = self.state_mut();
+        if !state.files.contains_key(&key) {
+            let path = self.path(&state, key);
+            let dir = path
+                .parent()
+                .expect("segment files are in a directory")
+                .to_path_buf();
+            let created = !path.exists();
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            if created {
+                state.new_dirs.insert(dir);
+            }
+            state.files.insert(key, file);
+        }
+
+        uio::pwrite(state.files[&key].as_raw_fd(), data, offset)?;
+        state.unsynced.insert(key);
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let mut state = self.state_mut();
+        for key in &state.unsynced {
+            unistd::fdatasync(state.files[key].as_raw_fd())?;
+        }
+        state.unsynced.clear();
+
+        // New files aren't durable until their directory entries are
+        for dir in &state.new_dirs {
+            File::open(dir)?.sync_all()?;
+        }
+        state.new_dirs.clear();
+
+        Ok(())
+    }
+
+    fn segment_len(&self, segment: SegmentId) -> io::Result<u32> {
+        let state = self.state();
+        let files = self.segment_files(&state, segment)?;
+        let Some((n, path)) = files.into_iter().max_by_key(|(n, _)| *n) else {
+            return Ok(0);
+        };
+
+        let pages = fs::metadata(path)?.len().div_ceil(PAGE_SIZE as u64) as u32;
+        Ok(n * self.file_pages + pages)
+    }
+
+    fn drop_segment(&self, segment: SegmentId) -> io::Result<()> {
+        let mut state = self.state_mut();
+        for (n, path) in self.segment_files(&state, segment)? {
+            state.files.remove(&(segment, n));
+            state.unsynced.remove(&(segment, n));
+            fs::remove_file(&path)?;
+            state.new_dirs.insert(
+                path.parent()
+                    .expect("segment files are in a directory")
+                    .to_path_buf(),
+            );
+        }
+
+        if state.tablespaces.remove(&segment).is_some() {
+            self.write_map(&state)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Segmented {
+    pub fn new(dir: impl AsRef<Path>) -> io::Result<Self> {
+        Self::with_file_pages(dir, DEFAULT_FILE_PAGES)
+    }
+
+    /// Opens the directory, creating it if needed. An existing directory must have been created
+    /// with the same `file_pages`.
+    pub fn with_file_pages(dir: impl AsRef<Path>, file_pages: u32) -> io::Result<Self> {
+        if file_pages == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "segment files must hold at least one page",
+            ));
+        }
+
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut state = State::default();
+        let ret = match fs::read_to_string(dir.join(SEGMENT_MAP)) {
+            Ok(map) => {
+                let (have, tablespaces) = parse_map(&map)?;
+                if have != file_pages {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("segment files in {dir:?} hold {have} pages, not {file_pages}"),
+                    ));
+                }
+                state.tablespaces = tablespaces;
+
+                Self {
+                    dir,
+                    file_pages,
+                    state: RwLock::new(state),
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let ret = Self {
+                    dir,
+                    file_pages,
+                    state: RwLock::new(state),
+                };
+                ret.write_map(&ret.state())?;
+
+                ret
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(ret)
+    }
+
+    /// Stores `segment` in the `dir` tablespace. The segment must not have any pages yet.
+    pub fn set_tablespace(&self, segment: SegmentId, dir: impl AsRef<Path>) -> io::Result<()> {
+        if self.segment_len(segment)? > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("segment {segment} already has pages"),
+            ));
+        }
+
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut state = self.state_mut();
+        state.tablespaces.insert(segment, dir);
+        self.write_map(&state)
+    }
+
+    /// Directory `segment` is stored in.
+    pub fn tablespace(&self, segment: SegmentId) -> PathBuf {
+        self.tablespace_in(&self.state(), segment).to_path_buf()
+    }
+
+    fn state(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unpoisoned()
+    }
+
+    fn state_mut(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unpoisoned()
+    }
+
+    /// File and offset of a page, files are keyed by segment and file number.
+    fn locate(&self, page_id: PageId) -> io::Result<((SegmentId, u32), i64)> {
+        if page_id < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid page id {page_id}"),
+            ));
+        }
+
+        let segment = page::segment_of(page_id);
+        let n = page::segment_offset(page_id);
+        let offset = i64::from(n % self.file_pages) * PAGE_SIZE as i64;
+
+        Ok(((segment, n / self.file_pages), offset))
+    }
+
+    fn tablespace_in<'a>(&'a self, state: &'a State, segment: SegmentId) -> &'a Path {
+        state.tablespaces.get(&segment).unwrap_or(&self.dir)
+    }
+
+    fn path(&self, state: &State, (segment, n): (SegmentId, u32)) -> PathBuf {
+        self.tablespace_in(state, segment)
+            .join(format!("{segment}.{n}"))
+    }
+
+    /// Every file of `segment` with its file number.
+    fn segment_files(&self, state: &State, segment: SegmentId) -> io::Result<Vec<(u32, PathBuf)>> {
+        let prefix = format!("{segment}.");
+        let mut ret = Vec::new();
+        for entry in fs::read_dir(self.tablespace_in(state, segment))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let n = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|n| n.parse().ok());
+            if let Some(n) = n {
+                ret.push((n, entry.path()));
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn write_map(&self, state: &State) -> io::Result<()> {
+        let mut map = format!("file_pages {}\n", self.file_pages);
+        for (segment, dir) in &state.tablespaces {
+            map += &format!("segment {segment} {}\n", dir.display());
+        }
+
+        // Write a new copy and swap it in so a crash can't leave a partial map
+        let tmp = self.dir.join(format!("{SEGMENT_MAP}.tmp"));
+        fs::write(&tmp, map)?;
+        File::open(&tmp)?.sync_all()?;
+        fs::rename(tmp, self.dir.join(SEGMENT_MAP))?;
+        File::open(&self.dir)?.sync_all()
+    }
+}
+
+fn parse_map(map: &str) -> io::Result<(u32, HashMap<SegmentId, PathBuf>)> {
+    let invalid = |line: &str| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("invalid segment map line {line:?}"))
+    };
+
+    let mut file_pages = None;
+    let mut tablespaces = HashMap::new();
+    for line in map.lines().filter(|l| !l.is_empty()) {
+        let mut parts = line.splitn(3, ' ');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("file_pages"), Some(n), None) => {
+                file_pages = Some(n.parse().map_err(|_| invalid(line))?);
+            }
+            (Some("segment"), Some(segment), Some(dir)) => {
+                let segment = segment.parse().map_err(|_| invalid(line))?;
+                tablespaces.insert(segment, PathBuf::from(dir));
+            }
+            _ => return Err(invalid(line)),
+        }
+    }
+
+    let file_pages = file_pages.ok_or_else(|| invalid("file_pages"))?;
+
+    Ok((file_pages, tablespaces))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::{
+        disk::{segmented::Segmented, Disk},
+        page::{segment_page_id, PAGE_SIZE},
+        test::CleanUp,
+    };
+
+    #[test]
+    fn test_segmented() -> crate::Result<()> {
+        const DIR: &str = "test_segmented";
+        const TABLESPACE: &str = "test_segmented_tablespace";
+        let _cleanup = CleanUp::dir(DIR);
+        let _cleanup_ts = CleanUp::dir(TABLESPACE);
+
+        let disk = Segmented::with_file_pages(DIR, 4)?;
+        disk.set_tablespace(2, TABLESPACE)?;
+
+        // Segment 1 rotates into a second file after 4 pages
+        for n in 0..6 {
+            disk.write_page(segment_page_id(1, n)?, &[n as u8 + 1; PAGE_SIZE])?;
+        }
+        disk.write_page(segment_page_id(2, 0)?, &[9; PAGE_SIZE])?;
+        disk.sync()?;
+
+        assert!(Path::new(DIR).join("1.0").exists());
+        assert!(Path::new(DIR).join("1.1").exists());
+        assert!(std::fs::metadata(Path::new(DIR).join("1.0"))?.len() == 4 * PAGE_SIZE as u64);
+        assert!(Path::new(TABLESPACE).join("2.0").exists());
+        assert!(!Path::new(DIR).join("2.0").exists());
+
+        assert!(disk.segment_len(0)? == 0);
+        assert!(disk.segment_len(1)? == 6);
+        assert!(disk.segment_len(2)? == 1);
+        assert!(disk.read_page(segment_page_id(0, 3)?)? == [0; PAGE_SIZE]);
+        assert!(disk.read_page(segment_page_id(1, 10)?)? == [0; PAGE_SIZE]);
+
+        // A segment can't be moved once it has pages
+        assert!(disk.set_tablespace(1, TABLESPACE).is_err());
+        assert!(Segmented::with_file_pages(DIR, 8).is_err());
+
+        // Reopen, the segment map keeps segment 2 in the tablespace
+        drop(disk);
+        let disk = Segmented::with_file_pages(DIR, 4)?;
+        assert!(disk.tablespace(2) == Path::new(TABLESPACE));
+        for n in 0..6 {
+            assert!(disk.read_page(segment_page_id(1, n)?)? == [n as u8 + 1; PAGE_SIZE]);
+        }
+        assert!(disk.read_page(segment_page_id(2, 0)?)? == [9; PAGE_SIZE]);
+
+        // Dropping a segment deletes its files
+        disk.drop_segment(1)?;
+        disk.drop_segment(2)?;
+        assert!(!Path::new(DIR).join("1.0").exists());
+        assert!(!Path::new(DIR).join("1.1").exists());
+        assert!(!Path::new(TABLESPACE).join("2.0").exists());
+        assert!(disk.segment_len(1)? == 0);
+        assert!(disk.read_page(segment_page_id(1, 0)?)? == [0; PAGE_SIZE]);
+        assert!(disk.tablespace(2) == Path::new(DIR));
+
+        Ok(())
+    }
+}
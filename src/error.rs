@@ -0,0 +1,95 @@
+use std::{
+    fmt, io,
+    sync::{LockResult, PoisonError},
+};
+
+use crate::page::{PageId, SegmentId};
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    Disk(io::ErrorKind),
+    /// Every frame in the page cache is pinned
+    OutOfMemory,
+    /// The page read from disk failed its checksum or doesn't hold what it should
+    Corrupted {
+        page_id: PageId,
+    },
+    /// Every page id in the segment has been allocated
+    SegmentFull {
+        segment: SegmentId,
+    },
+    /// Every segment is in use
+    OutOfSegments,
+    PageNotFound {
+        page_id: PageId,
+    },
+    /// The tuple doesn't fit in an empty table page
+    TupleTooLarge {
+        size: usize,
+    },
+    /// The key doesn't leave room for enough slots in a B+tree node
+    KeyTooLarge {
+        size: usize,
+    },
+    SchemaMismatch(String),
+    DuplicateKey,
+    /// A thread panicked while holding a lock, the data behind it may be half updated
+    LockPoisoned,
+    Unsupported(&'static str),
+    /// The page cache was closed
+    Closed,
+    /// Pins were still held when closing the page cache gave up waiting for them
+    Pinned {
+        pins: usize,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Disk(kind) => write!(f, "disk error: {kind}"),
+            Error::OutOfMemory => write!(f, "no free frames in the page cache"),
+            Error::Corrupted { page_id } => write!(f, "page {page_id} is corrupted"),
+            Error::SegmentFull { segment } => write!(f, "segment {segment} is full"),
+            Error::OutOfSegments => write!(f, "no free segments"),
+            Error::PageNotFound { page_id } => write!(f, "page {page_id} not found"),
+            Error::TupleTooLarge { size } => write!(f, "tuple of {size} bytes is too large"),
+            Error::KeyTooLarge { size } => write!(f, "key of {size} bytes is too large"),
+            Error::SchemaMismatch(reason) => write!(f, "schema mismatch: {reason}"),
+            Error::DuplicateKey => write!(f, "duplicate key"),
+            Error::LockPoisoned => write!(f, "lock poisoned"),
+            Error::Unsupported(what) => write!(f, "{what} is not supported"),
+            Error::Closed => write!(f, "page cache is closed"),
+            Error::Pinned { pins } => write!(f, "{pins} pins are still held"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Disk(e.kind())
+    }
+}
+
+pub(crate) trait LockExt<T> {
+    /// Returns `Error::LockPoisoned` if a thread panicked while holding the lock.
+    fn or_poisoned(self) -> Result<T>;
+
+    /// Takes the guard even if a thread panicked while holding the lock. Only for locks whose
+    /// critical sections can't panic partway through an update.
+    fn unpoisoned(self) -> T;
+}
+
+impl<T> LockExt<T> for LockResult<T> {
+    fn or_poisoned(self) -> Result<T> {
+        self.map_err(|_| Error::LockPoisoned)
+    }
+
+    fn unpoisoned(self) -> T {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
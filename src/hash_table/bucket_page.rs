@@ -2,7 +2,7 @@ use std::{mem::size_of, ops::Range};
 
 use crate::{
     bitmap::BitMap,
-    page::{PageBuf, PAGE_SIZE},
+    page::{Header, PageBuf, PageType, PAGE_HEADER_SIZE, PAGE_SIZE},
     pair::Pair,
     storable::Storable,
 };
@@ -10,8 +10,9 @@ use crate::{
 /// Number of bytes for the bitmaps
 pub const BIT_SIZE: usize = 512 / 8;
 
-const OCCUPIED: Range<usize> = 0..BIT_SIZE;
-const READABLE: Range<usize> = BIT_SIZE..BIT_SIZE + BIT_SIZE;
+const OCCUPIED: Range<usize> = PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + BIT_SIZE;
+const READABLE: Range<usize> = OCCUPIED.end..OCCUPIED.end + BIT_SIZE;
+const PAIRS_START: usize = READABLE.end;
 
 pub struct Bucket<K, V> {
     pub occupied: BitMap<BIT_SIZE>,
@@ -37,7 +38,7 @@ where
         let k_size = size_of::<K>();
         let v_size = size_of::<V>();
 
-        let mut pos = BIT_SIZE * 2;
+        let mut pos = PAIRS_START;
         for (i, pair) in pairs.iter_mut().enumerate() {
             if !occupied.check(i) {
                 pos += k_size + v_size;
@@ -72,10 +73,11 @@ where
     fn from(bucket: &Bucket<K, V>) -> Self {
         let mut ret: PageBuf = [0; PAGE_SIZE];
 
+        Header::from(&mut ret).init(PageType::HashBucket);
         ret[OCCUPIED].copy_from_slice(bucket.occupied.as_slice());
         ret[READABLE].copy_from_slice(bucket.occupied.as_slice());
 
-        let mut pos = BIT_SIZE * 2;
+        let mut pos = PAIRS_START;
         let p_size = size_of::<K>() + size_of::<V>();
         for pair in &bucket.pairs {
             if pos + p_size > PAGE_SIZE {
@@ -180,7 +182,7 @@ where
         let len = self.occupied.len();
         let s = size_of::<K>() + size_of::<V>();
 
-        len >= (PAGE_SIZE - 128) / s
+        len >= (PAGE_SIZE - PAIRS_START) / s
     }
 }
 
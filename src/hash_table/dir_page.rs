@@ -1,13 +1,13 @@
 use std::ops::Range;
 
-use crate::page::{PageBuf, PageId, PAGE_SIZE};
+use crate::page::{Header, PageBuf, PageId, PageType, PAGE_HEADER_SIZE, PAGE_SIZE};
 
 pub const PAGE_IDS_SIZE_U32: usize = 512;
 pub const PAGE_IDS_SIZE_U8: usize = 512 * 4;
 
-const GLOBAL_DEPTH: Range<usize> = 0..4;
-const LOCAL_DEPTHS: Range<usize> = 4..4 + PAGE_IDS_SIZE_U32;
-const PAGE_IDS: Range<usize> = PAGE_IDS_SIZE_U32..PAGE_IDS_SIZE_U32 + PAGE_IDS_SIZE_U8;
+const GLOBAL_DEPTH: Range<usize> = PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4;
+const LOCAL_DEPTHS: Range<usize> = GLOBAL_DEPTH.end..GLOBAL_DEPTH.end + PAGE_IDS_SIZE_U32;
+const PAGE_IDS: Range<usize> = LOCAL_DEPTHS.end..LOCAL_DEPTHS.end + PAGE_IDS_SIZE_U8;
 
 #[derive(Debug)]
 pub struct Directory {
@@ -40,6 +40,7 @@ impl From<&Directory> for PageBuf {
     fn from(dir: &Directory) -> Self {
         let mut ret: PageBuf = [0; PAGE_SIZE];
 
+        Header::from(&mut ret).init(PageType::HashDirectory);
         ret[GLOBAL_DEPTH].copy_from_slice(&dir.global_depth.to_be_bytes());
         ret[LOCAL_DEPTHS].copy_from_slice(&dir.local_depths);
         ret[PAGE_IDS].copy_from_slice(&dir.page_ids);
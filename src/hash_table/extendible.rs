@@ -8,8 +8,8 @@ use crate::{
     disk::{Disk, FileSystem},
     hash_table::bucket_page::Bucket,
     hash_table::dir_page::{self, Directory},
-    page::{PageBuf, PageId},
-    page_cache::SharedPageCache,
+    page::{PageBuf, PageId, PageType},
+    page_cache::{Pin, SharedPageCache},
     storable::Storable,
     writep,
 };
@@ -35,7 +35,7 @@ where
     }
 
     pub fn insert(&self, k: &K, v: &V) -> crate::Result<bool> {
-        let dir_page = self.pc.fetch_page(self.dir_page_id)?;
+        let dir_page = self.fetch_dir()?;
         let mut dir_page_w = dir_page.page.write();
         let mut dir = Directory::from(&dir_page_w.data);
 
@@ -43,12 +43,12 @@ where
         let bucket_page_id = dir.get(bucket_index);
         let bucket_page = match bucket_page_id {
             0 => {
-                let p = self.pc.new_page()?;
+                let p = self.new_bucket()?;
                 dir.insert(bucket_index, p.page.read().id);
                 writep!(dir_page_w, &PageBuf::from(&dir));
                 p
             }
-            _ => self.pc.fetch_page(bucket_page_id)?,
+            _ => self.fetch_bucket(bucket_page_id)?,
         };
 
         let mut bucket_page_w = bucket_page.page.write();
@@ -67,11 +67,11 @@ where
             // 2. Get the high bit of the old bucket (1 << local_depth)
             // 3. Reinsert into the new pages
             // 4. Update the page ids in the directory
-            let page0 = self.pc.new_page()?;
+            let page0 = self.new_bucket()?;
             let mut page0_w = page0.page.write();
             let mut bucket0 = Bucket::from(&page0_w.data);
 
-            let page1 = self.pc.new_page()?;
+            let page1 = self.new_bucket()?;
             let mut page1_w = page1.page.write();
             let mut bucket1 = Bucket::from(&page1_w.data);
 
@@ -102,7 +102,7 @@ where
     }
 
     pub fn remove(&self, k: &K, v: &V) -> crate::Result<bool> {
-        let dir_page = self.pc.fetch_page(self.dir_page_id)?;
+        let dir_page = self.fetch_dir()?;
         let dir_page_r = dir_page.page.read();
         let dir = Directory::from(&dir_page_r.data);
 
@@ -110,7 +110,7 @@ where
         let bucket_page_id = dir.get(bucket_index);
         let bucket_page = match bucket_page_id {
             0 => return Ok(false),
-            _ => self.pc.fetch_page(bucket_page_id)?,
+            _ => self.fetch_bucket(bucket_page_id)?,
         };
         let mut bucket_page_w = bucket_page.page.write();
         let mut bucket = Bucket::from(&bucket_page_w.data);
@@ -124,7 +124,7 @@ where
     }
 
     pub fn get(&self, k: &K) -> crate::Result<Vec<V>> {
-        let dir_page = self.pc.fetch_page(self.dir_page_id)?;
+        let dir_page = self.fetch_dir()?;
         let dir_page_r = dir_page.page.read();
         let dir = Directory::from(&dir_page_r.data);
 
@@ -132,7 +132,7 @@ where
         let bucket_page_id = dir.get(bucket_index);
         let bucket_page = match bucket_page_id {
             0 => return Ok(vec![]),
-            _ => self.pc.fetch_page(bucket_page_id)?,
+            _ => self.fetch_bucket(bucket_page_id)?,
         };
 
         let bucket_page_w = bucket_page.page.read();
@@ -142,13 +142,34 @@ where
     }
 
     pub fn get_num_buckets(&self) -> crate::Result<u32> {
-        let dir_page = self.pc.fetch_page(self.dir_page_id)?;
+        let dir_page = self.fetch_dir()?;
         let dir_page_r = dir_page.page.read();
         let dir = Directory::from(&dir_page_r.data);
 
         Ok(1 << dir.global_depth())
     }
 
+    fn fetch_dir(&self) -> crate::Result<Pin> {
+        let pin = self.pc.fetch_page(self.dir_page_id)?;
+        pin.set_type(PageType::HashDirectory);
+
+        Ok(pin)
+    }
+
+    fn fetch_bucket(&self, page_id: PageId) -> crate::Result<Pin> {
+        let pin = self.pc.fetch_page(page_id)?;
+        pin.set_type(PageType::HashBucket);
+
+        Ok(pin)
+    }
+
+    fn new_bucket(&self) -> crate::Result<Pin> {
+        let pin = self.pc.new_page()?;
+        pin.set_type(PageType::HashBucket);
+
+        Ok(pin)
+    }
+
     fn hash(k: &K) -> usize {
         let mut hasher = DefaultHasher::new();
         k.hash(&mut hasher);
@@ -170,7 +191,6 @@ mod test {
     use crate::{
         disk::Memory,
         hash_table::{bucket_page::BIT_SIZE, dir_page::Directory, extendible::ExtendibleHashTable},
-        page::PAGE_SIZE,
         page_cache::PageCache,
         replacer::LRU,
     };
@@ -193,10 +213,9 @@ mod test {
 
     #[test]
     fn test_extendible_hash_table() -> crate::Result<()> {
-        const MEMORY: usize = PAGE_SIZE * 4;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let replacer = LRU::new(K);
         let pm = PageCache::new(disk, replacer, 0);
         let _dir_page = pm.new_page();
@@ -238,10 +257,9 @@ mod test {
 
     #[test]
     fn test_split() {
-        const MEMORY: usize = PAGE_SIZE * 4;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let replacer = LRU::new(K);
         let pm = PageCache::new(disk, replacer, 0);
         let ht = ExtendibleHashTable::new(0, pm.clone());
@@ -250,7 +268,7 @@ mod test {
         assert!(ht.get_num_buckets().unwrap() == 1);
 
         // (i32, usize) = 12 bytes
-        // (4096 - 128) / 12 = 330
+        // (4096 - 132) / 12 = 330
         for (k, v) in (0..BIT_SIZE * 8).zip(0..BIT_SIZE * 8).take(330) {
             ht.insert(&k, &v).unwrap();
         }
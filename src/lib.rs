@@ -2,7 +2,9 @@ pub mod binder;
 pub mod bitmap;
 pub mod btree;
 pub mod catalog;
+pub mod checksum;
 pub mod disk;
+pub mod error;
 pub mod hash_table;
 pub mod page;
 pub mod page_cache;
@@ -11,7 +13,7 @@ pub mod replacer;
 pub mod storable;
 pub mod table;
 
-pub use page_cache::Result;
+pub use error::{Error, Result};
 
 #[cfg(test)]
 mod test {
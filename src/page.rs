@@ -1,4 +1,12 @@
-use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::{
+    ops::{Deref, DerefMut, Range},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use crate::{
+    checksum::crc32c,
+    error::{Error, LockExt},
+};
 
 #[macro_export]
 macro_rules! writep {
@@ -15,7 +23,87 @@ macro_rules! writep {
 
 pub const PAGE_SIZE: usize = 4 * 1024;
 
+// Common header at the start of every page, page formats are laid out after it
+// | Checksum (4) | Type (1) | Version (1) | Reserved (2) | LSN (8) |
+pub const PAGE_CHECKSUM: Range<usize> = 0..4;
+pub const PAGE_TYPE: usize = 4;
+pub const PAGE_VERSION: usize = 5;
+pub const PAGE_LSN: Range<usize> = 8..16;
+pub const PAGE_HEADER_SIZE: usize = 16;
+
+/// Version of the page formats written by this build. Pages that were never written are version 0.
+pub const PAGE_FORMAT_VERSION: u8 = 1;
+
+pub type Lsn = u64;
+
 pub type PageId = i32;
+
+// Page ids are split into a segment and the page number within it, so each segment is a contiguous
+// range of ids that can be stored and dropped on its own. Every segment, segment 0 included, holds
+// up to `SEGMENT_PAGES` pages (4 GiB)
+// | Segment (11) | Page (20) |
+pub type SegmentId = u16;
+pub const SEGMENT_BITS: u32 = 20;
+pub const SEGMENT_PAGES: u32 = 1 << SEGMENT_BITS;
+pub const MAX_SEGMENT: SegmentId = (i32::MAX >> SEGMENT_BITS) as SegmentId;
+
+pub fn segment_of(page_id: PageId) -> SegmentId {
+    (page_id >> SEGMENT_BITS) as SegmentId
+}
+
+/// Page number of `page_id` within its segment.
+pub fn segment_offset(page_id: PageId) -> u32 {
+    page_id as u32 & (SEGMENT_PAGES - 1)
+}
+
+/// Id of the `n`th page of `segment`, `SegmentFull` if the segment can't hold that many pages.
+pub fn segment_page_id(segment: SegmentId, n: u32) -> crate::Result<PageId> {
+    if segment > MAX_SEGMENT || n >= SEGMENT_PAGES {
+        return Err(Error::SegmentFull { segment });
+    }
+
+    Ok(((segment as i32) << SEGMENT_BITS) | n as i32)
+}
+
+/// What a page holds, stored in its header. Pages created since they were last loaded are tagged
+/// by their owner, see `Pin::set_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PageType {
+    #[default]
+    Unknown,
+    Table,
+    BTreeInternal,
+    BTreeLeaf,
+    HashDirectory,
+    HashBucket,
+}
+
+impl From<u8> for PageType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => PageType::Table,
+            2 => PageType::BTreeInternal,
+            3 => PageType::BTreeLeaf,
+            4 => PageType::HashDirectory,
+            5 => PageType::HashBucket,
+            _ => PageType::Unknown,
+        }
+    }
+}
+
+impl From<PageType> for u8 {
+    fn from(value: PageType) -> Self {
+        match value {
+            PageType::Unknown => 0,
+            PageType::Table => 1,
+            PageType::BTreeInternal => 2,
+            PageType::BTreeLeaf => 3,
+            PageType::HashDirectory => 4,
+            PageType::HashBucket => 5,
+        }
+    }
+}
+
 pub type PageBuf = [u8; PAGE_SIZE];
 pub type PageReadGuard<'a> = RwLockReadGuard<'a, PageInner>;
 pub type PageWriteGuard<'a> = RwLockWriteGuard<'a, PageInner>;
@@ -31,12 +119,34 @@ impl Default for Page {
 }
 
 impl Page {
+    /// The cache discards a poisoned page instead of writing it back, and reads it from disk
+    /// again the next time it's fetched, see `is_poisoned`.
     pub fn read(&self) -> PageReadGuard {
-        self.0.read().expect("todo")
+        self.0.read().unpoisoned()
     }
 
     pub fn write(&self) -> PageWriteGuard {
-        self.0.write().expect("todo")
+        self.0.write().unpoisoned()
+    }
+
+    /// Whether a thread panicked while holding the write lock, so the page may be half written.
+    pub fn is_poisoned(&self) -> bool {
+        self.0.is_poisoned()
+    }
+
+    /// Clears the poison once the page has been read from disk again.
+    pub fn clear_poison(&self) {
+        self.0.clear_poison();
+    }
+
+    /// Read lock on the page, or `None` if it's write locked.
+    pub fn try_read(&self) -> Option<PageReadGuard<'_>> {
+        self.0.try_read().ok()
+    }
+
+    /// Write lock on the page, or `None` if it's locked already.
+    pub fn try_write(&self) -> Option<PageWriteGuard<'_>> {
+        self.0.try_write().ok()
     }
 }
 
@@ -63,3 +173,52 @@ impl PageInner {
         self.data.fill(0);
     }
 }
+
+/// Zero-copy view of the common header of a page, over either `&PageBuf` or `&mut PageBuf`.
+pub struct Header<B>(B);
+
+impl<B> From<B> for Header<B> {
+    fn from(buf: B) -> Self {
+        Self(buf)
+    }
+}
+
+impl<B: Deref<Target = PageBuf>> Header<B> {
+    pub fn page_type(&self) -> PageType {
+        PageType::from(self.0[PAGE_TYPE])
+    }
+
+    pub fn version(&self) -> u8 {
+        self.0[PAGE_VERSION]
+    }
+
+    /// Log record of the last change to the page.
+    pub fn lsn(&self) -> Lsn {
+        Lsn::from_be_bytes(self.0[PAGE_LSN].try_into().unwrap())
+    }
+}
+
+impl<B: DerefMut<Target = PageBuf>> Header<B> {
+    /// Marks the page as holding `ty` in the current format.
+    pub fn init(&mut self, ty: PageType) {
+        self.0[PAGE_TYPE] = ty.into();
+        self.0[PAGE_VERSION] = PAGE_FORMAT_VERSION;
+    }
+
+    pub fn set_lsn(&mut self, lsn: Lsn) {
+        self.0[PAGE_LSN].copy_from_slice(&lsn.to_be_bytes());
+    }
+}
+
+/// Stores a checksum of the page, covering everything after the checksum field.
+pub fn write_checksum(data: &mut PageBuf) {
+    let checksum = crc32c(&data[PAGE_CHECKSUM.end..]);
+    data[PAGE_CHECKSUM].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// Pages that have never been written are all zeroes, these are considered valid.
+pub fn verify_checksum(data: &PageBuf) -> bool {
+    let checksum = u32::from_be_bytes(data[PAGE_CHECKSUM].try_into().unwrap());
+
+    checksum == crc32c(&data[PAGE_CHECKSUM.end..]) || data.iter().all(|b| *b == 0)
+}
@@ -0,0 +1,330 @@
+use std::{
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    disk::{Disk, FileSystem},
+    error::{Error, LockExt},
+    page_cache::{Result, SharedPageCache},
+    replacer::{LRUKReplacer, Replacer},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct FlusherConfig {
+    /// Share of the frames allowed to be dirty before pages are written back
+    pub dirty_ratio: f64,
+    /// How long to wait between rounds
+    pub interval: Duration,
+    /// Most pages written back in a round
+    pub batch: usize,
+}
+
+impl Default for FlusherConfig {
+    fn default() -> Self {
+        Self {
+            dirty_ratio: 0.25,
+            interval: Duration::from_millis(10),
+            batch: 16,
+        }
+    }
+}
+
+/// Background thread that writes dirty unpinned pages back ahead of eviction, so foreground
+/// fetches find clean victims. Writes aren't synced, durability still comes from `flush_page` and
+/// `flush_all_pages`. Errors don't stop the thread, see `take_error`, only closing the cache does.
+///
+/// Stopping the flusher, or dropping it, flushes every page.
+pub struct Flusher<D: Disk = FileSystem, R: Replacer = LRUKReplacer> {
+    pc: SharedPageCache<D, R>,
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+    /// Last error a round ran into
+    error: Arc<Mutex<Option<Error>>>,
+}
+
+impl<D, R> Flusher<D, R>
+where
+    D: Disk + Send + Sync + 'static,
+    R: Replacer + Send + 'static,
+{
+    pub fn start(pc: SharedPageCache<D, R>, config: FlusherConfig) -> Self {
+        let (stop, rx) = mpsc::channel();
+        let error = Arc::new(Mutex::new(None));
+        let handle = thread::spawn({
+            let pc = pc.clone();
+            let error = error.clone();
+            move || loop {
+                match rx.recv_timeout(config.interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // Stopped or the handle was dropped
+                    _ => return,
+                }
+
+                match pc.flush_dirty(config.dirty_ratio, config.batch) {
+                    Ok(_) => {}
+                    Err(Error::Closed) => return,
+                    Err(e) => {
+                        eprintln!("ERROR: flusher could not write pages back - {e:?}");
+                        *error.lock().unpoisoned() = Some(e);
+                    }
+                }
+            }
+        });
+
+        Self {
+            pc,
+            stop: Some(stop),
+            handle: Some(handle),
+            error,
+        }
+    }
+}
+
+impl<D: Disk, R: Replacer> Flusher<D, R> {
+    /// Takes the last error the thread ran into since it was last taken. The thread carries on
+    /// after errors, the pages it couldn't write are tried again in later rounds.
+    pub fn take_error(&self) -> Option<Error> {
+        self.error.lock().unpoisoned().take()
+    }
+
+    /// Stops the thread and flushes every page. Returns the flush's error, or else the last error
+    /// the thread ran into that wasn't taken.
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        let Some(handle) = self.handle.take() else {
+            return Ok(());
+        };
+
+        drop(self.stop.take());
+        handle.join().expect("flusher thread panicked");
+        self.pc.flush_all_pages()?;
+
+        match self.take_error() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<D: Disk, R: Replacer> Drop for Flusher<D, R> {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            eprintln!("ERROR: could not flush pages - {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering::Relaxed},
+            Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        disk::{fault::Faulty, Disk, Memory},
+        error::Error,
+        page::{PageBuf, PageId, SegmentId, PAGE_HEADER_SIZE},
+        page_cache::{
+            flusher::{Flusher, FlusherConfig},
+            PageCache,
+        },
+        replacer::LRU,
+        writep,
+    };
+
+    #[test]
+    fn test_flush_dirty() -> crate::Result<()> {
+        const CAPACITY: usize = 8;
+        let pc = PageCache::with_capacity(Faulty::new(Memory::new()), LRU::new(2), 0, CAPACITY);
+        let mut pins = Vec::new();
+        for _ in 0..CAPACITY {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+            drop(w);
+            pins.push(page);
+        }
+
+        // Pinned pages are left alone
+        assert!(pc.flush_dirty(0.0, CAPACITY)? == 0);
+        pins.truncate(2);
+
+        // Down to the ratio, a batch at a time
+        assert!(pc.flush_dirty(0.5, 1)? == 1);
+        assert!(pc.flush_dirty(0.5, CAPACITY)? == 3);
+        assert!(pc.flush_dirty(0.5, CAPACITY)? == 0);
+        assert!(pc.flush_dirty(0.0, CAPACITY)? == 2);
+        assert!(pc.disk().writes() == 6);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_dirty_unlocked() -> crate::Result<()> {
+        /// Holds writes up until the test lets them through
+        struct Gated {
+            inner: Memory,
+            gated: AtomicBool,
+            started: Barrier,
+            resume: Barrier,
+        }
+
+        impl Disk for Gated {
+            fn read_page(&self, page_id: PageId) -> std::io::Result<PageBuf> {
+                self.inner.read_page(page_id)
+            }
+
+            fn write_page(&self, page_id: PageId, data: &PageBuf) -> std::io::Result<()> {
+                if self.gated.load(Relaxed) {
+                    self.started.wait();
+                    self.resume.wait();
+                }
+                self.inner.write_page(page_id, data)
+            }
+
+            fn sync(&self) -> std::io::Result<()> {
+                self.inner.sync()
+            }
+
+            fn segment_len(&self, segment: SegmentId) -> std::io::Result<u32> {
+                self.inner.segment_len(segment)
+            }
+        }
+
+        let disk = Gated {
+            inner: Memory::new(),
+            gated: AtomicBool::new(true),
+            started: Barrier::new(2),
+            resume: Barrier::new(2),
+        };
+        let pc = PageCache::with_capacity(disk, LRU::new(2), 0, 4);
+        let id = {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &[1; 4]);
+            page.id
+        };
+
+        // The page can be fetched and changed while its copy is written
+        thread::scope(|s| -> crate::Result<()> {
+            let flush = s.spawn(|| pc.flush_dirty(0.0, 1));
+            pc.disk().started.wait();
+            {
+                let page = pc.fetch_page(id)?;
+                let mut w = page.write();
+                writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &[2; 4]);
+            }
+            pc.disk().resume.wait();
+            assert!(flush.join().expect("flush thread panicked")? == 1);
+
+            Ok(())
+        })?;
+
+        // Changed after it was copied, so it's still dirty
+        let data = pc.disk().inner.read_page(id)?;
+        assert!(data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == [1; 4]);
+        assert!(pc.fetch_page(id)?.read().dirty);
+
+        pc.disk().gated.store(false, Relaxed);
+        assert!(pc.flush_dirty(0.0, 1)? == 1);
+        let data = pc.disk().inner.read_page(id)?;
+        assert!(data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == [2; 4]);
+        assert!(!pc.fetch_page(id)?.read().dirty);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flusher() -> crate::Result<()> {
+        const CAPACITY: usize = 8;
+        let pc = PageCache::with_capacity(Faulty::new(Memory::new()), LRU::new(2), 0, CAPACITY);
+        let config = FlusherConfig {
+            dirty_ratio: 0.0,
+            interval: Duration::from_millis(1),
+            batch: 2,
+        };
+        let flusher = Flusher::start(pc.clone(), config);
+
+        for _ in 0..CAPACITY {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+
+        let mut waited = 0;
+        while pc.disk().writes() < CAPACITY {
+            assert!(waited < 1000, "flusher didn't write the pages back");
+            thread::sleep(Duration::from_millis(1));
+            waited += 1;
+        }
+
+        // Evicting clean pages doesn't write
+        for _ in 0..CAPACITY {
+            pc.new_page()?;
+        }
+        assert!(pc.disk().writes() == CAPACITY);
+
+        // Stopping flushes a pinned page too
+        let page = pc.fetch_page(CAPACITY as PageId)?;
+        let mut w = page.write();
+        writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        drop(w);
+        flusher.stop()?;
+        let data = pc
+            .disk()
+            .read_page(page.id)
+            .expect("page should be on disk");
+        assert!(data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == page.id.to_be_bytes());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flusher_errors() -> crate::Result<()> {
+        const CAPACITY: usize = 8;
+        let pc = PageCache::with_capacity(Faulty::new(Memory::new()), LRU::new(2), 0, CAPACITY);
+        let config = FlusherConfig {
+            dirty_ratio: 0.0,
+            interval: Duration::from_millis(1),
+            batch: 2,
+        };
+        pc.disk().fail_write(0);
+        let flusher = Flusher::start(pc.clone(), config);
+
+        for _ in 0..CAPACITY {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+
+        // The thread carries on, and the page that failed is written in a later round
+        let mut waited = 0;
+        while pc.disk().writes() < CAPACITY + 1 {
+            assert!(waited < 1000, "flusher didn't write the pages back");
+            thread::sleep(Duration::from_millis(1));
+            waited += 1;
+        }
+        assert!(matches!(flusher.take_error(), Some(Error::Disk(_))));
+        assert!(flusher.take_error().is_none());
+
+        // Closing the cache ends it
+        pc.close(Duration::ZERO)?;
+        assert!(pc.flush_dirty(0.0, CAPACITY) == Err(Error::Closed));
+        flusher.stop()?;
+
+        Ok(())
+    }
+}
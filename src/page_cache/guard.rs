@@ -0,0 +1,251 @@
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{
+    page::{PageBuf, PageId, PageInner, PageReadGuard, PageWriteGuard},
+    page_cache::Pin,
+    replacer::{LRUKReplacer, Replacer},
+};
+
+/*
+    The guards borrow from the `Arc<Page>` in the pin they hold. The page is on the heap so moving
+    the pin doesn't move it, and the guard is declared first so it's dropped before the pin.
+*/
+
+/// Read lock on a page that holds its own pin, so it can be returned or stored on its own.
+pub struct PinRead<R: Replacer = LRUKReplacer> {
+    guard: PageReadGuard<'static>,
+    pin: Pin<R>,
+}
+
+/// Write lock on a page that holds its own pin. Any mutable access marks the page dirty.
+pub struct PinWrite<R: Replacer = LRUKReplacer> {
+    guard: PageWriteGuard<'static>,
+    pin: Pin<R>,
+}
+
+/// Part of a read locked page, see `PinRead::map`.
+pub struct MappedPinRead<T: ?Sized, R: Replacer = LRUKReplacer> {
+    value: *const T,
+    _guard: PinRead<R>,
+}
+
+/// Part of a write locked page, see `PinWrite::map`. The page is marked dirty when it's mapped.
+pub struct MappedPinWrite<T: ?Sized, R: Replacer = LRUKReplacer> {
+    value: *mut T,
+    _guard: PinWrite<R>,
+}
+
+impl<R: Replacer> Pin<R> {
+    pub fn read_owned(self) -> PinRead<R> {
+        let guard = self.page.read();
+        // SAFETY: see the comment at the top of the file
+        let guard = unsafe { mem::transmute::<PageReadGuard<'_>, PageReadGuard<'static>>(guard) };
+
+        PinRead { guard, pin: self }
+    }
+
+    pub fn write_owned(self) -> PinWrite<R> {
+        let guard = self.page.write();
+        assert!(self.id == guard.id, "page was swapped out whilst a pin was held");
+        // SAFETY: see the comment at the top of the file
+        let guard = unsafe { mem::transmute::<PageWriteGuard<'_>, PageWriteGuard<'static>>(guard) };
+
+        PinWrite { guard, pin: self }
+    }
+}
+
+impl<R: Replacer> PinRead<R> {
+    pub fn id(&self) -> PageId {
+        self.pin.id
+    }
+
+    pub fn pin(&self) -> &Pin<R> {
+        &self.pin
+    }
+
+    /// Releases the lock, keeping the page pinned.
+    pub fn unlock(self) -> Pin<R> {
+        let PinRead { guard, pin } = self;
+        drop(guard);
+
+        pin
+    }
+
+    /// Swaps the read lock for a write lock. The lock is released in between, so another writer
+    /// may get in first and anything read before has to be read again.
+    pub fn upgrade(self) -> PinWrite<R> {
+        self.unlock().write_owned()
+    }
+
+    /// Narrows the guard to part of the page, such as a typed view of its data.
+    pub fn map<T: ?Sized>(self, f: impl FnOnce(&PageBuf) -> &T) -> MappedPinRead<T, R> {
+        let value: *const T = f(&self.guard.data);
+
+        MappedPinRead {
+            value,
+            _guard: self,
+        }
+    }
+}
+
+impl<R: Replacer> Deref for PinRead<R> {
+    type Target = PageInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<R: Replacer> PinWrite<R> {
+    pub fn id(&self) -> PageId {
+        self.pin.id
+    }
+
+    pub fn pin(&self) -> &Pin<R> {
+        &self.pin
+    }
+
+    /// Releases the lock, keeping the page pinned.
+    pub fn unlock(self) -> Pin<R> {
+        let PinWrite { guard, pin } = self;
+        drop(guard);
+
+        pin
+    }
+
+    /// Swaps the write lock for a read lock. The lock is released in between, so another writer
+    /// may get in first.
+    pub fn downgrade(self) -> PinRead<R> {
+        self.unlock().read_owned()
+    }
+
+    /// Narrows the guard to part of the page, such as a typed view of its data.
+    pub fn map<T: ?Sized>(
+        mut self,
+        f: impl FnOnce(&mut PageBuf) -> &mut T,
+    ) -> MappedPinWrite<T, R> {
+        let value: *mut T = f(&mut self.data);
+
+        MappedPinWrite {
+            value,
+            _guard: self,
+        }
+    }
+}
+
+impl<R: Replacer> Deref for PinWrite<R> {
+    type Target = PageInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<R: Replacer> DerefMut for PinWrite<R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.dirty = true;
+
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized, R: Replacer> Deref for MappedPinRead<T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: points into the page, which stays locked for as long as the guard is held
+        unsafe { &*self.value }
+    }
+}
+
+impl<T: ?Sized, R: Replacer> Deref for MappedPinWrite<T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: points into the page, which stays locked for as long as the guard is held
+        unsafe { &*self.value }
+    }
+}
+
+impl<T: ?Sized, R: Replacer> DerefMut for MappedPinWrite<T, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: as above, and the write lock makes this the only reference
+        unsafe { &mut *self.value }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        disk::Memory,
+        page::{PageBuf, PAGE_HEADER_SIZE},
+        page_cache::PageCache,
+        replacer::LRU,
+    };
+
+    #[test]
+    fn test_pin_guards() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::with_capacity(Memory::new(), LRU::new(K), 0, 2);
+
+        // Mutable access marks the page dirty
+        let id = pc.new_page()?.id;
+        let mut w = pc.fetch_page(id)?.write_owned();
+        assert!(w.id() == id && !w.dirty);
+        w.data[PAGE_HEADER_SIZE] = 1;
+        assert!(w.dirty);
+
+        // Still pinned and locked once the pin it was taken from is gone
+        let r = w.downgrade();
+        assert!(r.data[PAGE_HEADER_SIZE] == 1 && r.dirty);
+        assert!(pc.stats().pinned == 1);
+
+        // Reading doesn't dirty the page
+        let pin = r.unlock();
+        pc.flush_page(id)?;
+        let r = pin.read_owned();
+        assert!(!r.dirty);
+
+        let mut w = r.upgrade();
+        assert!(!w.dirty);
+        w.dirty = false;
+        let pin = w.unlock();
+        assert!(!pin.read().dirty);
+
+        // Mapped guards keep the page locked
+        let view: Vec<u8> = {
+            let header = pin
+                .read_owned()
+                .map(|data| &data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 2]);
+            assert!(pc.fetch_page(id)?.page.try_write().is_none());
+            header.to_vec()
+        };
+        assert!(view == [1, 0]);
+
+        let pin = pc.fetch_page(id)?;
+        {
+            let mut byte = pin
+                .write_owned()
+                .map(|data| &mut data[PAGE_HEADER_SIZE + 1]);
+            *byte = 2;
+        }
+        let r = pc.fetch_page(id)?.read_owned();
+        assert!(r.dirty && r.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 2] == [1, 2]);
+        drop(r);
+
+        // Typed views of the page
+        fn first(data: &PageBuf) -> &u8 {
+            &data[PAGE_HEADER_SIZE]
+        }
+        let byte = pc.fetch_page(id)?.read_owned().map(first);
+        assert!(*byte == 1);
+        drop(byte);
+
+        assert!(pc.stats().pinned == 0);
+
+        Ok(())
+    }
+}
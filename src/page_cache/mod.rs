@@ -0,0 +1,1838 @@
+pub mod flusher;
+pub mod guard;
+pub mod pins;
+pub mod stats;
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicUsize, Ordering::*},
+        Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    disk::{Disk, FileSystem},
+    error::{Error, LockExt, Result},
+    page::{
+        self, Header, Page, PageBuf, PageId, PageInner, PageType, SegmentId, PAGE_FORMAT_VERSION,
+        PAGE_SIZE, SEGMENT_PAGES,
+    },
+    page_cache::{
+        pins::{PinHolder, PinTracker},
+        stats::Counters,
+    },
+    replacer::{AccessType, LRUKReplacer, Replacer, Shared},
+};
+
+/// Default number of frames
+pub const CACHE_SIZE: usize = 64;
+
+const PAGE_TABLE_SHARDS: usize = 16;
+
+pub type FrameId = usize;
+
+#[derive(Default)]
+pub struct FreeList {
+    free: Mutex<Vec<FrameId>>,
+}
+
+impl FreeList {
+    /// Free list holding frames `0..len`.
+    pub fn new(len: usize) -> Self {
+        Self {
+            free: Mutex::new((0..len).rev().collect()),
+        }
+    }
+
+    fn free(&self) -> MutexGuard<'_, Vec<FrameId>> {
+        self.free.lock().unpoisoned()
+    }
+
+    pub fn pop(&self) -> Option<FrameId> {
+        self.free().pop()
+    }
+
+    pub fn push(&self, frame_id: FrameId) {
+        self.free().push(frame_id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.free().is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.free().len()
+    }
+}
+
+/*
+    Frame states:
+
+    Free -> Loading -> Resident -> Evicting -> Free
+                          |           |
+                          |           +-> Resident (the write back failed)
+                          +-> Removed -> Free
+
+    The page table maps a page to its frame while the frame is Loading, Resident or Evicting that
+    page. Both only change together, with the page table shard and then the frame locked, so a
+    frame found through the page table is always in one of those states for that page.
+
+    A frame can only be pinned while Resident. Only Resident or Removed frames without pins can be
+    claimed for eviction, and a claimed frame belongs to the thread that claimed it until it's
+    loaded again or put on the free list. Fetches that find a page Loading or Evicting wait for it
+    to settle and look again, so a page is only ever loaded into one frame.
+
+    A Resident page can be written back from a copy while it stays pinnable, see `write_frame`.
+    Eviction and removal wait for that write to finish.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FrameState {
+    /// On the free list, or claimed by a thread that's about to load a page into it
+    Free,
+    Loading(PageId),
+    Resident(PageId),
+    /// Being written back before the frame is reused
+    Evicting(PageId),
+    /// Taken out of the page table while pinned, the page is discarded once it's evicted
+    Removed,
+}
+
+struct FrameMeta {
+    state: FrameState,
+    pins: usize,
+    last_access: Option<Instant>,
+    /// Pins taken while tracking was on, by token
+    holders: Vec<(u64, PinHolder)>,
+    /// A copy of the page is being written back, see `write_frame`
+    writing: bool,
+}
+
+struct Frame {
+    page: Arc<Page>,
+    meta: Mutex<FrameMeta>,
+    /// Signalled when the frame leaves `Loading` or `Evicting`, when its last pin is dropped and
+    /// when a write back finishes
+    settled: Condvar,
+    /// `PageType` of the page, read from its header when it's loaded
+    ty: AtomicU8,
+    /// Cleared when the page is loaded, see `Pin::is_checked`
+    checked: AtomicBool,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            page: Arc::new(Page::default()),
+            meta: Mutex::new(FrameMeta {
+                state: FrameState::Free,
+                pins: 0,
+                last_access: None,
+                holders: Vec::new(),
+                writing: false,
+            }),
+            settled: Condvar::new(),
+            ty: AtomicU8::new(PageType::Unknown.into()),
+            checked: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Frame {
+    fn meta(&self) -> MutexGuard<'_, FrameMeta> {
+        self.meta.lock().unpoisoned()
+    }
+}
+
+/// Keeps a page resident until dropped. The frame is shared with the cache so a pin stays valid
+/// even if the cache is resized.
+pub struct Pin<R: Replacer = LRUKReplacer> {
+    pub page: Arc<Page>,
+    pub id: PageId,
+    i: FrameId,
+    frame: Arc<Frame>,
+    replacer: Arc<Shared<R>>,
+    /// Set if the pin is tracked, see `PageCache::track_pins`
+    token: Option<u64>,
+}
+
+impl<R: Replacer> Drop for Pin<R> {
+    fn drop(&mut self) {
+        // Unpinned with the frame locked so the frame can't be claimed in between
+        let mut meta = self.frame.meta();
+        meta.pins -= 1;
+        if let Some(token) = self.token {
+            meta.holders.retain(|(t, _)| *t != token);
+        }
+        self.replacer.unpin(self.i);
+        if meta.pins == 0 {
+            self.frame.settled.notify_all();
+        }
+    }
+}
+
+impl<R: Replacer> Pin<R> {
+    fn new(
+        frame: Arc<Frame>,
+        i: FrameId,
+        id: PageId,
+        token: Option<u64>,
+        replacer: Arc<Shared<R>>,
+    ) -> Self {
+        Self {
+            page: frame.page.clone(),
+            id,
+            i,
+            frame,
+            replacer,
+            token,
+        }
+    }
+
+    /// Tells the cache what a new page holds, for `PageCache::page_types`. Pages read from disk are
+    /// tagged from their header.
+    pub fn set_type(&self, ty: PageType) {
+        self.frame.ty.store(ty.into(), Relaxed);
+    }
+
+    /// Whether the page's owner checked that its contents are well formed since it was read from
+    /// disk, so pages that stay resident are only checked once.
+    pub fn is_checked(&self) -> bool {
+        self.frame.checked.load(Relaxed)
+    }
+
+    pub fn set_checked(&self) {
+        self.frame.checked.store(true, Relaxed);
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, PageInner> {
+        let w = self.page.write();
+
+        assert!(self.id == w.id, "page was swapped out whilst a pin was held");
+
+        w
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, PageInner> {
+        self.page.read()
+    }
+}
+
+/// Maps resident pages to their frames. Split into shards by page id, each with its own lock, so
+/// lookups of different pages don't contend.
+struct PageTable {
+    shards: Vec<RwLock<HashMap<PageId, FrameId>>>,
+}
+
+impl PageTable {
+    fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, page_id: PageId) -> &RwLock<HashMap<PageId, FrameId>> {
+        &self.shards[page_id as usize % self.shards.len()]
+    }
+
+    fn read(&self, page_id: PageId) -> RwLockReadGuard<'_, HashMap<PageId, FrameId>> {
+        self.shard(page_id).read().unpoisoned()
+    }
+
+    fn write(&self, page_id: PageId) -> RwLockWriteGuard<'_, HashMap<PageId, FrameId>> {
+        self.shard(page_id).write().unpoisoned()
+    }
+
+    fn contains(&self, page_id: PageId) -> bool {
+        self.read(page_id).contains_key(&page_id)
+    }
+
+    /// Copy of every entry. Shards are read one at a time so it isn't a consistent snapshot.
+    fn entries(&self) -> Vec<(PageId, FrameId)> {
+        let mut ret = Vec::new();
+        for shard in &self.shards {
+            ret.extend(shard.read().unpoisoned().iter().map(|(p, i)| (*p, *i)));
+        }
+
+        ret
+    }
+}
+
+pub struct PageCache<D: Disk = FileSystem, R: Replacer = LRUKReplacer> {
+    /// Frames removed by shrinking the cache are `None` until it grows again
+    frames: RwLock<Vec<Option<Arc<Frame>>>>,
+    capacity: AtomicUsize,
+    page_table: PageTable,
+    free: FreeList,
+    disk: D,
+    next_page_id: AtomicI32,
+    /// Next page number in each segment other than 0, loaded from the disk on first use
+    segments: Mutex<HashMap<SegmentId, u32>>,
+    replacer: Arc<Shared<R>>,
+    counters: Counters,
+    pin_tracker: PinTracker,
+    /// Set by `close`, no more pages can be fetched
+    closed: AtomicBool,
+}
+pub type SharedPageCache<D, R = LRUKReplacer> = Arc<PageCache<D, R>>;
+
+impl<D: Disk, R: Replacer> PageCache<D, R> {
+    pub fn new(disk: D, replacer: Arc<Shared<R>>, next_page_id: PageId) -> Arc<Self> {
+        Self::with_capacity(disk, replacer, next_page_id, CACHE_SIZE)
+    }
+
+    /// Creates a cache holding up to `capacity` pages, see `resize` to change it later.
+    pub fn with_capacity(
+        disk: D,
+        replacer: Arc<Shared<R>>,
+        next_page_id: PageId,
+        capacity: usize,
+    ) -> Arc<Self> {
+        let frames = (0..capacity)
+            .map(|_| Some(Arc::new(Frame::default())))
+            .collect();
+
+        let page_table = PageTable::new(PAGE_TABLE_SHARDS);
+        let free = FreeList::new(capacity);
+        let next_page_id = AtomicI32::new(next_page_id);
+
+        Arc::new(Self {
+            frames: RwLock::new(frames),
+            capacity: AtomicUsize::new(capacity),
+            page_table,
+            free,
+            disk,
+            next_page_id,
+            segments: Mutex::new(HashMap::new()),
+            replacer,
+            counters: Counters::default(),
+            pin_tracker: PinTracker::default(),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Number of frames in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Relaxed)
+    }
+
+    /// Grows or shrinks the cache to `capacity` frames. Shrinking uses free frames first and then
+    /// evicts unpinned pages, writing them back if they're dirty. If there aren't enough unpinned
+    /// pages the cache is left as small as it could get and `OutOfMemory` is returned.
+    pub fn resize(&self, capacity: usize) -> Result<()> {
+        let current = self.capacity();
+        if capacity >= current {
+            let mut frames = self.frames.write().unpoisoned();
+            let mut added = 0;
+            for (i, frame) in frames.iter_mut().enumerate() {
+                if added == capacity - current {
+                    break;
+                }
+
+                if frame.is_none() {
+                    *frame = Some(Arc::new(Frame::default()));
+                    self.free.push(i);
+                    added += 1;
+                }
+            }
+            for _ in added..capacity - current {
+                frames.push(Some(Arc::new(Frame::default())));
+                self.free.push(frames.len() - 1);
+            }
+            self.capacity.store(capacity, Relaxed);
+
+            return Ok(());
+        }
+
+        for _ in capacity..current {
+            let i = self
+                .take_frame(false, &[])?
+                .ok_or_else(|| self.out_of_memory())?;
+
+            self.frames.write().unpoisoned()[i] = None;
+            self.capacity.fetch_sub(1, Relaxed);
+        }
+
+        Ok(())
+    }
+
+    fn frame(&self, i: FrameId) -> Arc<Frame> {
+        self.frames.read().unpoisoned()[i]
+            .clone()
+            .expect("frame should be in use")
+    }
+
+    pub fn disk(&self) -> &D {
+        &self.disk
+    }
+
+    fn allocate_page(&self, segment: SegmentId) -> Result<PageId> {
+        if segment == 0 {
+            let page_id = self.next_page_id.fetch_add(1, Relaxed);
+            if page_id as u32 >= SEGMENT_PAGES {
+                return Err(Error::SegmentFull { segment });
+            }
+
+            return Ok(page_id);
+        }
+
+        let mut segments = self.segments(segment)?;
+        let next = segments.get_mut(&segment).expect("segment was loaded");
+
+        let page_id = page::segment_page_id(segment, *next)?;
+        *next += 1;
+
+        Ok(page_id)
+    }
+
+    /// Locks the next page numbers, loading `segment`'s from the disk if it isn't known yet.
+    fn segments(&self, segment: SegmentId) -> Result<MutexGuard<'_, HashMap<SegmentId, u32>>> {
+        let mut segments = self.segments.lock().or_poisoned()?;
+        if let Entry::Vacant(entry) = segments.entry(segment) {
+            entry.insert(self.disk.segment_len(segment)?);
+        }
+
+        Ok(segments)
+    }
+
+    /// Hands out a segment other than 0 that has no pages on disk and hasn't been used since the
+    /// cache was created. Segments are taken once a page is written to them, so this carries on
+    /// after a restart the same way page allocation does.
+    pub fn allocate_segment(&self) -> Result<SegmentId> {
+        let mut segments = self.segments.lock().or_poisoned()?;
+        for segment in 1..=page::MAX_SEGMENT {
+            if let Entry::Vacant(entry) = segments.entry(segment) {
+                let len = self.disk.segment_len(segment)?;
+                entry.insert(len);
+                if len == 0 {
+                    return Ok(segment);
+                }
+            }
+        }
+
+        Err(Error::OutOfSegments)
+    }
+
+    #[track_caller]
+    pub fn new_page(&self) -> Result<Pin<R>> {
+        self.new_page_in(0)
+    }
+
+    /// Allocates a new page in `segment`. Segment 0 holds pages that don't belong to a segment of
+    /// their own.
+    #[track_caller]
+    pub fn new_page_in(&self, segment: SegmentId) -> Result<Pin<R>> {
+        if self.closed.load(Relaxed) {
+            return Err(Error::Closed);
+        }
+        let page_id = self.allocate_page(segment)?;
+
+        self.fetch_page(page_id)
+    }
+
+    #[track_caller]
+    pub fn fetch_page(&self, page_id: PageId) -> Result<Pin<R>> {
+        self.fetch_page_with(page_id, AccessType::Get)
+    }
+
+    /// Fetches a page, telling the replacer how it's being accessed. Scans should use
+    /// `AccessType::Scan` so they don't flush the working set.
+    #[track_caller]
+    pub fn fetch_page_with(&self, page_id: PageId, access_type: AccessType) -> Result<Pin<R>> {
+        if page_id < 0 {
+            return Err(Error::PageNotFound { page_id });
+        }
+        if self.closed.load(Relaxed) {
+            return Err(Error::Closed);
+        }
+
+        loop {
+            if let Some(pin) = self.pin(page_id, Some(access_type)) {
+                if pin.page.is_poisoned() {
+                    // A thread panicked partway through changing the page, so it's read again
+                    drop(pin);
+                    self.remove_page(page_id);
+                    continue;
+                }
+                self.counters.hits.fetch_add(1, Relaxed);
+
+                return Ok(pin);
+            }
+
+            // All pages are pinned
+            let i = self
+                .take_frame(false, &[])?
+                .ok_or_else(|| self.out_of_memory())?;
+            let Some(pin) = self.load(i, page_id, access_type)? else {
+                // Loaded by another thread in the meantime
+                continue;
+            };
+            self.counters.misses.fetch_add(1, Relaxed);
+
+            return Ok(pin);
+        }
+    }
+
+    /// Pins `page_id` if it's resident, waiting for it if it's being loaded or evicted. The access
+    /// is recorded if there's an `access_type`.
+    #[track_caller]
+    fn pin(&self, page_id: PageId, access_type: Option<AccessType>) -> Option<Pin<R>> {
+        loop {
+            let page_table = self.page_table.read(page_id);
+            let i = *page_table.get(&page_id)?;
+            let frame = self.frame(i);
+            let mut meta = frame.meta();
+            drop(page_table);
+
+            match meta.state {
+                FrameState::Resident(p) if p == page_id => {
+                    meta.pins += 1;
+                    let mut replacer = self.replacer.lock(i);
+                    if let Some(access_type) = access_type {
+                        meta.last_access = Some(Instant::now());
+                        replacer.record_access(i, page_id, access_type);
+                    }
+                    replacer.pin(i);
+                    drop(replacer);
+                    let token = self.track(&mut meta, i, page_id);
+                    drop(meta);
+
+                    return Some(Pin::new(frame, i, page_id, token, self.replacer.clone()));
+                }
+                FrameState::Loading(p) | FrameState::Evicting(p) if p == page_id => {
+                    let state = meta.state;
+                    let _settled = frame
+                        .settled
+                        .wait_while(meta, |m| m.state == state)
+                        .unpoisoned();
+                }
+                state => unreachable!("page {page_id} maps to frame {i} in state {state:?}"),
+            }
+        }
+    }
+
+    /// Takes a frame to load a page into, from the free list or by evicting a page. With
+    /// `scanned_only` only pages loaded by scans are evicted, and never the ones in frames in
+    /// `keep`. Returns `None` if there's no frame that can be used.
+    fn take_frame(&self, scanned_only: bool, keep: &[FrameId]) -> Result<Option<FrameId>> {
+        loop {
+            if let Some(i) = self.free.pop() {
+                return Ok(Some(i));
+            }
+
+            let victim = match scanned_only {
+                true => self.replacer.evict_scanned(),
+                false => self.replacer.evict(),
+            };
+            let Some(i) = victim.filter(|i| !keep.contains(i)) else {
+                return Ok(None);
+            };
+
+            if self.evict(i)? {
+                return Ok(Some(i));
+            }
+        }
+    }
+
+    /// Claims frame `i`, which the replacer chose, writing its page back if it's dirty. Returns
+    /// false if the frame was pinned or claimed by another thread since it was chosen.
+    fn evict(&self, i: FrameId) -> Result<bool> {
+        let frame = self.frame(i);
+        let state = frame.meta().state;
+        let page_table = match state {
+            FrameState::Resident(p) => Some(self.page_table.write(p)),
+            FrameState::Removed => None,
+            _ => return Ok(false),
+        };
+
+        let mut meta = frame.meta();
+        if meta.state != state || meta.pins > 0 {
+            return Ok(false);
+        }
+        if meta.writing {
+            // The page is clean once the write is done, the caller tries again
+            drop(page_table);
+            let _settled = frame.settled.wait_while(meta, |m| m.writing).unpoisoned();
+            return Ok(false);
+        }
+        self.replacer.remove(i);
+
+        let FrameState::Resident(page_id) = state else {
+            // Removed pages are discarded
+            meta.state = FrameState::Free;
+            return Ok(true);
+        };
+        meta.state = FrameState::Evicting(page_id);
+        drop(meta);
+        drop(page_table);
+
+        // The page stays in the page table so fetches wait for the write instead of reading the
+        // old version from disk
+        // Poisoned pages may be half written, so they're discarded
+        let mut page_w = frame.page.write();
+        let written = match page_w.dirty && !frame.page.is_poisoned() {
+            true => self.write_page(&mut page_w),
+            false => Ok(()),
+        };
+        drop(page_w);
+
+        let mut page_table = self.page_table.write(page_id);
+        let mut meta = frame.meta();
+        match written {
+            Ok(()) => {
+                page_table.remove(&page_id);
+                meta.state = FrameState::Free;
+                self.counters.evictions.fetch_add(1, Relaxed);
+            }
+            Err(_) => {
+                // Still resident, and evictable again
+                meta.state = FrameState::Resident(page_id);
+                self.replacer.record_access(i, page_id, AccessType::Get);
+            }
+        }
+        frame.settled.notify_all();
+
+        written.map(|_| true)
+    }
+
+    /// Loads `page_id` into frame `i`, which the caller took, and pins it. Returns `None` if
+    /// another thread loaded the page first, in which case the frame goes back on the free list.
+    #[track_caller]
+    fn load(&self, i: FrameId, page_id: PageId, access_type: AccessType) -> Result<Option<Pin<R>>> {
+        let frame = self.frame(i);
+        let mut page_table = self.page_table.write(page_id);
+        if page_table.contains_key(&page_id) {
+            drop(page_table);
+            self.free.push(i);
+            return Ok(None);
+        }
+
+        page_table.insert(page_id, i);
+        let mut meta = frame.meta();
+        meta.state = FrameState::Loading(page_id);
+        meta.pins = 1;
+        drop(meta);
+        drop(page_table);
+
+        let data = self
+            .disk
+            .read_page(page_id)
+            .map_err(Error::from)
+            .and_then(|data| {
+                // Pages written by a newer build can't be read
+                let version = Header::from(&data).version();
+                match page::verify_checksum(&data) && version <= PAGE_FORMAT_VERSION {
+                    true => Ok(data),
+                    false => Err(Error::Corrupted { page_id }),
+                }
+            });
+
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                let mut page_table = self.page_table.write(page_id);
+                let mut meta = frame.meta();
+                page_table.remove(&page_id);
+                meta.state = FrameState::Free;
+                meta.pins = 0;
+                frame.settled.notify_all();
+                drop(meta);
+                drop(page_table);
+                self.free.push(i);
+
+                return Err(e);
+            }
+        };
+
+        frame
+            .ty
+            .store(Header::from(&data).page_type().into(), Relaxed);
+        frame.checked.store(false, Relaxed);
+        let mut page_w = frame.page.write();
+        page_w.reset();
+        page_w.id = page_id;
+        page_w.data = data;
+        drop(page_w);
+        frame.page.clear_poison();
+
+        let mut meta = frame.meta();
+        meta.state = FrameState::Resident(page_id);
+        meta.last_access = Some(Instant::now());
+        let mut replacer = self.replacer.lock(i);
+        replacer.record_access(i, page_id, access_type);
+        replacer.pin(i);
+        drop(replacer);
+        let token = self.track(&mut meta, i, page_id);
+        frame.settled.notify_all();
+        drop(meta);
+
+        Ok(Some(Pin::new(frame, i, page_id, token, self.replacer.clone())))
+    }
+
+    /// Loads the pages that aren't resident into free frames, or frames that only scans have
+    /// used, without pinning them. It's only a hint, pages are skipped once loading them would mean
+    /// evicting the working set. Returns the number of pages loaded.
+    pub fn prefetch(&self, page_ids: &[PageId]) -> Result<usize> {
+        if self.closed.load(Relaxed) {
+            return Err(Error::Closed);
+        }
+
+        let mut loaded = Vec::new();
+        for page_id in page_ids {
+            if self.page_table.contains(*page_id) {
+                continue;
+            }
+
+            // Don't replace the pages loaded by this call
+            let Some(i) = self.take_frame(true, &loaded)? else {
+                break;
+            };
+
+            if self.load(i, *page_id, AccessType::Scan)?.is_some() {
+                self.counters.prefetched.fetch_add(1, Relaxed);
+                loaded.push(i);
+            }
+        }
+
+        Ok(loaded.len())
+    }
+
+    /// Discards the page without writing it back. If it's pinned the frame is reused once the pins
+    /// are dropped.
+    pub fn remove_page(&self, page_id: PageId) {
+        loop {
+            let mut page_table = self.page_table.write(page_id);
+            let Some(i) = page_table.get(&page_id).copied() else {
+                return;
+            };
+            let frame = self.frame(i);
+            let mut meta = frame.meta();
+
+            match meta.state {
+                FrameState::Resident(_) if meta.writing => {
+                    drop(page_table);
+                    let _settled = frame.settled.wait_while(meta, |m| m.writing).unpoisoned();
+                }
+                FrameState::Resident(_) => {
+                    page_table.remove(&page_id);
+                    if meta.pins == 0 {
+                        meta.state = FrameState::Free;
+                        self.replacer.remove(i);
+                        self.free.push(i);
+                    } else {
+                        meta.state = FrameState::Removed;
+                    }
+
+                    return;
+                }
+                state => {
+                    drop(page_table);
+                    let _settled = frame
+                        .settled
+                        .wait_while(meta, |m| m.state == state)
+                        .unpoisoned();
+                }
+            }
+        }
+    }
+
+    /// Discards every resident page in `segment` without writing them back and deletes the segment
+    /// from disk.
+    pub fn drop_segment(&self, segment: SegmentId) -> Result<()> {
+        for (page_id, _) in self.page_table.entries() {
+            if page::segment_of(page_id) == segment {
+                self.remove_page(page_id);
+            }
+        }
+
+        if segment == 0 {
+            self.next_page_id.store(0, Relaxed);
+        } else {
+            self.segments.lock().or_poisoned()?.insert(segment, 0);
+        }
+
+        Ok(self.disk.drop_segment(segment)?)
+    }
+
+    /// Writes the page back to disk and waits for it to be durable.
+    pub fn flush_page(&self, page_id: PageId) -> Result<()> {
+        self.write_back(page_id)?;
+
+        Ok(self.disk.sync()?)
+    }
+
+    /// Writes every resident page back to disk, issuing a single sync at the end.
+    pub fn flush_all_pages(&self) -> Result<()> {
+        for (page_id, _) in self.page_table.entries() {
+            self.write_back(page_id)?;
+        }
+
+        Ok(self.disk.sync()?)
+    }
+
+    /// Stops pages from being fetched, waits up to `timeout` for the pins already held to be
+    /// dropped, then writes every page back and syncs. Returns `Pinned` without writing anything
+    /// if the pins weren't dropped in time, in which case dropping the cache writes back what it
+    /// can.
+    pub fn close(&self, timeout: Duration) -> Result<()> {
+        self.closed.store(true, Relaxed);
+
+        let deadline = Instant::now() + timeout;
+        let frames = self.frames.read().unpoisoned().clone();
+        for frame in frames.iter().flatten() {
+            let mut meta = frame.meta();
+            while meta.pins > 0 {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    drop(meta);
+                    self.report_pins("closed");
+                    let pins = frames.iter().flatten().map(|f| f.meta().pins).sum();
+                    return Err(Error::Pinned { pins });
+                }
+
+                meta = frame.settled.wait_timeout(meta, left).unpoisoned().0;
+            }
+        }
+
+        for (page_id, _) in self.page_table.entries() {
+            self.write_back(page_id)?;
+        }
+        self.persist_allocator()?;
+
+        Ok(self.disk.sync()?)
+    }
+
+    /// Next page numbers are recovered from the highest page written in each segment, so the last
+    /// page allocated in a segment is written as zeroes if it never made it to disk. Otherwise it
+    /// would be allocated again after a restart.
+    fn persist_allocator(&self) -> Result<()> {
+        let mut next = vec![(0, (self.next_page_id.load(Relaxed) as u32).min(SEGMENT_PAGES))];
+        next.extend(
+            self.segments
+                .lock()
+                .or_poisoned()?
+                .iter()
+                .map(|(s, n)| (*s, *n)),
+        );
+
+        for (segment, n) in next {
+            if n > 0 && self.disk.segment_len(segment)? < n {
+                let page_id = page::segment_page_id(segment, n - 1)?;
+                self.disk.write_page(page_id, &[0; PAGE_SIZE])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_back(&self, page_id: PageId) -> Result<()> {
+        let Some(pin) = self.pin(page_id, None) else {
+            return Ok(());
+        };
+
+        self.write_frame(&pin.frame, page_id, false)?;
+
+        Ok(())
+    }
+
+    /// Writes the page in `frame` back, without holding the frame's locks while the
+    /// disk is busy. The page is copied under a read lock and only marked clean if it still
+    /// matches the copy afterwards. `writing` keeps the page from being evicted, removed or
+    /// written by another thread in the meantime, so an older copy can't land after a newer one.
+    /// In the `background` pinned, locked and already writing pages are skipped instead of waited
+    /// for. Returns whether the page was written.
+    fn write_frame(&self, frame: &Frame, page_id: PageId, background: bool) -> Result<bool> {
+        let meta = frame.meta();
+        if background && (meta.writing || meta.pins > 0) {
+            return Ok(false);
+        }
+        let mut meta = frame.settled.wait_while(meta, |m| m.writing).unpoisoned();
+        if meta.state != FrameState::Resident(page_id) {
+            return Ok(false);
+        }
+        meta.writing = true;
+        drop(meta);
+
+        let written = self.write_copy(frame, background);
+
+        frame.meta().writing = false;
+        frame.settled.notify_all();
+
+        written
+    }
+
+    fn write_copy(&self, frame: &Frame, background: bool) -> Result<bool> {
+        let page_r = match background {
+            true => frame.page.try_read(),
+            false => Some(frame.page.read()),
+        };
+        // Only the background skips clean pages
+        let Some(page_r) = page_r.filter(|r| (r.dirty || !background) && !frame.page.is_poisoned())
+        else {
+            return Ok(false);
+        };
+        let (page_id, copy) = (page_r.id, page_r.data);
+        drop(page_r);
+
+        let mut data = copy;
+        self.write_data(page_id, &mut data)?;
+
+        // Left dirty if it changed while it was written, or is locked in the background
+        let page_w = match background {
+            true => frame.page.try_write(),
+            false => Some(frame.page.write()),
+        };
+        if let Some(mut page_w) = page_w.filter(|w| w.data == copy) {
+            page_w.dirty = false;
+        }
+
+        Ok(true)
+    }
+
+    fn write_page(&self, page_w: &mut PageInner) -> Result<()> {
+        self.write_data(page_w.id, &mut page_w.data)?;
+        page_w.dirty = false;
+
+        Ok(())
+    }
+
+    /// Every page written back goes through here.
+    // TODO: there's no log yet. Once pages carry an LSN the log has to be flushed up to it before
+    // the page is written
+    fn write_data(&self, page_id: PageId, data: &mut PageBuf) -> Result<()> {
+        page::write_checksum(data);
+        self.disk.write_page(page_id, data)?;
+        self.counters.writebacks.fetch_add(1, Relaxed);
+
+        Ok(())
+    }
+
+    /// Writes back dirty pages until at most `ratio` of the frames are dirty, writing no more than
+    /// `batch` pages. Pinned and locked pages are skipped since they're likely to be dirtied again,
+    /// and so this never waits on foreground work. Returns the number of pages written, or
+    /// `Closed` once the cache is closed.
+    pub fn flush_dirty(&self, ratio: f64, batch: usize) -> Result<usize> {
+        if self.closed.load(Relaxed) {
+            return Err(Error::Closed);
+        }
+
+        self.write_dirty(ratio, batch)
+    }
+
+    fn write_dirty(&self, ratio: f64, batch: usize) -> Result<usize> {
+        let target = (self.capacity() as f64 * ratio) as usize;
+        let mut dirty = Vec::new();
+        for (page_id, i) in self.page_table.entries() {
+            let Some(frame) = self.frames.read().unpoisoned()[i].clone() else {
+                continue;
+            };
+            if frame.page.try_read().is_some_and(|r| r.dirty) {
+                dirty.push((page_id, frame));
+            }
+        }
+
+        let mut written = 0;
+        for (page_id, frame) in &dirty {
+            if written == batch || dirty.len() - written <= target {
+                break;
+            }
+
+            if self.write_frame(frame, *page_id, true)? {
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Panics if the page table, frames and free list disagree. Only meaningful while no other
+    /// thread is using the cache.
+    #[cfg(test)]
+    fn assert_consistent(&self) {
+        let frames = self.frames.read().unpoisoned();
+        let mut mapped = HashMap::new();
+        for (page_id, i) in self.page_table.entries() {
+            let frame = frames[i].as_ref().expect("mapped frame should be in use");
+            let state = frame.meta().state;
+            assert!(
+                state == FrameState::Resident(page_id),
+                "page {page_id} maps to frame {i} in state {state:?}"
+            );
+            assert!(mapped.insert(i, page_id).is_none(), "frame {i} is mapped twice");
+        }
+
+        let free = self.free.free().clone();
+        for (i, frame) in frames.iter().enumerate() {
+            let Some(frame) = frame else {
+                assert!(!free.contains(&i), "removed frame {i} is on the free list");
+                continue;
+            };
+
+            let meta = frame.meta();
+            assert!(meta.pins == 0, "frame {i} is still pinned");
+            assert!(!meta.writing, "frame {i} is still being written");
+            match meta.state {
+                FrameState::Free => assert!(free.contains(&i), "free frame {i} was leaked"),
+                FrameState::Resident(page_id) => {
+                    assert!(mapped.get(&i) == Some(&page_id), "frame {i} isn't mapped")
+                }
+                FrameState::Removed => {}
+                state => panic!("frame {i} was left in state {state:?}"),
+            }
+        }
+    }
+}
+
+impl<D: Disk, R: Replacer> Drop for PageCache<D, R> {
+    /// Writes back what it can without waiting, pages that are still pinned are lost. Use `close`
+    /// to find out if anything went wrong.
+    fn drop(&mut self) {
+        self.report_pins("dropped");
+
+        let flushed = self
+            .write_dirty(0.0, usize::MAX)
+            .and_then(|_| self.persist_allocator())
+            .and_then(|_| Ok(self.disk.sync()?));
+        if let Err(e) = flushed {
+            eprintln!("ERROR: could not flush pages - {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering::Relaxed},
+            Arc, Barrier,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{
+        disk::{fault::Faulty, Disk, FileSystem, Memory},
+        error::Error,
+        page::{
+            self, Header, PageBuf, PageId, PageType, SegmentId, PAGE_FORMAT_VERSION,
+            PAGE_HEADER_SIZE, PAGE_SIZE, PAGE_VERSION,
+        },
+        page_cache::{FreeList, PageCache, CACHE_SIZE},
+        replacer::{AccessType, Clock, Replacer, Shared, TwoQ, ARC, LRU},
+        test::CleanUp,
+        writep,
+    };
+
+    #[test]
+    fn test_pm_read() -> crate::Result<()> {
+        const K: usize = 2;
+        let disk = Memory::new();
+        let replacer = LRU::new(K);
+        let pc = PageCache::new(disk, replacer, 0);
+
+        // Hold CACHE_SIZE - 3 pins
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE - 2 {
+            pages.push(pc.new_page()?)
+        }
+
+        // Write to page CACHE_SIZE - 2
+        let want = b"test string";
+        let id;
+        {
+            let page = pc.new_page()?;
+            id = page.id;
+            let mut w = page.write();
+            w.data[0..want.len()].copy_from_slice(want);
+            w.dirty = true;
+        }
+
+        // Swap the page out and write something to page PAGE_CACHE - 1 (last available page):
+        {
+            let data = b"page 8";
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, 0..data.len(), data);
+        }
+
+        // Read back page CACHE_SIZE - 2
+        let page = pc.fetch_page(id)?;
+        let r = page.read();
+        let have = &r.data[0..want.len()];
+        assert!(want == have, "Want: {want:?}, Have: {have:?}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_replacer_full() -> crate::Result<()> {
+        const K: usize = 2;
+        let disk = Memory::new();
+        let replacer = LRU::new(K);
+        let pc = PageCache::new(disk, replacer, 0);
+
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+
+        let have = pc.new_page();
+        assert!(have.is_err(), "Expected new_page to return OutOfMemory when all pages are pinned");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_all_pages_single_sync() -> crate::Result<()> {
+        struct Counting {
+            inner: Memory,
+            writes: AtomicUsize,
+            syncs: AtomicUsize,
+        }
+
+        impl Disk for Counting {
+            fn read_page(&self, page_id: PageId) -> std::io::Result<PageBuf> {
+                self.inner.read_page(page_id)
+            }
+
+            fn write_page(&self, page_id: PageId, data: &PageBuf) -> std::io::Result<()> {
+                self.writes.fetch_add(1, Relaxed);
+                self.inner.write_page(page_id, data)
+            }
+
+            fn sync(&self) -> std::io::Result<()> {
+                self.syncs.fetch_add(1, Relaxed);
+                self.inner.sync()
+            }
+
+            fn segment_len(&self, segment: SegmentId) -> std::io::Result<u32> {
+                self.inner.segment_len(segment)
+            }
+        }
+
+        const K: usize = 2;
+        let disk = Counting {
+            inner: Memory::new(),
+            writes: AtomicUsize::new(0),
+            syncs: AtomicUsize::new(0),
+        };
+        let replacer = LRU::new(K);
+        let pc = PageCache::new(disk, replacer, 0);
+
+        for _ in 0..8 {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+
+        pc.flush_all_pages()?;
+        assert!(pc.disk.writes.load(Relaxed) == 8);
+        assert!(pc.disk.syncs.load(Relaxed) == 1);
+
+        pc.flush_page(0)?;
+        assert!(pc.disk.writes.load(Relaxed) == 9);
+        assert!(pc.disk.syncs.load(Relaxed) == 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_disk_errors() -> crate::Result<()> {
+        const K: usize = 2;
+        let disk = Faulty::new(Memory::new());
+        let replacer = LRU::new(K);
+        let pc = PageCache::new(disk, replacer, 0);
+
+        // Fill the cache with dirty pages
+        for _ in 0..CACHE_SIZE {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+
+        // Writing out the victim fails
+        pc.disk.fail_write(0);
+        let have = pc.new_page();
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        // Reading in the page fails
+        pc.disk.fail_read(0);
+        let have = pc.fetch_page(CACHE_SIZE as PageId * 2 - 1);
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        // No frames were leaked
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+
+        // Nothing was lost
+        for id in 0..CACHE_SIZE as PageId {
+            let page = pc.fetch_page(id)?;
+            let r = page.read();
+            assert!(
+                r.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == id.to_be_bytes(),
+                "Page {id} was lost"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_corruption() -> crate::Result<()> {
+        const K: usize = 2;
+        let disk = Faulty::new(Memory::new());
+        let replacer = LRU::new(K);
+        let pc = PageCache::new(disk, replacer, 0);
+
+        let data = b"test string";
+        for _ in 0..2 {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + data.len(), data);
+        }
+        pc.flush_all_pages()?;
+
+        // Evict both pages
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+
+        // Flipped bits
+        pc.disk
+            .corrupt(0, PAGE_HEADER_SIZE + 2)
+            .expect("corrupt page 0");
+        let have = pc.fetch_page(0);
+        assert!(
+            matches!(have, Err(Error::Corrupted { page_id: 0 })),
+            "Expected page 0 to be corrupted"
+        );
+
+        // Torn write, a crash before the rest of the page makes it to disk
+        {
+            let page = pc.fetch_page(1)?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, b"torn");
+        }
+        pc.disk.tear_write(0, PAGE_HEADER_SIZE);
+        assert!(pc.flush_page(1).is_err());
+
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+        pc.disk.crash();
+
+        let have = pc.fetch_page(1);
+        assert!(
+            matches!(have, Err(Error::Corrupted { page_id: 1 })),
+            "Expected page 1 to be corrupted"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_page_header() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::with_capacity(Memory::new(), LRU::new(K), 0, 2);
+
+        let a = pc.new_page()?;
+        let mut w = a.write_owned();
+        let mut header = Header::from(&mut w.data);
+        header.init(PageType::BTreeLeaf);
+        header.set_lsn(7);
+        let a = w.unlock().id;
+
+        // Written by a newer version
+        let b = pc.new_page()?.id;
+        let mut data = [0; PAGE_SIZE];
+        Header::from(&mut data).init(PageType::Table);
+        data[PAGE_VERSION] = PAGE_FORMAT_VERSION + 1;
+        page::write_checksum(&mut data);
+        pc.disk().write_page(b, &data)?;
+
+        // Evict both pages, the type comes from the header when they're read back
+        for _ in 0..2 {
+            pc.new_page()?;
+        }
+        assert!(pc.page_types() == [(PageType::Unknown, 2)].into());
+
+        let page = pc.fetch_page(a)?;
+        assert!(pc.page_types()[&PageType::BTreeLeaf] == 1);
+        let r = page.read();
+        let header = Header::from(&r.data);
+        assert!(header.page_type() == PageType::BTreeLeaf);
+        assert!(header.version() == PAGE_FORMAT_VERSION && header.lsn() == 7);
+        drop(r);
+
+        assert!(matches!(pc.fetch_page(b), Err(Error::Corrupted { .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_resize() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::with_capacity(Memory::new(), LRU::new(K), 0, 4);
+        assert!(pc.capacity() == 4);
+
+        let mut pages = Vec::new();
+        for _ in 0..4 {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+            drop(w);
+            pages.push(page);
+        }
+        assert!(pc.new_page().is_err());
+
+        // Shrinking evicts the unpinned pages and leaves the pinned one alone
+        let pinned = pages.remove(0);
+        drop(pages);
+        pc.resize(1)?;
+        assert!(pc.capacity() == 1);
+        assert!(pinned.read().data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == 0_i32.to_be_bytes());
+        assert!(matches!(pc.fetch_page(1), Err(Error::OutOfMemory)));
+
+        // Can't shrink past pinned pages
+        assert!(pc.resize(0) == Err(Error::OutOfMemory));
+        assert!(pc.capacity() == 1);
+
+        // Grow again, evicted pages were written back
+        pc.resize(8)?;
+        assert!(pc.capacity() == 8);
+        for id in 1..4 {
+            let page = pc.fetch_page(id)?;
+            assert!(page.read().data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == id.to_be_bytes());
+        }
+        drop(pinned);
+
+        let mut pages = Vec::new();
+        for _ in 0..4 {
+            pages.push(pc.new_page()?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_replacers() -> crate::Result<()> {
+        fn run<R: Replacer>(replacer: Arc<Shared<R>>) -> crate::Result<()> {
+            const CAPACITY: usize = 8;
+            let pc = PageCache::with_capacity(Memory::new(), replacer, 0, CAPACITY);
+
+            for _ in 0..CAPACITY * 4 {
+                let page = pc.new_page()?;
+                let mut w = page.write();
+                writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+            }
+
+            // Hold some pins, the rest of the frames are still usable
+            let pins = (0..CAPACITY as PageId / 2)
+                .map(|id| pc.fetch_page(id))
+                .collect::<Result<Vec<_>, _>>()?;
+            for id in (0..CAPACITY as PageId * 4).rev() {
+                let page = pc.fetch_page(id)?;
+                assert!(
+                    page.read().data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == id.to_be_bytes()
+                );
+            }
+
+            let mut more = Vec::new();
+            for _ in 0..CAPACITY / 2 {
+                more.push(pc.new_page()?);
+            }
+            assert!(matches!(pc.new_page(), Err(Error::OutOfMemory)));
+            drop(pins);
+
+            Ok(())
+        }
+
+        run(LRU::new(2))?;
+        run(LRU::with_shards(2, 4))?;
+        run(Clock::new())?;
+        run(TwoQ::new())?;
+        run(ARC::new())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_scan() -> crate::Result<()> {
+        fn run<R: Replacer>(replacer: Arc<Shared<R>>) -> crate::Result<()> {
+            const CAPACITY: usize = 8;
+            const HOT: PageId = 4;
+            let pc = PageCache::with_capacity(Faulty::new(Memory::new()), replacer, 0, CAPACITY);
+            for _ in 0..CAPACITY * 8 {
+                pc.new_page()?;
+            }
+
+            // The rest of the frames are accessed too, but further apart than the working set
+            for round in 0..3 {
+                let cold = if round < 2 { HOT..CAPACITY as PageId } else { 0..0 };
+                for id in cold.chain(0..HOT) {
+                    pc.fetch_page(id)?;
+                }
+            }
+
+            // A scan much larger than the cache only cycles through the frames the working set
+            // isn't using
+            for id in HOT..CAPACITY as PageId * 8 {
+                pc.fetch_page_with(id, AccessType::Scan)?;
+            }
+
+            let reads = pc.disk().reads();
+            for id in 0..HOT {
+                pc.fetch_page(id)?;
+            }
+            assert!(
+                pc.disk().reads() == reads,
+                "scan evicted the working set {}",
+                std::any::type_name::<R>()
+            );
+
+            Ok(())
+        }
+
+        run(LRU::new(2))?;
+        run(LRU::with_shards(2, 4))?;
+        run(Clock::new())?;
+        run(TwoQ::new())?;
+        run(ARC::new())?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_prefetch() -> crate::Result<()> {
+        const CAPACITY: usize = 8;
+        let pc = PageCache::with_capacity(Faulty::new(Memory::new()), LRU::new(2), 0, CAPACITY);
+        for _ in 0..CAPACITY * 4 {
+            pc.new_page()?;
+        }
+
+        // Every frame holds a page that was fetched normally
+        assert!(pc.prefetch(&[0, 1])? == 0);
+
+        // Free frames are used, and the pages aren't pinned
+        pc.resize(CAPACITY / 2)?;
+        pc.resize(CAPACITY)?;
+        let reads = pc.disk().reads();
+        assert!(pc.prefetch(&[0, 1, 2, 2, 30])? == 3);
+        assert!(pc.disk().reads() == reads + 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_sharded() -> crate::Result<()> {
+        const PAGES: PageId = 32;
+        const THREADS: usize = 4;
+        let pc = PageCache::with_capacity(Memory::new(), LRU::with_shards(2, 4), 0, 64);
+        for _ in 0..PAGES {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+
+        let writes = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let pc = &pc;
+                let writes = &writes;
+                s.spawn(move || {
+                    for n in 0..1000 {
+                        let id = ((n * 7 + t * 13) % PAGES as usize) as PageId;
+                        let page = pc.fetch_page(id).expect("everything fits in the cache");
+                        if n % 10 == 0 {
+                            let mut w = page.write();
+                            let at = PAGE_HEADER_SIZE + 4 + t * 4;
+                            let count = u32::from_be_bytes(w.data[at..at + 4].try_into().unwrap());
+                            writep!(w, at..at + 4, &(count + 1).to_be_bytes());
+                            writes.fetch_add(1, Relaxed);
+                        }
+
+                        let r = page.read();
+                        assert!(r.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == id.to_be_bytes());
+                    }
+                });
+            }
+        });
+
+        let mut total = 0;
+        for id in 0..PAGES {
+            let page = pc.fetch_page(id)?;
+            let r = page.read();
+            for t in 0..THREADS {
+                let at = PAGE_HEADER_SIZE + 4 + t * 4;
+                total += u32::from_be_bytes(r.data[at..at + 4].try_into().unwrap()) as usize;
+            }
+        }
+        assert!(total == writes.load(Relaxed));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_single_load() -> crate::Result<()> {
+        struct Slow {
+            inner: Memory,
+            reads: AtomicUsize,
+        }
+
+        impl Disk for Slow {
+            fn read_page(&self, page_id: PageId) -> std::io::Result<PageBuf> {
+                self.reads.fetch_add(1, Relaxed);
+                thread::sleep(Duration::from_millis(20));
+                self.inner.read_page(page_id)
+            }
+
+            fn write_page(&self, page_id: PageId, data: &PageBuf) -> std::io::Result<()> {
+                self.inner.write_page(page_id, data)
+            }
+
+            fn sync(&self) -> std::io::Result<()> {
+                self.inner.sync()
+            }
+
+            fn segment_len(&self, segment: SegmentId) -> std::io::Result<u32> {
+                self.inner.segment_len(segment)
+            }
+        }
+
+        const THREADS: usize = 8;
+        let disk = Slow {
+            inner: Memory::new(),
+            reads: AtomicUsize::new(0),
+        };
+        let pc = PageCache::with_capacity(disk, LRU::new(2), 1, 4);
+
+        // Everyone asks for the page while it's being read
+        let barrier = Barrier::new(THREADS);
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    barrier.wait();
+                    let page = pc.fetch_page(0).expect("fetch page 0");
+                    assert!(page.read().id == 0);
+                });
+            }
+        });
+        assert!(pc.disk.reads.load(Relaxed) == 1);
+        pc.assert_consistent();
+
+        // Evicting writes the page back before the frame is reused
+        {
+            let page = pc.fetch_page(0)?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &7_i32.to_be_bytes());
+        }
+        pc.resize(0)?;
+        let page = pc.fetch_page(0);
+        assert!(matches!(page, Err(Error::OutOfMemory)));
+        pc.resize(1)?;
+        let page = pc.fetch_page(0)?;
+        assert!(page.read().data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4] == 7_i32.to_be_bytes());
+        drop(page);
+        pc.assert_consistent();
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_concurrent() -> crate::Result<()> {
+        const CAPACITY: usize = 8;
+        const THREADS: usize = 4;
+        const OPS: usize = 2000;
+        // Pages past this are removed at random, so only their ids are checked
+        const PAGES: PageId = 24;
+        const REMOVABLE: PageId = 32;
+
+        let seed: u64 = rand::random();
+        eprintln!("seed: {seed}");
+
+        fn id(page: &crate::page::PageInner) -> PageId {
+            PageId::from_be_bytes(
+                page.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4]
+                    .try_into()
+                    .unwrap(),
+            )
+        }
+
+        fn count(page: &crate::page::PageInner, t: usize) -> u32 {
+            let at = PAGE_HEADER_SIZE + 4 + t * 4;
+            u32::from_be_bytes(page.data[at..at + 4].try_into().unwrap())
+        }
+
+        let pc = PageCache::with_capacity(Memory::new(), LRU::with_shards(2, 2), 0, CAPACITY);
+        for _ in 0..REMOVABLE {
+            let page = pc.new_page()?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &page.id.to_be_bytes());
+        }
+        pc.flush_all_pages()?;
+
+        let writes: Vec<AtomicUsize> = (0..THREADS).map(|_| AtomicUsize::new(0)).collect();
+        thread::scope(|s| {
+            for t in 0..THREADS {
+                let pc = &pc;
+                let writes = &writes;
+                s.spawn(move || {
+                    let mut rng = StdRng::seed_from_u64(seed + t as u64);
+                    let mut held = Vec::new();
+                    for _ in 0..OPS {
+                        let page_id = rng.gen_range(0..REMOVABLE);
+                        match rng.gen_range(0..100) {
+                            0..=59 => {
+                                let access_type = match rng.gen_bool(0.2) {
+                                    true => AccessType::Scan,
+                                    false => AccessType::Get,
+                                };
+                                let page = match pc.fetch_page_with(page_id, access_type) {
+                                    Ok(page) => page,
+                                    Err(Error::OutOfMemory) => continue,
+                                    Err(e) => panic!("seed {seed}: {e:?}"),
+                                };
+
+                                if page_id < PAGES && rng.gen_bool(0.5) {
+                                    let mut w = page.write();
+                                    let at = PAGE_HEADER_SIZE + 4 + t * 4;
+                                    let n = count(&w, t) + 1;
+                                    writep!(w, at..at + 4, &n.to_be_bytes());
+                                    writes[t].fetch_add(1, Relaxed);
+                                }
+
+                                let have = id(&page.read());
+                                assert!(
+                                    have == page_id,
+                                    "seed {seed}: want {page_id}, have {have}"
+                                );
+
+                                // Sometimes hold on to it for a while
+                                if rng.gen_bool(0.1) {
+                                    held.push(page);
+                                }
+                                if held.len() > 1 || rng.gen_bool(0.1) {
+                                    held.clear();
+                                }
+                            }
+                            60..=74 => {
+                                let page_ids = [page_id, page_id + 1, page_id + 2];
+                                pc.prefetch(&page_ids).expect("prefetch");
+                            }
+                            75..=84 => {
+                                pc.flush_page(page_id).expect("flush page");
+                            }
+                            85..=89 => {
+                                pc.flush_dirty(0.5, 2).expect("flush dirty");
+                            }
+                            _ if page_id >= PAGES => pc.remove_page(page_id),
+                            _ => {}
+                        }
+                    }
+                });
+            }
+        });
+
+        pc.assert_consistent();
+        let mut total = [0; THREADS];
+        for page_id in 0..REMOVABLE {
+            let page = pc.fetch_page(page_id)?;
+            let r = page.read();
+            assert!(id(&r) == page_id, "seed {seed}: page {page_id} has id {}", id(&r));
+            for (t, total) in total.iter_mut().enumerate() {
+                *total += count(&r, t) as usize;
+            }
+        }
+        for (t, total) in total.iter().enumerate() {
+            assert!(
+                *total == writes[t].load(Relaxed),
+                "seed {seed}: writes by thread {t} were lost"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_free_list() {
+        thread::scope(|s| {
+            const SIZE: usize = 8;
+            let list = Arc::new(FreeList::new(SIZE));
+
+            // Pop
+            let list_a = list.clone();
+            let a = s.spawn(move || {
+                let mut got = vec![];
+                for _ in 0..SIZE / 2 {
+                    got.push(list_a.pop().unwrap());
+                }
+
+                got
+            });
+
+            let list_b = list.clone();
+            let b = s.spawn(move || {
+                let mut got = vec![];
+                for _ in 4..SIZE {
+                    got.push(list_b.pop().unwrap());
+                }
+
+                got
+            });
+
+            let mut got = a.join().unwrap();
+            let got_b = b.join().unwrap();
+
+            got.extend(&got_b);
+            got.sort();
+
+            assert!(got == vec![0, 1, 2, 3, 4, 5, 6, 7], "Got: {got:?}");
+
+            // Push
+            let list_c = list.clone();
+            let c = s.spawn(move || {
+                for i in 4..8 {
+                    list_c.push(i);
+                }
+            });
+
+            let list_d = list.clone();
+            let d = s.spawn(move || {
+                for i in 8..12 {
+                    list_d.push(i);
+                }
+            });
+
+            c.join().unwrap();
+            d.join().unwrap();
+
+            let mut got = list.free().clone();
+            got.sort();
+
+            assert!(got == [4, 5, 6, 7, 8, 9, 10, 11]);
+        });
+    }
+
+    #[test]
+    fn test_pm_poisoned() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::new(Memory::new(), LRU::new(K), 0);
+        assert!(matches!(pc.fetch_page(-1), Err(Error::PageNotFound { page_id: -1 })));
+
+        let id = pc.new_page()?.id;
+        let before = b"before panic";
+        {
+            let page = pc.fetch_page(id)?;
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + before.len(), before);
+        }
+        pc.flush_all_pages()?;
+
+        let pc2 = pc.clone();
+        let have = thread::spawn(move || {
+            let page = pc2.fetch_page(id).unwrap();
+            let mut w = page.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, b"torn");
+            panic!("panicked holding page {id}");
+        })
+        .join();
+        assert!(have.is_err());
+
+        // The half written page isn't written back
+        pc.flush_all_pages()?;
+        let data = pc.disk().read_page(id)?;
+        assert!(&data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + before.len()] == before);
+
+        // It's read from disk again and usable from there
+        let want = b"after panic";
+        {
+            let page = pc.fetch_page(id)?;
+            let mut w = page.write();
+            assert!(&w.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + before.len()] == before);
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + want.len(), want);
+        }
+        pc.flush_all_pages()?;
+        pc.assert_consistent();
+
+        let data = pc.disk().read_page(id)?;
+        assert!(&data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + want.len()] == want);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pm_close() -> crate::Result<()> {
+        const FILE: &str = "test_pm_close.db";
+        const K: usize = 2;
+        let _cleanup = CleanUp::file(FILE);
+        let want = b"closed";
+
+        let pc = PageCache::new(FileSystem::new(FILE)?, LRU::new(K), 0);
+        let a = pc.new_page()?;
+        {
+            let mut w = a.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + want.len(), want);
+        }
+        // Never written, the segment should still remember it was allocated
+        drop(pc.new_page_in(1)?);
+        drop(pc.new_page_in(1)?);
+
+        // Gives up while a pin is held
+        assert!(matches!(pc.close(Duration::ZERO), Err(Error::Pinned { pins: 1 })));
+        assert!(matches!(pc.new_page(), Err(Error::Closed)));
+        assert!(matches!(pc.fetch_page(a.id), Err(Error::Closed)));
+
+        // Waits for the pin to be dropped
+        let id = a.id;
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(a);
+        });
+        pc.close(Duration::from_secs(10))?;
+        holder.join().unwrap();
+        assert!(pc.disk().segment_len(0)? == 1);
+        assert!(pc.disk().segment_len(1)? == 2);
+        drop(pc);
+
+        let pc = PageCache::new(FileSystem::new(FILE)?, LRU::new(K), 1);
+        {
+            let page = pc.fetch_page(id)?;
+            let r = page.read();
+            assert!(&r.data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + want.len()] == want);
+        }
+        // Segments with pages on disk aren't handed out again
+        assert!(pc.allocate_segment()? == 2);
+        assert!(pc.allocate_segment()? == 3);
+        assert!(page::segment_offset(pc.new_page_in(1)?.id) == 2);
+        assert!(
+            page::segment_page_id(1, page::SEGMENT_PAGES) == Err(Error::SegmentFull { segment: 1 })
+        );
+
+        // Dropping without closing writes back dirty pages that aren't pinned
+        let b = pc.new_page()?;
+        {
+            let mut w = b.write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + want.len(), want);
+        }
+        let id = b.id;
+        drop(b);
+        drop(pc);
+
+        let disk = FileSystem::new(FILE)?;
+        let data = disk.read_page(id)?;
+        assert!(&data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + want.len()] == want);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    fmt,
+    panic::Location,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    disk::Disk,
+    error::{Error, LockExt},
+    page::PageId,
+    page_cache::{FrameId, FrameMeta, PageCache},
+    replacer::Replacer,
+};
+
+#[derive(Default)]
+pub(super) struct PinTracker {
+    enabled: AtomicBool,
+    next_token: AtomicU64,
+}
+
+/// Where an outstanding pin was taken, recorded while `PageCache::track_pins` is on.
+#[derive(Debug, Clone)]
+pub struct PinHolder {
+    pub page_id: PageId,
+    pub frame_id: FrameId,
+    /// Caller of the `PageCache` method that returned the pin
+    pub location: &'static Location<'static>,
+    pub thread: String,
+    pub since: Instant,
+    /// Only captured if `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` is set
+    pub backtrace: Arc<Backtrace>,
+}
+
+impl fmt::Display for PinHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "page {} (frame {}) pinned at {} on thread {} {:?} ago",
+            self.page_id,
+            self.frame_id,
+            self.location,
+            self.thread,
+            self.since.elapsed()
+        )?;
+        if self.backtrace.status() == BacktraceStatus::Captured {
+            write!(f, "\n{}", self.backtrace)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Disk, R: Replacer> PageCache<D, R> {
+    /// Turns recording where pins are taken on or off. It costs a backtrace per pin if backtraces
+    /// are enabled, so it's meant for tests and debugging. Outstanding pins are reported when a
+    /// fetch runs out of frames and when the cache is dropped.
+    pub fn track_pins(&self, on: bool) {
+        self.pin_tracker.enabled.store(on, Relaxed);
+    }
+
+    /// Outstanding pins taken while tracking was on, oldest first.
+    pub fn pin_holders(&self) -> Vec<PinHolder> {
+        let frames = self.frames.read().unpoisoned().clone();
+
+        let mut ret = Vec::new();
+        for frame in frames.iter().flatten() {
+            ret.extend(frame.meta().holders.iter().cloned());
+        }
+        ret.sort_by_key(|(token, _)| *token);
+
+        ret.into_iter().map(|(_, h)| h).collect()
+    }
+
+    /// Records the caller as holding a new pin on frame `i`. Returns the token the pin removes its
+    /// record with, or `None` if tracking is off.
+    #[track_caller]
+    pub(super) fn track(&self, meta: &mut FrameMeta, i: FrameId, page_id: PageId) -> Option<u64> {
+        if !self.pin_tracker.enabled.load(Relaxed) {
+            return None;
+        }
+
+        let current = thread::current();
+        let holder = PinHolder {
+            page_id,
+            frame_id: i,
+            location: Location::caller(),
+            thread: match current.name() {
+                Some(name) => name.into(),
+                None => format!("{:?}", current.id()),
+            },
+            since: Instant::now(),
+            backtrace: Arc::new(Backtrace::capture()),
+        };
+
+        let token = self.pin_tracker.next_token.fetch_add(1, Relaxed);
+        meta.holders.push((token, holder));
+
+        Some(token)
+    }
+
+    /// Reports the outstanding pins and returns `OutOfMemory`.
+    pub(super) fn out_of_memory(&self) -> Error {
+        self.report_pins("out of frames");
+
+        Error::OutOfMemory
+    }
+
+    pub(super) fn report_pins(&self, why: &str) {
+        let holders = self.pin_holders();
+        if holders.is_empty() {
+            return;
+        }
+
+        eprintln!("WARN: page cache {why}, {} pins outstanding:", holders.len());
+        for holder in holders {
+            eprintln!("    {holder}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{disk::Memory, error::Error, page_cache::PageCache, replacer::LRU};
+
+    #[test]
+    fn test_pin_holders() -> crate::Result<()> {
+        const K: usize = 2;
+        let pc = PageCache::with_capacity(Memory::new(), LRU::new(K), 0, 3);
+
+        // Not tracked
+        let a = pc.new_page()?;
+        assert!(pc.pin_holders().is_empty());
+
+        pc.track_pins(true);
+        let b = pc.new_page()?;
+        let line = line!() - 1;
+        let b2 = pc.fetch_page(b.id)?;
+        let c = std::thread::Builder::new()
+            .name("holder".into())
+            .spawn({
+                let pc = pc.clone();
+                move || pc.new_page()
+            })
+            .unwrap()
+            .join()
+            .unwrap()?;
+
+        let holders = pc.pin_holders();
+        assert!(holders.len() == 3);
+        assert!(holders[0].page_id == b.id && holders[0].location.file() == file!());
+        assert!(holders[0].location.line() == line);
+        assert!(holders[1].page_id == b.id && holders[1].location.line() == line + 2);
+        assert!(holders[2].page_id == c.id && holders[2].thread == "holder");
+
+        // Every frame is pinned
+        assert!(matches!(pc.new_page(), Err(Error::OutOfMemory)));
+
+        drop(b);
+        let holders = pc.pin_holders();
+        assert!(holders.len() == 2 && holders[0].location.line() == line + 2);
+
+        drop((a, b2, c));
+        assert!(pc.pin_holders().is_empty());
+        pc.new_page()?;
+        assert!(pc.pin_holders().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::Instant,
+};
+
+use crate::{
+    disk::Disk,
+    error::LockExt,
+    page::{PageId, PageType},
+    page_cache::{FrameId, FrameState, PageCache},
+    replacer::Replacer,
+};
+
+/// Counted since the cache was created.
+#[derive(Default)]
+pub(super) struct Counters {
+    pub hits: AtomicU64,
+    pub misses: AtomicU64,
+    pub prefetched: AtomicU64,
+    pub evictions: AtomicU64,
+    pub writebacks: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// Fetches of pages that were already resident
+    pub hits: u64,
+    /// Fetches that read the page from disk
+    pub misses: u64,
+    /// Pages read by `prefetch`
+    pub prefetched: u64,
+    /// Pages evicted to make room for others
+    pub evictions: u64,
+    /// Pages written to disk, by evictions, flushes and the flusher
+    pub writebacks: u64,
+    pub capacity: usize,
+    /// Frames holding a page
+    pub resident: usize,
+    pub pinned: usize,
+    pub dirty: usize,
+}
+
+impl Stats {
+    /// Share of fetches that were hits, 0 if there haven't been any.
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+/// A frame as it was when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    pub frame_id: FrameId,
+    /// `None` unless a page is resident in the frame
+    pub page_id: Option<PageId>,
+    pub page_type: PageType,
+    pub pins: usize,
+    /// `None` if the page was write locked
+    pub dirty: Option<bool>,
+    pub last_access: Option<Instant>,
+}
+
+impl<D: Disk, R: Replacer> PageCache<D, R> {
+    pub fn stats(&self) -> Stats {
+        let mut ret = Stats {
+            hits: self.counters.hits.load(Relaxed),
+            misses: self.counters.misses.load(Relaxed),
+            prefetched: self.counters.prefetched.load(Relaxed),
+            evictions: self.counters.evictions.load(Relaxed),
+            writebacks: self.counters.writebacks.load(Relaxed),
+            capacity: self.capacity(),
+            ..Default::default()
+        };
+
+        for frame in self.snapshot() {
+            ret.resident += frame.page_id.is_some() as usize;
+            ret.pinned += (frame.pins > 0) as usize;
+            ret.dirty += (frame.dirty == Some(true)) as usize;
+        }
+
+        ret
+    }
+
+    /// Every frame in the cache. Write locked pages are reported without their dirty flag instead
+    /// of waiting for the lock, so it's safe to call while holding pins.
+    pub fn snapshot(&self) -> Vec<FrameInfo> {
+        let frames = self.frames.read().unpoisoned().clone();
+
+        let mut ret = Vec::with_capacity(frames.len());
+        for (i, frame) in frames.iter().enumerate() {
+            let Some(frame) = frame else {
+                continue;
+            };
+
+            let meta = frame.meta();
+            let (page_id, pins, last_access) = match meta.state {
+                FrameState::Resident(page_id) => (Some(page_id), meta.pins, meta.last_access),
+                _ => (None, meta.pins, None),
+            };
+            drop(meta);
+
+            let dirty = match page_id {
+                Some(page_id) => frame
+                    .page
+                    .try_read()
+                    .filter(|r| r.id == page_id)
+                    .map(|r| r.dirty),
+                None => Some(false),
+            };
+
+            ret.push(FrameInfo {
+                frame_id: i,
+                page_id,
+                page_type: match page_id {
+                    Some(_) => PageType::from(frame.ty.load(Relaxed)),
+                    None => PageType::Unknown,
+                },
+                pins,
+                dirty,
+                last_access,
+            });
+        }
+
+        ret
+    }
+
+    /// Number of resident pages of each type.
+    pub fn page_types(&self) -> HashMap<PageType, usize> {
+        let mut ret = HashMap::new();
+        for frame in self.snapshot() {
+            if frame.page_id.is_some() {
+                *ret.entry(frame.page_type).or_default() += 1;
+            }
+        }
+
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        disk::Memory,
+        page::{PageType, PAGE_HEADER_SIZE},
+        page_cache::PageCache,
+        replacer::LRU,
+        writep,
+    };
+
+    #[test]
+    fn test_stats() -> crate::Result<()> {
+        const K: usize = 2;
+        let replacer = LRU::new(K);
+        let pc = PageCache::with_capacity(Memory::new(), replacer.clone(), 0, 4);
+
+        let mut pages = Vec::new();
+        for _ in 0..4 {
+            pages.push(pc.new_page()?);
+        }
+        pages[0].set_type(PageType::Table);
+        pages[1].set_type(PageType::BTreeLeaf);
+        {
+            let mut w = pages[1].write();
+            writep!(w, PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4, &[1; 4]);
+        }
+
+        let stats = pc.stats();
+        assert!(stats.misses == 4 && stats.hits == 0, "{stats:?}");
+        assert!(stats.resident == 4 && stats.pinned == 4 && stats.dirty == 1, "{stats:?}");
+
+        let snapshot = pc.snapshot();
+        let frame = snapshot
+            .iter()
+            .find(|f| f.page_id == Some(pages[1].id))
+            .expect("page should be resident");
+        assert!(frame.pins == 1 && frame.dirty == Some(true) && frame.last_access.is_some());
+        assert!(frame.page_type == PageType::BTreeLeaf);
+
+        let types = pc.page_types();
+        assert!(types[&PageType::Table] == 1);
+        assert!(types[&PageType::BTreeLeaf] == 1);
+        assert!(types[&PageType::Unknown] == 2);
+
+        // Hits don't reset the type, loading another page into the frame does
+        let id = pages[0].id;
+        drop(pages);
+        drop(pc.fetch_page(id)?);
+        assert!(pc.page_types()[&PageType::Table] == 1);
+
+        // Evict every page, the one that was hit first as it has a K-distance, writing back the
+        // dirty one
+        for _ in 0..4 {
+            pc.new_page()?;
+        }
+
+        let stats = pc.stats();
+        assert!(stats.hits == 1 && stats.misses == 8, "{stats:?}");
+        assert!(stats.evictions == 4 && stats.writebacks == 1, "{stats:?}");
+        assert!(stats.pinned == 0 && stats.dirty == 0, "{stats:?}");
+        assert!(stats.hit_rate() == 1.0 / 9.0);
+        assert!(pc.page_types() == [(PageType::Unknown, 4)].into());
+
+        let replacer = replacer.stats();
+        assert!(replacer.accesses == 9 && replacer.evictions == 4, "{replacer:?}");
+        assert!(replacer.tracked == 4 && replacer.evictable == 4, "{replacer:?}");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use crate::{
+    page::PageId,
+    page_cache::FrameId,
+    replacer::{AccessType, Pins, Queue, Replacer},
+};
+
+/// Adaptive Replacement Cache (Megiddo and Modha). Balances between pages seen once and pages seen
+/// more than once, using the history of recently evicted pages to adapt the target size of each.
+#[derive(Default, Debug)]
+pub struct ARCReplacer {
+    /// Frames whose page was seen once recently, least recently used first
+    t1: Queue<FrameId>,
+    /// Frames whose page was seen at least twice recently
+    t2: Queue<FrameId>,
+    /// Pages evicted from `t1`
+    b1: Queue<PageId>,
+    /// Pages evicted from `t2`
+    b2: Queue<PageId>,
+    /// Target size of `t1`
+    p: usize,
+    pages: HashMap<FrameId, PageId>,
+    /// Most frames tracked at once
+    capacity: usize,
+    pins: Pins,
+}
+
+impl Replacer for ARCReplacer {
+    fn record_access(&mut self, i: FrameId, page_id: PageId, access_type: AccessType) {
+        let scan = access_type == AccessType::Scan;
+        match self.pages.get(&i) {
+            Some(p) if *p == page_id => {
+                if !scan && (self.t1.remove(i) || self.t2.contains(i)) {
+                    self.t2.push_back(i);
+                }
+
+                return;
+            }
+            Some(_) => self.remove(i),
+            None => {}
+        }
+
+        self.pages.insert(i, page_id);
+        self.capacity = self.capacity.max(self.pages.len());
+
+        if scan {
+            // Scanned pages don't adapt the target, they're seen once by definition
+            self.b1.remove(page_id);
+            self.b2.remove(page_id);
+            self.t1.push_back(i);
+            return;
+        }
+
+        let (b1, b2) = (self.b1.len(), self.b2.len());
+        if self.b1.remove(page_id) {
+            // Evicted from t1 too early, give t1 more room
+            let delta = if b1 >= b2 { 1 } else { b2 / b1 };
+            self.p = (self.p + delta).min(self.capacity);
+            self.t2.push_back(i);
+        } else if self.b2.remove(page_id) {
+            let delta = if b2 >= b1 { 1 } else { b1 / b2 };
+            self.p = self.p.saturating_sub(delta);
+            self.t2.push_back(i);
+        } else {
+            self.t1.push_back(i);
+        }
+    }
+
+    fn pin(&mut self, i: FrameId) {
+        self.pins.pin(i);
+    }
+
+    fn unpin(&mut self, i: FrameId) {
+        self.pins.unpin(i);
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        let t1 = self.pins.first_unpinned(&self.t1);
+        let t2 = self.pins.first_unpinned(&self.t2);
+
+        if self.t1.len() > self.p {
+            t1.or(t2)
+        } else {
+            t2.or(t1)
+        }
+    }
+
+    fn remove(&mut self, i: FrameId) {
+        self.pins.remove(i);
+        let Some(page_id) = self.pages.remove(&i) else {
+            return;
+        };
+
+        if self.t1.remove(i) {
+            self.b1.push_back(page_id);
+        } else if self.t2.remove(i) {
+            self.b2.push_back(page_id);
+        }
+
+        // Keep the history to at most `capacity` pages on each side
+        let c = self.capacity;
+        while self.t1.len() + self.b1.len() > c {
+            self.b1.pop_front();
+        }
+        while self.t1.len() + self.t2.len() + self.b1.len() + self.b2.len() > c * 2 {
+            if self.b2.pop_front().is_none() {
+                self.b1.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::replacer::{ARCReplacer, AccessType, Replacer};
+
+    #[test]
+    fn test_arc() {
+        let mut replacer = ARCReplacer::default();
+        for i in 0..4 {
+            replacer.record_access(i, i as i32, AccessType::Get);
+        }
+
+        // A second access moves the page to t2, t1 is evicted first while p is 0
+        replacer.record_access(0, 0, AccessType::Get);
+        assert!(replacer.evict() == Some(1));
+        replacer.remove(1);
+        assert!(replacer.b1.contains(1));
+
+        // A hit in b1 grows t1's target and brings the page back in t2
+        replacer.record_access(1, 1, AccessType::Get);
+        assert!(replacer.p == 1);
+        assert!(replacer.t2.contains(1));
+
+        // t1 = [2, 3] is over target so it's still evicted first
+        assert!(replacer.evict() == Some(2));
+        replacer.pin(2);
+        replacer.pin(3);
+        assert!(replacer.evict() == Some(0));
+
+        for i in [0, 1] {
+            replacer.pin(i);
+        }
+        assert!(replacer.evict().is_none());
+    }
+}
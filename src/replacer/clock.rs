@@ -0,0 +1,149 @@
+use crate::{
+    page::PageId,
+    page_cache::FrameId,
+    replacer::{AccessType, Pins, Queue, Replacer},
+};
+
+/// Second chance replacement. The hand sweeps over the frames clearing reference bits and evicts
+/// the first unpinned frame that wasn't referenced since the last sweep.
+///
+/// Frames loaded by a scan are kept in a separate ring and evicted first, so a scan reuses its own
+/// frames instead of sweeping the working set away.
+#[derive(Default, Debug)]
+pub struct ClockReplacer {
+    /// Reference bit of each frame, `None` if the frame isn't tracked
+    frames: Vec<Option<bool>>,
+    hand: usize,
+    /// Frames only accessed by scans, oldest first
+    scans: Queue<FrameId>,
+    pins: Pins,
+}
+
+impl Replacer for ClockReplacer {
+    fn record_access(&mut self, i: FrameId, _page_id: PageId, access_type: AccessType) {
+        if i >= self.frames.len() {
+            self.frames.resize(i + 1, None);
+        }
+
+        match access_type {
+            AccessType::Get => {
+                self.scans.remove(i);
+                self.frames[i] = Some(true);
+            }
+            AccessType::Scan if self.frames[i].is_none() => {
+                self.scans.push_back(i);
+                self.frames[i] = Some(false);
+            }
+            // Scanning a resident page doesn't give it a second chance
+            AccessType::Scan => {}
+        }
+    }
+
+    fn pin(&mut self, i: FrameId) {
+        self.pins.pin(i);
+    }
+
+    fn unpin(&mut self, i: FrameId) {
+        self.pins.unpin(i);
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        if let Some(i) = self.evict_scanned() {
+            return Some(i);
+        }
+
+        let len = self.frames.len();
+
+        // The first sweep may only clear reference bits
+        for _ in 0..len * 2 {
+            let i = self.hand;
+            self.hand = (self.hand + 1) % len;
+
+            if self.pins.is_pinned(i) {
+                continue;
+            }
+
+            match &mut self.frames[i] {
+                Some(referenced) if *referenced => *referenced = false,
+                Some(_) => return Some(i),
+                None => {}
+            }
+        }
+
+        None
+    }
+
+    fn evict_scanned(&mut self) -> Option<FrameId> {
+        self.pins.first_unpinned(&self.scans)
+    }
+
+    fn remove(&mut self, i: FrameId) {
+        if let Some(frame) = self.frames.get_mut(i) {
+            *frame = None;
+        }
+
+        self.scans.remove(i);
+        self.pins.remove(i);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::replacer::{AccessType, ClockReplacer, Replacer};
+
+    #[test]
+    fn test_clock() {
+        let mut replacer = ClockReplacer::default();
+        for i in 0..4 {
+            replacer.record_access(i, i as i32, AccessType::Get);
+        }
+
+        // Everything was referenced, so the hand goes all the way around once
+        assert!(replacer.evict() == Some(0));
+        replacer.remove(0);
+        replacer.record_access(0, 4, AccessType::Get);
+
+        // Frame 1 gets a second chance, 2 doesn't
+        replacer.record_access(1, 1, AccessType::Get);
+        assert!(replacer.evict() == Some(2));
+
+        // Frame 1's bit was cleared on the way past, frame 0's is cleared now
+        replacer.pin(3);
+        replacer.remove(2);
+        assert!(replacer.evict() == Some(1));
+        replacer.remove(1);
+        assert!(replacer.evict() == Some(0));
+        replacer.remove(0);
+        assert!(replacer.evict().is_none());
+
+        replacer.unpin(3);
+        assert!(replacer.evict() == Some(3));
+    }
+
+    #[test]
+    fn test_clock_scan() {
+        let mut replacer = ClockReplacer::default();
+        for i in 0..4 {
+            replacer.record_access(i, i as i32, AccessType::Get);
+        }
+        for i in 4..6 {
+            replacer.record_access(i, i as i32, AccessType::Scan);
+        }
+
+        // Scanned frames go first without the hand moving, scanning a resident frame doesn't
+        // reference it
+        replacer.record_access(0, 0, AccessType::Scan);
+        for page_id in 6..20 {
+            let i = replacer.evict().unwrap();
+            assert!(i == 4 || i == 5, "evicted frame {i} during a scan");
+            replacer.remove(i);
+            replacer.record_access(i, page_id, AccessType::Scan);
+        }
+
+        // Getting a scanned frame moves it into the clock
+        replacer.record_access(4, 18, AccessType::Get);
+        assert!(replacer.evict() == Some(5));
+        replacer.remove(5);
+        assert!(replacer.evict() == Some(0));
+    }
+}
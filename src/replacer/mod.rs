@@ -0,0 +1,547 @@
+pub mod arc;
+pub mod clock;
+pub mod two_q;
+
+use std::{
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, VecDeque},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+use crate::{error::LockExt, page::PageId, page_cache::FrameId};
+
+pub use arc::ARCReplacer;
+pub use clock::ClockReplacer;
+pub use two_q::TwoQReplacer;
+
+/// Decides which frame the page cache evicts next.
+///
+/// Frames are tracked from their first `record_access` until `remove`. `evict` picks an unpinned
+/// tracked frame but keeps tracking it, the page cache removes it once the page is swapped out.
+pub trait Replacer {
+    /// Records an access to `page_id`, which is resident in frame `i`.
+    fn record_access(&mut self, i: FrameId, page_id: PageId, access_type: AccessType);
+    fn pin(&mut self, i: FrameId);
+    fn unpin(&mut self, i: FrameId);
+    fn evict(&mut self) -> Option<FrameId>;
+    /// Like `evict`, but only picks frames that were loaded by a scan and haven't been accessed
+    /// otherwise since. Prefetching uses it so it can't push out the working set.
+    fn evict_scanned(&mut self) -> Option<FrameId> {
+        None
+    }
+    fn remove(&mut self, i: FrameId);
+}
+
+#[derive(Debug)]
+struct LRUKNode {
+    /// The last K access timestamps, oldest first
+    history: VecDeque<u64>,
+    /// Only accessed by scans so far
+    scan: bool,
+    pin: u64,
+}
+
+impl LRUKNode {
+    pub fn new(k: usize, scan: bool) -> Self {
+        Self {
+            history: VecDeque::with_capacity(k),
+            scan,
+            pin: 0,
+        }
+    }
+
+    fn push(&mut self, k: usize, ts: u64) {
+        if self.history.len() == k {
+            self.history.pop_front();
+        }
+        self.history.push_back(ts);
+    }
+
+    /// Position of the node in the eviction order. Frames only accessed by scans go first. Then
+    /// frames with K accesses, the largest K-distance between their oldest and latest access
+    /// first. The rest, with fewer than K accesses or a distance of 0, go last ordered by their
+    /// most recent access.
+    fn key(&self, k: usize) -> (u8, u64) {
+        let last = *self.history.back().expect("nodes have at least one access");
+        let first = *self
+            .history
+            .front()
+            .expect("nodes have at least one access");
+        if self.scan {
+            (0, last)
+        } else if self.history.len() == k && last > first {
+            (1, u64::MAX - (last - first))
+        } else {
+            (2, last)
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct LRUKReplacer {
+    nodes: HashMap<FrameId, LRUKNode>,
+    /// Unpinned frames in eviction order
+    evictable: BTreeSet<((u8, u64), FrameId)>,
+    current_ts: u64,
+    k: usize,
+    stats: ReplacerStats,
+}
+
+/// Counted since the replacer was created, see `LRU::stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplacerStats {
+    pub accesses: u64,
+    /// Accesses by scans, included in `accesses`
+    pub scans: u64,
+    /// Frames picked by `evict` and `evict_scanned`
+    pub evictions: u64,
+    /// Picked frames that only scans had accessed, included in `evictions`
+    pub scan_evictions: u64,
+    /// Frames being tracked, as of now
+    pub tracked: usize,
+    /// Tracked frames that aren't pinned, as of now
+    pub evictable: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessType {
+    Get,
+    /// Part of a sequential scan. Scans load pages with low priority and don't promote pages that
+    /// are already resident, so they can't flush the working set.
+    Scan,
+}
+
+impl LRUKReplacer {
+    pub fn new(k: usize) -> Self {
+        assert!(k > 0, "k must be at least 1");
+
+        Self {
+            k,
+            ..Default::default()
+        }
+    }
+
+    pub fn stats(&self) -> ReplacerStats {
+        ReplacerStats {
+            tracked: self.nodes.len(),
+            evictable: self.evictable.len(),
+            ..self.stats
+        }
+    }
+
+    fn picked(&mut self, victim: Option<((u8, u64), FrameId)>) -> Option<FrameId> {
+        let ((class, _), i) = victim?;
+        self.stats.evictions += 1;
+        if class == 0 {
+            self.stats.scan_evictions += 1;
+        }
+
+        Some(i)
+    }
+}
+
+impl Replacer for LRUKReplacer {
+    fn evict(&mut self) -> Option<FrameId> {
+        let victim = self.evictable.first().copied();
+        self.picked(victim)
+    }
+
+    fn evict_scanned(&mut self) -> Option<FrameId> {
+        let victim = self
+            .evictable
+            .first()
+            .filter(|((class, _), _)| *class == 0)
+            .copied();
+        self.picked(victim)
+    }
+
+    fn record_access(&mut self, i: FrameId, _page_id: PageId, access_type: AccessType) {
+        let scan = access_type == AccessType::Scan;
+        self.stats.accesses += 1;
+        self.stats.scans += scan as u64;
+        let node = self
+            .nodes
+            .entry(i)
+            .or_insert_with(|| LRUKNode::new(self.k, scan));
+        if scan && !node.scan {
+            return;
+        }
+
+        let evictable = node.pin == 0;
+        if evictable && !node.history.is_empty() {
+            self.evictable.remove(&(node.key(self.k), i));
+        }
+
+        if node.scan && !scan {
+            // Accesses by the scan don't count towards K
+            node.scan = false;
+            node.history.clear();
+        }
+        node.push(self.k, self.current_ts);
+        self.current_ts += 1;
+
+        if evictable {
+            self.evictable.insert((node.key(self.k), i));
+        }
+    }
+
+    fn pin(&mut self, i: FrameId) {
+        if let Some(node) = self.nodes.get_mut(&i) {
+            if node.pin == 0 {
+                self.evictable.remove(&(node.key(self.k), i));
+            }
+            node.pin += 1;
+        }
+    }
+
+    fn unpin(&mut self, i: FrameId) {
+        if let Some(node) = self.nodes.get_mut(&i) {
+            node.pin -= 1;
+            if node.pin == 0 {
+                self.evictable.insert((node.key(self.k), i));
+            }
+        }
+    }
+
+    fn remove(&mut self, i: FrameId) {
+        match self.nodes.entry(i) {
+            Entry::Occupied(node) => {
+                let pins = node.get().pin;
+                if pins != 0 {
+                    eprintln!("WARN: frame {} is still pinned, {} pins", i, pins);
+                } else {
+                    self.evictable.remove(&(node.get().key(self.k), i));
+                }
+
+                node.remove();
+            }
+            Entry::Vacant(_) => {}
+        }
+    }
+}
+
+/// Replacer shared between the page cache and its pins.
+///
+/// The frames can be split between several replacers, each behind its own lock, so threads using
+/// different frames don't contend. Frame `i` belongs to shard `i % shards`. Each shard only orders
+/// its own frames, eviction takes the victim from the next shard that has one.
+pub struct Shared<R: Replacer = LRUKReplacer> {
+    shards: Vec<Mutex<R>>,
+    /// Shard the next eviction starts from
+    next: AtomicUsize,
+}
+
+pub type LRU = Shared<LRUKReplacer>;
+pub type Clock = Shared<ClockReplacer>;
+pub type TwoQ = Shared<TwoQReplacer>;
+pub type ARC = Shared<ARCReplacer>;
+
+impl LRU {
+    pub fn new(k: usize) -> Arc<Self> {
+        Self::wrap(LRUKReplacer::new(k))
+    }
+
+    pub fn with_shards(k: usize, shards: usize) -> Arc<Self> {
+        Self::sharded((0..shards).map(|_| LRUKReplacer::new(k)).collect())
+    }
+
+    /// Stats summed over the shards.
+    pub fn stats(&self) -> ReplacerStats {
+        self.shards
+            .iter()
+            .fold(ReplacerStats::default(), |acc, shard| {
+                let s = shard.lock().unpoisoned().stats();
+                ReplacerStats {
+                    accesses: acc.accesses + s.accesses,
+                    scans: acc.scans + s.scans,
+                    evictions: acc.evictions + s.evictions,
+                    scan_evictions: acc.scan_evictions + s.scan_evictions,
+                    tracked: acc.tracked + s.tracked,
+                    evictable: acc.evictable + s.evictable,
+                }
+            })
+    }
+}
+
+impl Clock {
+    pub fn new() -> Arc<Self> {
+        Self::wrap(ClockReplacer::default())
+    }
+}
+
+impl TwoQ {
+    pub fn new() -> Arc<Self> {
+        Self::wrap(TwoQReplacer::default())
+    }
+}
+
+impl ARC {
+    pub fn new() -> Arc<Self> {
+        Self::wrap(ARCReplacer::default())
+    }
+}
+
+impl<R: Replacer> Shared<R> {
+    pub fn wrap(replacer: R) -> Arc<Self> {
+        Self::sharded(vec![replacer])
+    }
+
+    pub fn sharded(shards: Vec<R>) -> Arc<Self> {
+        assert!(!shards.is_empty(), "need at least one shard");
+
+        Arc::new(Self {
+            shards: shards.into_iter().map(Mutex::new).collect(),
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Locks the shard frame `i` belongs to.
+    pub fn lock(&self, i: FrameId) -> MutexGuard<'_, R> {
+        self.shards[i % self.shards.len()].lock().unpoisoned()
+    }
+
+    /// Frames loaded by scans are taken from any shard before other frames are considered.
+    pub fn evict(&self) -> Option<FrameId> {
+        self.evict_scanned().or_else(|| self.find(|r| r.evict()))
+    }
+
+    pub fn evict_scanned(&self) -> Option<FrameId> {
+        self.find(|r| r.evict_scanned())
+    }
+
+    /// First frame `f` picks, trying the shards in turn starting from a different one each time.
+    fn find(&self, f: impl Fn(&mut R) -> Option<FrameId>) -> Option<FrameId> {
+        let start = self.next.fetch_add(1, Relaxed);
+        (0..self.shards.len()).find_map(|n| {
+            let shard = &self.shards[(start + n) % self.shards.len()];
+            f(&mut shard.lock().unpoisoned())
+        })
+    }
+
+    pub fn record_access(&self, i: FrameId, page_id: PageId, a: AccessType) {
+        self.lock(i).record_access(i, page_id, a)
+    }
+
+    pub fn pin(&self, i: FrameId) {
+        self.lock(i).pin(i)
+    }
+
+    pub fn unpin(&self, i: FrameId) {
+        self.lock(i).unpin(i)
+    }
+
+    pub fn remove(&self, i: FrameId) {
+        self.lock(i).remove(i)
+    }
+}
+
+/// Items in the order they were pushed, with removal from anywhere in O(log n).
+#[derive(Debug)]
+struct Queue<T> {
+    order: BTreeMap<u64, T>,
+    index: HashMap<T, u64>,
+    next: u64,
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self {
+            order: BTreeMap::new(),
+            index: HashMap::new(),
+            next: 0,
+        }
+    }
+}
+
+impl<T: Hash + Eq + Copy> Queue<T> {
+    /// Pushes `t` to the back, moving it there if it's already queued.
+    fn push_back(&mut self, t: T) {
+        self.remove(t);
+        self.order.insert(self.next, t);
+        self.index.insert(t, self.next);
+        self.next += 1;
+    }
+
+    fn pop_front(&mut self) -> Option<T> {
+        let (_, t) = self.order.pop_first()?;
+        self.index.remove(&t);
+
+        Some(t)
+    }
+
+    fn remove(&mut self, t: T) -> bool {
+        match self.index.remove(&t) {
+            Some(seq) => {
+                self.order.remove(&seq);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn contains(&self, t: T) -> bool {
+        self.index.contains_key(&t)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Front to back.
+    fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        self.order.values().copied()
+    }
+}
+
+/// Pin counts of frames, shared by the queue based replacers.
+#[derive(Default, Debug)]
+struct Pins(HashMap<FrameId, u64>);
+
+impl Pins {
+    fn pin(&mut self, i: FrameId) {
+        *self.0.entry(i).or_default() += 1;
+    }
+
+    fn unpin(&mut self, i: FrameId) {
+        if let Entry::Occupied(mut entry) = self.0.entry(i) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+
+    fn is_pinned(&self, i: FrameId) -> bool {
+        self.0.contains_key(&i)
+    }
+
+    fn remove(&mut self, i: FrameId) {
+        if let Some(pins) = self.0.remove(&i) {
+            eprintln!("WARN: frame {} is still pinned, {} pins", i, pins);
+        }
+    }
+
+    /// First unpinned frame in `queue`.
+    fn first_unpinned(&self, queue: &Queue<FrameId>) -> Option<FrameId> {
+        queue.iter().find(|i| !self.is_pinned(*i))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AccessType, LRUKReplacer, Replacer, LRU};
+    use crate::page::PageId;
+
+    #[test]
+    fn test_evict() {
+        const K: usize = 2;
+        let replacer = LRU::new(K);
+
+        {
+            for i in 0..8 {
+                replacer.remove(i);
+                replacer.record_access(i, i as PageId, AccessType::Get);
+                replacer.pin(i);
+            }
+
+            for i in (0..8).rev() {
+                replacer.unpin(i);
+
+                let have = replacer.evict();
+                let want = Some(i);
+                assert!(want == have, "Want: {want:?}, Have: {have:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_evict_k_distance() {
+        const K: usize = 2;
+        let mut replacer = LRUKReplacer::new(K);
+
+        for i in [0, 1, 2, 0, 3, 1] {
+            replacer.record_access(i, i as PageId, AccessType::Get);
+        }
+
+        // Frames with K accesses go first, the largest distance between them first
+        assert!(replacer.evict() == Some(1));
+        replacer.remove(1);
+        assert!(replacer.evict() == Some(0));
+        replacer.remove(0);
+
+        // Then frames with fewer than K accesses, least recently used first
+        assert!(replacer.evict() == Some(2));
+
+        // A frame with K accesses goes before the older frame with fewer
+        replacer.record_access(3, 3, AccessType::Get);
+        assert!(replacer.evict() == Some(3));
+
+        replacer.pin(3);
+        assert!(replacer.evict() == Some(2));
+        replacer.pin(2);
+        assert!(replacer.evict().is_none());
+        replacer.unpin(3);
+        assert!(replacer.evict() == Some(3));
+
+        // Only the last K accesses are kept
+        for _ in 0..1000 {
+            replacer.record_access(1, 1, AccessType::Get);
+        }
+        assert!(replacer.nodes[&1].history.len() == K);
+    }
+
+    #[test]
+    fn test_evict_order() {
+        const K: usize = 2;
+        let mut replacer = LRUKReplacer::new(K);
+
+        // 0 and 1 have K accesses, 0 spread the furthest apart
+        for i in [1, 0, 1, 2, 0] {
+            replacer.record_access(i, i as PageId, AccessType::Get);
+        }
+
+        // The largest K-distance goes first, frames with fewer than K accesses only after that
+        assert!(replacer.evict() == Some(0));
+        replacer.remove(0);
+        assert!(replacer.evict() == Some(1));
+        replacer.remove(1);
+        assert!(replacer.evict() == Some(2));
+    }
+
+    #[test]
+    fn test_evict_scan() {
+        const K: usize = 2;
+        let mut replacer = LRUKReplacer::new(K);
+
+        // Hot frames
+        for i in [1, 0, 0, 1] {
+            replacer.record_access(i, i as PageId, AccessType::Get);
+        }
+
+        // Scans go first, even though they're more recent
+        for i in 2..6 {
+            replacer.record_access(i, i as PageId, AccessType::Scan);
+        }
+        replacer.record_access(0, 0, AccessType::Scan);
+        for i in 2..6 {
+            assert!(replacer.evict() == Some(i));
+            replacer.remove(i);
+        }
+
+        // Scanning frame 0 didn't count as an access, which would have put it before frame 1
+        assert!(replacer.evict() == Some(1));
+
+        // A get after a scan promotes the frame, the scan doesn't count towards K
+        replacer.record_access(2, 2, AccessType::Scan);
+        replacer.record_access(2, 2, AccessType::Get);
+        assert!(replacer.nodes[&2].history.len() == 1);
+        assert!(replacer.evict_scanned().is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::{
+    page::PageId,
+    page_cache::FrameId,
+    replacer::{AccessType, Pins, Queue, Replacer},
+};
+
+/// Full 2Q (Johnson and Shasha). Pages start in a FIFO queue and only move to the main LRU queue if
+/// they're accessed again after being evicted from it, so a single scan can't flush hot pages.
+///
+/// Pages loaded by `AccessType::Scan` go in their own queue which is evicted first and never
+/// remembered in `a1out`.
+#[derive(Default, Debug)]
+pub struct TwoQReplacer {
+    /// Frames only accessed by scans, oldest first
+    scans: Queue<FrameId>,
+    /// Frames whose page was loaded recently, oldest first
+    a1in: Queue<FrameId>,
+    /// Frames whose page is hot, least recently used first
+    am: Queue<FrameId>,
+    /// Pages evicted from `a1in`, oldest first
+    a1out: Queue<PageId>,
+    pages: HashMap<FrameId, PageId>,
+    /// Most frames tracked at once, the queue sizes are relative to it
+    capacity: usize,
+    pins: Pins,
+}
+
+impl TwoQReplacer {
+    fn kin(&self) -> usize {
+        (self.capacity / 4).max(1)
+    }
+
+    fn kout(&self) -> usize {
+        (self.capacity / 2).max(1)
+    }
+}
+
+impl Replacer for TwoQReplacer {
+    fn record_access(&mut self, i: FrameId, page_id: PageId, access_type: AccessType) {
+        let scan = access_type == AccessType::Scan;
+        match self.pages.get(&i) {
+            Some(p) if *p == page_id => {
+                if scan {
+                    return;
+                }
+
+                // Accesses while in `a1in` are assumed to be correlated and don't count
+                if self.am.contains(i) {
+                    self.am.push_back(i);
+                } else if self.scans.remove(i) {
+                    self.a1in.push_back(i);
+                }
+
+                return;
+            }
+            Some(_) => self.remove(i),
+            None => {}
+        }
+
+        self.pages.insert(i, page_id);
+        self.capacity = self.capacity.max(self.pages.len());
+
+        if scan {
+            self.scans.push_back(i);
+        } else if self.a1out.remove(page_id) {
+            self.am.push_back(i);
+        } else {
+            self.a1in.push_back(i);
+        }
+    }
+
+    fn pin(&mut self, i: FrameId) {
+        self.pins.pin(i);
+    }
+
+    fn unpin(&mut self, i: FrameId) {
+        self.pins.unpin(i);
+    }
+
+    fn evict(&mut self) -> Option<FrameId> {
+        if let Some(i) = self.evict_scanned() {
+            return Some(i);
+        }
+
+        let a1in = self.pins.first_unpinned(&self.a1in);
+        let am = self.pins.first_unpinned(&self.am);
+
+        if self.a1in.len() > self.kin() {
+            a1in.or(am)
+        } else {
+            am.or(a1in)
+        }
+    }
+
+    fn evict_scanned(&mut self) -> Option<FrameId> {
+        self.pins.first_unpinned(&self.scans)
+    }
+
+    fn remove(&mut self, i: FrameId) {
+        self.pins.remove(i);
+        let Some(page_id) = self.pages.remove(&i) else {
+            return;
+        };
+
+        if self.a1in.remove(i) {
+            self.a1out.push_back(page_id);
+            while self.a1out.len() > self.kout() {
+                self.a1out.pop_front();
+            }
+        }
+        self.am.remove(i);
+        self.scans.remove(i);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::replacer::{AccessType, Replacer, TwoQReplacer};
+
+    #[test]
+    fn test_two_q() {
+        let mut replacer = TwoQReplacer::default();
+        for i in 0..8 {
+            replacer.record_access(i, i as i32, AccessType::Get);
+        }
+
+        // Evict page 0 from a1in, bringing it back puts it in am
+        assert!(replacer.evict() == Some(0));
+        replacer.remove(0);
+        replacer.record_access(0, 0, AccessType::Get);
+        assert!(replacer.am.contains(0));
+
+        // Scan through a1in, the hot page is never chosen
+        for page_id in 100..120 {
+            let i = replacer.evict().unwrap();
+            assert!(i != 0, "hot page was evicted by a scan");
+            replacer.remove(i);
+            replacer.record_access(i, page_id, AccessType::Get);
+        }
+
+        // Fall back to am once a1in is pinned
+        for i in 1..8 {
+            replacer.pin(i);
+        }
+        assert!(replacer.evict() == Some(0));
+        replacer.pin(0);
+        assert!(replacer.evict().is_none());
+    }
+
+    #[test]
+    fn test_two_q_scan() {
+        let mut replacer = TwoQReplacer::default();
+        for i in 0..4 {
+            replacer.record_access(i, i as i32, AccessType::Get);
+        }
+        for i in 4..8 {
+            replacer.record_access(i, i as i32, AccessType::Scan);
+        }
+
+        // Scanned pages are evicted before a1in and don't end up in a1out
+        for page_id in 8..20 {
+            let i = replacer.evict().unwrap();
+            assert!(i >= 4, "evicted frame {i} during a scan");
+            replacer.remove(i);
+            replacer.record_access(i, page_id, AccessType::Scan);
+        }
+        assert!(replacer.a1out.len() == 0);
+
+        // Getting a scanned page moves it to a1in
+        replacer.record_access(4, 16, AccessType::Get);
+        assert!(replacer.a1in.contains(4));
+    }
+}
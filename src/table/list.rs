@@ -4,11 +4,12 @@ use bytes::BytesMut;
 
 use crate::{
     disk::{Disk, FileSystem},
-    page::{PageBuf, PageId},
-    page_cache::{Result, SharedPageCache},
-    table::node::Node,
+    error::{Error, LockExt, Result},
+    page::{self, PageId, PageType, SegmentId},
+    page_cache::{Pin, SharedPageCache},
+    replacer::AccessType,
+    table::node::{Node, MAX_TUPLE_SIZE},
     table::tuple::{RId, Tuple, TupleMeta},
-    writep,
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -28,6 +29,7 @@ impl Default for TableMeta {
 
 pub struct List<D: Disk = FileSystem> {
     pc: SharedPageCache<D>,
+    segment: SegmentId,
     first_page_id: PageId,
     last_page_id: Mutex<PageId>,
 }
@@ -47,41 +49,59 @@ impl<D: Disk> List<D> {
 
         if first_page_id == -1 || last_page_id == -1 {
             let page = pc.new_page()?;
+            page.set_type(PageType::Table);
             first_page_id = page.id;
             last_page_id = page.id;
+            Node::from(&mut page.write_owned().data).init();
         }
 
         Ok(Self {
             pc,
+            segment: page::segment_of(first_page_id),
             first_page_id,
             last_page_id: Mutex::new(last_page_id),
         })
     }
 
     pub fn default(pc: SharedPageCache<D>) -> crate::Result<List<D>> {
-        let page = pc.new_page()?;
+        Self::in_segment(pc, 0)
+    }
+
+    /// Creates an empty table with all of its pages in `segment`.
+    pub fn in_segment(pc: SharedPageCache<D>, segment: SegmentId) -> crate::Result<List<D>> {
+        let page = pc.new_page_in(segment)?;
+        page.set_type(PageType::Table);
         let first_page_id = page.id;
         let last_page_id = page.id;
-        drop(page);
+        Node::from(&mut page.write_owned().data).init();
 
         Ok(Self {
             pc,
+            segment,
             first_page_id,
             last_page_id: Mutex::new(last_page_id),
         })
     }
 
-    fn last_page_id(&self) -> PageId {
-        *self.last_page_id.lock().expect("todo")
+    fn last_page_id(&self) -> Result<PageId> {
+        Ok(*self.last_page_id.lock().or_poisoned()?)
+    }
+
+    fn last_page_id_mut(&self) -> Result<std::sync::MutexGuard<'_, PageId>> {
+        self.last_page_id.lock().or_poisoned()
     }
 
-    fn last_page_id_mut(&self) -> std::sync::MutexGuard<'_, PageId> {
-        self.last_page_id.lock().expect("todo")
+    fn fetch(&self, page_id: PageId, access_type: AccessType) -> Result<Pin> {
+        let pin = self.pc.fetch_page_with(page_id, access_type)?;
+        // The first page may have been created by the caller and not written yet
+        pin.set_type(PageType::Table);
+
+        Ok(pin)
     }
 
     pub fn iter(&self) -> Result<Iter<'_, D>> {
-        let last_page_id = self.last_page_id();
-        let page = self.pc.fetch_page(last_page_id)?;
+        let last_page_id = self.last_page_id()?;
+        let page = self.fetch(last_page_id, AccessType::Get)?;
         let page_r = page.read();
         let node = Node::from(&page_r.data);
 
@@ -95,56 +115,61 @@ impl<D: Disk> List<D> {
                 page_id: last_page_id,
                 slot_id: node.len(),
             },
+            sequential: false,
         })
     }
 
     pub fn insert(&self, tuple_data: &BytesMut, meta: &TupleMeta) -> Result<Option<RId>> {
-        let mut last_page_id = self.last_page_id_mut();
-        let page = self.pc.fetch_page(*last_page_id)?;
-        let mut page_w = page.write();
-        let mut node = Node::from(&page_w.data);
-
-        if let Some(slot_id) = node.insert(tuple_data, meta) {
-            writep!(page_w, &PageBuf::from(&node));
-            return Ok(Some(RId {
+        if tuple_data.len() > MAX_TUPLE_SIZE {
+            return Err(Error::TupleTooLarge {
+                size: tuple_data.len(),
+            });
+        }
+
+        let mut last_page_id = self.last_page_id_mut()?;
+        let page = self.fetch(*last_page_id, AccessType::Get)?;
+        let mut page_w = page.write_owned();
+
+        // Only take the page mutably once the tuple is known to fit, so it isn't dirtied for nothing
+        if Node::from(&page_w.data)
+            .next_tuple_offset(tuple_data, *last_page_id)?
+            .is_some()
+        {
+            let slot_id = Node::from(&mut page_w.data).insert(tuple_data, meta, *last_page_id)?;
+            return Ok(slot_id.map(|slot_id| RId {
                 page_id: *last_page_id,
                 slot_id,
             }));
         }
 
-        if node.len() == 0 {
-            todo!("tuple too large error")
-        }
-
         // Insert into a new page and set the next pointer
-        let npage = self.pc.new_page()?;
-        let mut npage_w = npage.write();
-        node.next_page_id = npage.id;
-        *last_page_id = npage.id;
-
-        // Write the next page id on first node
-        // TODO: just write the page id instead of the entire page?
-        writep!(page_w, &PageBuf::from(&node));
-
-        let mut node = Node::from(&npage_w.data);
-        match node.insert(tuple_data, meta) {
-            Some(slot_id) => {
-                writep!(npage_w, &PageBuf::from(&node));
-                Ok(Some(RId {
-                    page_id: *last_page_id,
-                    slot_id,
-                }))
-            }
+        let npage = self.pc.new_page_in(self.segment)?;
+        npage.set_type(PageType::Table);
+        let mut npage_w = npage.write_owned();
+        Node::from(&mut page_w.data).set_next_page_id(npage_w.id());
+        *last_page_id = npage_w.id();
+
+        let mut node = Node::from(&mut npage_w.data);
+        node.init();
+        match node.insert(tuple_data, meta, *last_page_id)? {
+            Some(slot_id) => Ok(Some(RId {
+                page_id: *last_page_id,
+                slot_id,
+            })),
             None => unreachable!(),
         }
     }
 
     pub fn get(&self, r_id: RId) -> Result<Option<(TupleMeta, Tuple)>> {
-        let page = self.pc.fetch_page(r_id.page_id)?;
+        self.get_with(r_id, AccessType::Get)
+    }
+
+    fn get_with(&self, r_id: RId, access_type: AccessType) -> Result<Option<(TupleMeta, Tuple)>> {
+        let page = self.fetch(r_id.page_id, access_type)?;
         let page_r = page.read();
         let node = Node::from(&page_r.data);
 
-        let mut tuple = node.get(&r_id);
+        let mut tuple = node.get(&r_id)?;
         if let Some((_, tuple)) = &mut tuple {
             tuple.rid = r_id;
         }
@@ -152,8 +177,18 @@ impl<D: Disk> List<D> {
         Ok(tuple)
     }
 
-    pub fn update(&mut self, _meta: &TupleMeta) -> Result<()> {
-        todo!()
+    /// Replaces the meta of the tuple at `r_id`, such as to mark it deleted. Returns false if
+    /// there's no tuple there.
+    pub fn update(&self, r_id: RId, meta: &TupleMeta) -> Result<bool> {
+        let page = self.fetch(r_id.page_id, AccessType::Get)?;
+        let mut page_w = page.write_owned();
+
+        // Only take the page mutably if there's a slot to change, so it isn't dirtied for nothing
+        if r_id.slot_id >= Node::from(&page_w.data).len() {
+            return Ok(false);
+        }
+
+        Node::from(&mut page_w.data).update_meta(&r_id, meta)
     }
 }
 
@@ -162,6 +197,8 @@ pub struct Iter<'a, D: Disk = FileSystem> {
     list: &'a List<D>,
     r_id: RId,
     end: RId,
+    /// Set once the iterator moves on to a next page, from then on the page after it is read ahead
+    sequential: bool,
 }
 
 impl<'a, D: Disk> Iterator for Iter<'a, D> {
@@ -172,7 +209,7 @@ impl<'a, D: Disk> Iterator for Iter<'a, D> {
             return None;
         }
 
-        let result = match self.list.get(self.r_id) {
+        let result = match self.list.get_with(self.r_id, AccessType::Scan) {
             Ok(opt) => match opt {
                 Some(t) => Ok(t),
                 None => return None,
@@ -180,26 +217,32 @@ impl<'a, D: Disk> Iterator for Iter<'a, D> {
             Err(e) => Err(e),
         };
 
-        let page = match self.list.pc.fetch_page(self.r_id.page_id) {
+        let page = match self.list.fetch(self.r_id.page_id, AccessType::Scan) {
             Ok(p) => p,
             Err(e) => return Some(Err(e)),
         };
         let page_r = page.read();
         let node = Node::from(&page_r.data);
 
+        if self.sequential && self.r_id.slot_id == 0 && self.r_id.page_id != self.end.page_id {
+            // Read ahead is only a hint
+            let _ = self.list.pc.prefetch(&[node.next_page_id()]);
+        }
+
         if self.r_id.page_id == self.end.page_id && self.r_id.slot_id == self.end.slot_id - 1 {
             // Last tuple, increment (so the next iteration returns None) and return result
             self.r_id.slot_id += 1;
             return Some(result);
         } else if self.r_id.slot_id + 1 < node.len() {
             self.r_id.slot_id += 1;
-        } else if node.next_page_id == 0 {
+        } else if node.next_page_id() == 0 {
             return None;
         } else {
             self.r_id = RId {
-                page_id: node.next_page_id,
+                page_id: node.next_page_id(),
                 slot_id: 0,
-            }
+            };
+            self.sequential = true;
         }
 
         Some(result)
@@ -211,23 +254,23 @@ mod test {
     use bytes::BytesMut;
 
     use crate::{
-        disk::Memory,
-        page::PAGE_SIZE,
-        page_cache::PageCache,
+        disk::{fault::Faulty, Memory},
+        error::Error,
+        page::{PageType, PAGE_SIZE},
+        page_cache::{PageCache, CACHE_SIZE},
         replacer::LRU,
         table::list::List,
         table::{
             list::TableMeta,
-            tuple::{Tuple, TupleMeta},
+            tuple::{RId, Tuple, TupleMeta},
         },
     };
 
     #[test]
     fn test_table() -> crate::Result<()> {
-        const MEMORY: usize = PAGE_SIZE * 1;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let lru = LRU::new(K);
         let pc = PageCache::new(disk, lru, 0);
 
@@ -243,7 +286,7 @@ mod test {
             pc,
             TableMeta {
                 first_page_id: list.first_page_id,
-                last_page_id: list.last_page_id(),
+                last_page_id: list.last_page_id()?,
             },
         )?;
 
@@ -253,15 +296,33 @@ mod test {
         assert_eq!(tuple_a, have_a.data);
         assert_eq!(tuple_b, have_b.data);
 
+        // Past the last slot
+        let r_id_c = RId {
+            slot_id: r_id_b.slot_id + 1,
+            ..r_id_b
+        };
+        assert!(list.get(r_id_c)?.is_none());
+
+        // Only the meta changes
+        let deleted = TupleMeta { deleted: true };
+        assert!(list.update(r_id_a, &deleted)?);
+        assert!(list.get(r_id_a)? == Some((deleted, have_a)));
+        assert!(list.get(r_id_b)?.unwrap().0 == meta);
+        assert!(!list.update(r_id_c, &deleted)?);
+
+        // Too large to fit in an empty page
+        let tuple = BytesMut::zeroed(PAGE_SIZE);
+        let have = list.insert(&tuple, &meta);
+        assert!(have == Err(Error::TupleTooLarge { size: PAGE_SIZE }), "Have: {have:?}");
+
         Ok(())
     }
 
     #[test]
     fn test_iter() -> crate::Result<()> {
-        const MEMORY: usize = PAGE_SIZE * 4;
         const K: usize = 2;
 
-        let disk = Memory::new::<MEMORY>();
+        let disk = Memory::new();
         let lru = LRU::new(K);
         let pc = PageCache::new(disk, lru, 0);
 
@@ -296,6 +357,65 @@ mod test {
             assert_eq!(tuples[i], tuple.data)
         }
 
+        let types = pc.page_types();
+        assert!(types.len() == 1 && types[&PageType::Table] > 1, "{types:?}");
+
+        // Evict the table, leaving free frames to read ahead into
+        pc.flush_all_pages()?;
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+        pc.resize(CACHE_SIZE / 2)?;
+        pc.resize(CACHE_SIZE)?;
+
+        // Once on its second page, the iterator reads ahead the page after the one it's on. The
+        // last page is already resident, `iter` reads it to find the end
+        let prefetched = pc.stats().prefetched;
+        assert!(list.iter()?.count() == WANT_LEN);
+        assert!(pc.stats().prefetched - prefetched == types[&PageType::Table] as u64 - 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_errors() -> crate::Result<()> {
+        const K: usize = 2;
+
+        let disk = Faulty::new(Memory::new());
+        let lru = LRU::new(K);
+        let pc = PageCache::new(disk, lru, 0);
+
+        let list = List::default(pc.clone())?;
+        let meta = TupleMeta { deleted: false };
+        let tuple = BytesMut::from(&[1; 150][..]);
+
+        for _ in 0..50 {
+            list.insert(&tuple, &meta)?;
+        }
+
+        // Inserting into a new page fails
+        pc.disk().fail_read(0);
+        let have = (0..100).try_for_each(|_| list.insert(&tuple, &meta).map(|_| ()));
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        // Evict the table and fail reading it back in
+        pc.flush_all_pages()?;
+        let mut pages = Vec::new();
+        for _ in 0..CACHE_SIZE {
+            pages.push(pc.new_page()?);
+        }
+        drop(pages);
+
+        pc.disk().fail_read(0);
+        let have = list.iter();
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
+        pc.disk().fail_read(1);
+        let have = list.iter()?.collect::<crate::Result<Vec<_>>>();
+        assert!(matches!(have, Err(Error::Disk(_))), "Expected a disk error");
+
         Ok(())
     }
 }
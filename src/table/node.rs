@@ -1,15 +1,16 @@
-use std::ops::Range;
+use std::ops::{Deref, DerefMut, Range};
 
 use bytes::BytesMut;
 
 use crate::{
-    page::{PageBuf, PageId, PAGE_SIZE},
+    error::Error,
+    page::{Header, PageBuf, PageId, PageType, PAGE_HEADER_SIZE, PAGE_SIZE},
     table::tuple::{RId, Slot, Tuple, TupleInfoBuf, TupleMeta},
 };
 
 /*
     TablePage:
-    NextPageID | NumTuples | NumDeletedTuples | Slots | Free | Tuples
+    Header | NextPageID | NumTuples | NumDeletedTuples | Slots | Free | Tuples
 
     Slot:
     TupleInfo
@@ -18,142 +19,145 @@ use crate::{
     RId | Data
 */
 
-pub const NEXT_PAGE_ID: Range<usize> = 0..4;
-pub const TUPLES_LEN: Range<usize> = 4..8;
-pub const DELETED_TUPLES_LEN: Range<usize> = 8..12;
-pub const SLOTS_START: usize = 12;
-
-#[derive(Debug, PartialEq)]
-pub struct Node {
-    page_start: *mut u8,
-    pub next_page_id: PageId,
-    deleted_tuples_len: u32,
-    slots: Vec<Slot>,
-}
+pub const NEXT_PAGE_ID: Range<usize> = PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 4;
+pub const TUPLES_LEN: Range<usize> = NEXT_PAGE_ID.end..NEXT_PAGE_ID.end + 4;
+pub const DELETED_TUPLES_LEN: Range<usize> = TUPLES_LEN.end..TUPLES_LEN.end + 4;
+pub const SLOTS_START: usize = DELETED_TUPLES_LEN.end;
 
-impl From<&PageBuf> for Node {
-    fn from(buf: &PageBuf) -> Self {
-        let page_start = buf.as_ptr() as *mut u8;
-        let next_page_id = i32::from_be_bytes(buf[NEXT_PAGE_ID].try_into().unwrap());
-        let tuples_len = u32::from_be_bytes(buf[TUPLES_LEN].try_into().unwrap());
-        let deleted_tuples_len = u32::from_be_bytes(buf[DELETED_TUPLES_LEN].try_into().unwrap());
-
-        let mut slots = Vec::new();
-        const SLOT_SIZE: usize = Slot::SIZE;
-        let left = &buf[SLOTS_START..];
-        let mut from = 0;
-        let mut rem = tuples_len;
-        while rem > 0 {
-            let bytes = &left[from..from + SLOT_SIZE];
-            let slot = Slot::from(bytes);
-            slots.push(slot);
-            from = from + SLOT_SIZE;
-            rem -= 1;
-        }
+/// Largest tuple that fits in an empty page.
+pub const MAX_TUPLE_SIZE: usize = PAGE_SIZE - SLOTS_START - Slot::SIZE;
 
-        Self {
-            page_start,
-            next_page_id,
-            deleted_tuples_len,
-            slots,
-        }
+/// Zero-copy view of a table page, over either `&PageBuf` or `&mut PageBuf`.
+pub struct Node<B>(B);
+
+impl<B> From<B> for Node<B> {
+    fn from(buf: B) -> Self {
+        Self(buf)
     }
 }
 
-impl From<&Node> for PageBuf {
-    fn from(table: &Node) -> Self {
-        let mut ret: PageBuf = [0; PAGE_SIZE];
+fn slot_range(slot_id: u32) -> Range<usize> {
+    let start = SLOTS_START + slot_id as usize * Slot::SIZE;
 
-        ret[NEXT_PAGE_ID].copy_from_slice(&table.next_page_id.to_be_bytes());
-        ret[TUPLES_LEN].copy_from_slice(&(table.slots.len() as u32).to_be_bytes());
-        ret[DELETED_TUPLES_LEN].copy_from_slice(&table.deleted_tuples_len.to_be_bytes());
+    start..start + Slot::SIZE
+}
 
-        const SLOT_SIZE: usize = Slot::SIZE;
-        let mut from = SLOTS_START;
-        for slot in &table.slots {
-            let slot = TupleInfoBuf::from(slot);
-            ret[from..from + SLOT_SIZE].copy_from_slice(&slot);
-            from += SLOT_SIZE;
-        }
+impl<B: Deref<Target = PageBuf>> Node<B> {
+    pub fn next_page_id(&self) -> PageId {
+        PageId::from_be_bytes(self.0[NEXT_PAGE_ID].try_into().unwrap())
+    }
 
-        let offset = match table.slots.last() {
-            Some(o) => o.offset as usize,
-            None => return ret,
-        };
-        assert!(offset < PAGE_SIZE, "tuple being written at PAGE_SIZE or greater");
+    pub fn len(&self) -> u32 {
+        u32::from_be_bytes(self.0[TUPLES_LEN].try_into().unwrap())
+    }
 
-        unsafe {
-            let tuples_ptr = table.page_start.add(offset);
-            let tuples = std::slice::from_raw_parts(tuples_ptr, PAGE_SIZE - offset);
-            ret[offset..].copy_from_slice(tuples);
+    /// Slot `slot_id` of the page `page_id`, checking that the slots fit in the page and that its
+    /// tuple lies between them and the end of the page.
+    fn slot(&self, slot_id: u32, page_id: PageId) -> crate::Result<Slot> {
+        let slots_end = SLOTS_START + Slot::SIZE * self.len() as usize;
+        if slots_end > PAGE_SIZE {
+            return Err(Error::Corrupted { page_id });
         }
 
-        ret
-    }
-}
-
-impl Node {
-    const HEADER_SIZE: usize = 12;
+        let slot = Slot::from(&self.0[slot_range(slot_id)]);
+        if (slot.offset as usize) < slots_end
+            || slot.offset as usize + slot.len as usize > PAGE_SIZE
+        {
+            return Err(Error::Corrupted { page_id });
+        }
 
-    pub fn len(&self) -> u32 {
-        self.slots.len() as u32
+        Ok(slot)
     }
 
-    pub fn next_tuple_offset(&self, tuple_data: &BytesMut) -> Option<usize> {
-        let offset = match self.slots.last() {
-            Some(slot) => slot.offset as usize,
-            None => PAGE_SIZE,
+    pub fn next_tuple_offset(
+        &self,
+        tuple_data: &BytesMut,
+        page_id: PageId,
+    ) -> crate::Result<Option<usize>> {
+        let offset = match self.len() {
+            0 => PAGE_SIZE,
+            len => self.slot(len - 1, page_id)?.offset as usize,
         };
 
-        let tuple_offset = offset - tuple_data.len();
+        let Some(tuple_offset) = offset.checked_sub(tuple_data.len()) else {
+            return Ok(None);
+        };
 
         // Ensure tuple isn't written over header/slots
-        let size = Self::HEADER_SIZE + Slot::SIZE * (self.len() as usize + 1);
+        let size = SLOTS_START + Slot::SIZE * (self.len() as usize + 1);
         if tuple_offset < size {
-            return None;
+            return Ok(None);
+        }
+
+        Ok(Some(tuple_offset))
+    }
+
+    pub fn get(&self, r_id: &RId) -> crate::Result<Option<(TupleMeta, Tuple)>> {
+        if r_id.slot_id >= self.len() {
+            return Ok(None);
         }
 
-        Some(tuple_offset)
+        let Slot { offset, len, meta } = self.slot(r_id.slot_id, r_id.page_id)?;
+        let tuple = Tuple {
+            rid: *r_id,
+            data: BytesMut::from(&self.0[offset as usize..(offset + len) as usize]),
+        };
+
+        Ok(Some((meta, tuple)))
+    }
+}
+
+impl<B: DerefMut<Target = PageBuf>> Node<B> {
+    /// Formats a new page as an empty table page.
+    pub fn init(&mut self) {
+        Header::from(&mut *self.0).init(PageType::Table);
+    }
+
+    pub fn set_next_page_id(&mut self, page_id: PageId) {
+        self.0[NEXT_PAGE_ID].copy_from_slice(&page_id.to_be_bytes());
     }
 
-    pub fn insert(&mut self, tuple_data: &BytesMut, meta: &TupleMeta) -> Option<u32> {
-        let offset = self.next_tuple_offset(tuple_data)?;
+    pub fn insert(
+        &mut self,
+        tuple_data: &BytesMut,
+        meta: &TupleMeta,
+        page_id: PageId,
+    ) -> crate::Result<Option<u32>> {
+        let Some(offset) = self.next_tuple_offset(tuple_data, page_id)? else {
+            return Ok(None);
+        };
+        if Header::from(&*self.0).version() == 0 {
+            // Never written, pages handed to `List::new` may not have been formatted
+            self.init();
+        }
+
         let slot_id = self.len();
-        self.slots.push(Slot {
+        let slot = Slot {
             offset: offset as u32,
             len: tuple_data.len() as u32,
             meta: *meta,
-        });
+        };
 
-        unsafe {
-            // TODO: This writes to the page buffer but doesn't set the dirty flag
-            let tuples_ptr = self.page_start.add(offset);
-            let tuples = std::slice::from_raw_parts_mut(tuples_ptr, PAGE_SIZE - offset);
-            tuples[..tuple_data.len()].copy_from_slice(&tuple_data);
-        }
+        self.0[slot_range(slot_id)].copy_from_slice(&TupleInfoBuf::from(&slot));
+        self.0[offset..offset + tuple_data.len()].copy_from_slice(tuple_data);
+        self.0[TUPLES_LEN].copy_from_slice(&(slot_id + 1).to_be_bytes());
 
-        Some(slot_id)
+        Ok(Some(slot_id))
     }
 
-    pub fn get(&self, r_id: &RId) -> Option<(TupleMeta, Tuple)> {
-        let slot_id = r_id.slot_id;
-        if slot_id > self.len() {
-            todo!()
+    /// Replaces the meta of the tuple at `r_id`. Returns false if there's no such slot.
+    pub fn update_meta(&mut self, r_id: &RId, meta: &TupleMeta) -> crate::Result<bool> {
+        if r_id.slot_id >= self.len() {
+            return Ok(false);
         }
 
-        let Slot { offset, len, meta } = self.slots[slot_id as usize];
-        let mut tuple = Tuple {
-            rid: *r_id,
-            data: BytesMut::zeroed(len as usize),
+        let slot = Slot {
+            meta: *meta,
+            ..self.slot(r_id.slot_id, r_id.page_id)?
         };
+        self.0[slot_range(r_id.slot_id)].copy_from_slice(&TupleInfoBuf::from(&slot));
 
-        unsafe {
-            let tuple_ptr = self.page_start.add(offset as usize);
-            let tuple_data = std::slice::from_raw_parts(tuple_ptr, len as usize);
-            tuple.data[..].copy_from_slice(tuple_data);
-        }
-
-        Some((meta, tuple))
+        Ok(true)
     }
 }
 
@@ -162,66 +166,20 @@ mod test {
     use bytes::BytesMut;
 
     use crate::{
-        page::{PageBuf, PAGE_SIZE},
-        table::node::{Node, RId, Slot, Tuple, TupleMeta},
+        error::Error,
+        page::{Header, PageType, PAGE_SIZE},
+        table::{
+            node::{slot_range, Node, RId, Tuple, TupleMeta, MAX_TUPLE_SIZE, TUPLES_LEN},
+            tuple::{LEN, OFFSET},
+        },
     };
 
     #[test]
-    fn test_from() {
-        let mut buf = [0; PAGE_SIZE];
-
-        let tuple_a = std::array::from_fn::<u8, 10, _>(|i| (i * 2) as u8);
-        let tuple_b = std::array::from_fn::<u8, 15, _>(|i| (i * 3) as u8);
-
-        buf[PAGE_SIZE - 10..].copy_from_slice(&tuple_a);
-        buf[PAGE_SIZE - 25..PAGE_SIZE - 10].copy_from_slice(&tuple_b);
-
-        let mut table = Node {
-            page_start: buf.as_mut_ptr(),
-            next_page_id: 10,
-            deleted_tuples_len: 0,
-            slots: vec![
-                Slot {
-                    offset: (PAGE_SIZE - 10) as u32,
-                    len: 10,
-                    meta: TupleMeta { deleted: false },
-                },
-                Slot {
-                    offset: (PAGE_SIZE - 25) as u32,
-                    len: 15,
-                    meta: TupleMeta { deleted: false },
-                },
-            ],
-        };
-
-        let bytes = PageBuf::from(&table);
-
-        let mut table2 = Node::from(&bytes);
-
-        let offset = table.slots.last().unwrap().offset as usize;
-        let tuples = unsafe {
-            let tuples_ptr = table2.page_start.add(offset);
-            std::slice::from_raw_parts(tuples_ptr, PAGE_SIZE - offset)
-        };
-        assert_eq!(&tuples[0..15], &tuple_b);
-        assert_eq!(&tuples[15..], &tuple_a);
-
-        table.page_start = std::ptr::null_mut();
-        table2.page_start = std::ptr::null_mut();
-
-        assert_eq!(table, table2);
-    }
-
-    #[test]
-    fn test_insert() {
+    fn test_insert() -> crate::Result<()> {
         let mut buf = [0; PAGE_SIZE];
-
-        let mut table = Node {
-            page_start: buf.as_mut_ptr(),
-            next_page_id: 0,
-            deleted_tuples_len: 0,
-            slots: Vec::new(),
-        };
+        let mut table = Node::from(&mut buf);
+        table.init();
+        table.set_next_page_id(10);
 
         let meta = TupleMeta { deleted: false };
 
@@ -237,11 +195,19 @@ mod test {
         };
         let tuple_b = BytesMut::from(&std::array::from_fn::<u8, 15, _>(|i| (i * 3) as u8)[..]);
 
-        table.insert(&tuple_a, &meta);
-        table.insert(&tuple_b, &meta);
+        assert!(table.insert(&tuple_a, &meta, 0)? == Some(0));
+        assert!(table.insert(&tuple_b, &meta, 0)? == Some(1));
+
+        // Tuples are written from the end of the page
+        assert!(buf[PAGE_SIZE - 10..] == tuple_a[..]);
+        assert!(buf[PAGE_SIZE - 25..PAGE_SIZE - 10] == tuple_b[..]);
 
-        let (_, have_a) = table.get(&r_id_a).unwrap();
-        let (_, have_b) = table.get(&r_id_b).unwrap();
+        let table = Node::from(&buf);
+        assert!(Header::from(&buf).page_type() == PageType::Table);
+        assert!(table.next_page_id() == 10 && table.len() == 2);
+
+        let (_, have_a) = table.get(&r_id_a)?.unwrap();
+        let (_, have_b) = table.get(&r_id_b)?.unwrap();
         assert_eq!(
             Tuple {
                 data: tuple_a,
@@ -255,6 +221,65 @@ mod test {
                 rid: r_id_b
             },
             have_b
-        )
+        );
+        assert!(table
+            .get(&RId {
+                page_id: 0,
+                slot_id: 2
+            })?
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_full() -> crate::Result<()> {
+        let meta = TupleMeta { deleted: false };
+
+        let mut buf = [0; PAGE_SIZE];
+        let mut table = Node::from(&mut buf);
+        assert!(table
+            .insert(&BytesMut::zeroed(MAX_TUPLE_SIZE + 1), &meta, 0)?
+            .is_none());
+        assert!(table.insert(&BytesMut::zeroed(MAX_TUPLE_SIZE), &meta, 0)? == Some(0));
+        assert!(table.insert(&BytesMut::zeroed(0), &meta, 0)?.is_none());
+        assert!(table.len() == 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted() -> crate::Result<()> {
+        let meta = TupleMeta { deleted: false };
+        let mut buf = [0; PAGE_SIZE];
+        let mut table = Node::from(&mut buf);
+        table.init();
+        for _ in 0..2 {
+            table.insert(&BytesMut::zeroed(10), &meta, 3)?;
+        }
+
+        let r_id = RId {
+            page_id: 3,
+            slot_id: 1,
+        };
+        let corrupt = |f: &dyn Fn(&mut [u8; PAGE_SIZE])| {
+            let mut buf = buf;
+            f(&mut buf);
+            let table = Node::from(&buf);
+            (table.get(&r_id).err(), table.next_tuple_offset(&BytesMut::zeroed(10), 3).err())
+        };
+        let want = || Some(Error::Corrupted { page_id: 3 });
+
+        let slot = slot_range(1).start;
+        // Tuple running past the end of the page
+        assert!(corrupt(&|b| b[slot + LEN.start..slot + LEN.end].fill(0xFF)) == (want(), want()));
+        // Tuple over the slots
+        assert!(
+            corrupt(&|b| b[slot + OFFSET.start..slot + OFFSET.end].fill(0)) == (want(), want())
+        );
+        // More slots than fit in the page
+        assert!(corrupt(&|b| b[TUPLES_LEN].fill(0xFF)) == (want(), want()));
+
+        Ok(())
     }
 }
@@ -65,7 +65,7 @@ impl Value {
                 Value::BigInt(i64::from_be_bytes(data.try_into().unwrap()))
             }
             Type::Varchar => {
-                let str = std::str::from_utf8(data).expect("todo");
+                let str = std::str::from_utf8(data).expect("varchar columns are written as utf8");
                 Value::Varchar(str.into())
             }
         }
@@ -242,7 +242,7 @@ pub struct TupleMeta {
 
 impl From<&[u8]> for TupleMeta {
     fn from(value: &[u8]) -> Self {
-        let deleted = u8::from_be_bytes(value[0..1].try_into().unwrap()) > 1;
+        let deleted = value[0] != 0;
 
         Self { deleted }
     }
@@ -286,6 +286,33 @@ impl From<&Slot> for TupleInfoBuf {
     }
 }
 
+/// Compares tuples laid out by `schema` in place. Integer columns are compared without decoding
+/// them into `Value`s.
+pub fn compare(schema: &Schema, a: &[u8], b: &[u8]) -> Ordering {
+    macro_rules! cmp_int {
+        ($t:ty, $offset:expr) => {{
+            const SIZE: usize = size_of::<$t>();
+            let a = <$t>::from_be_bytes(a[$offset..$offset + SIZE].try_into().unwrap());
+            let b = <$t>::from_be_bytes(b[$offset..$offset + SIZE].try_into().unwrap());
+            a.cmp(&b)
+        }};
+    }
+
+    for column in schema.iter() {
+        let ord = match column.ty {
+            Type::TinyInt => cmp_int!(i8, column.offset),
+            Type::Int => cmp_int!(i32, column.offset),
+            Type::BigInt => cmp_int!(i64, column.offset),
+            _ => Value::from(column, a).cmp(&Value::from(column, b)),
+        };
+        if ord != Equal {
+            return ord;
+        }
+    }
+
+    Equal
+}
+
 // pub struct Comparand<'a, 'b, T>(pub &'a Schema, pub &'b T);
 pub struct Comparand<'a, T>(pub &'a Schema, pub T);
 
@@ -304,18 +331,7 @@ impl<'a, 'b> PartialOrd for Comparand<'a, &'b Tuple> {
 
 impl<'a, 'b> Ord for Comparand<'a, &'b Tuple> {
     fn cmp(&self, other: &Self) -> Ordering {
-        for (_, col) in self.0.iter().enumerate() {
-            let lhs = self.1.get_value(col);
-            let rhs = other.1.get_value(col);
-
-            match lhs.cmp(&rhs) {
-                Less => return Less,
-                Greater => return Greater,
-                _ => {}
-            }
-        }
-
-        Equal
+        compare(self.0, &self.1.data, &other.1.data)
     }
 }
 
@@ -426,7 +442,7 @@ mod test {
 
     use crate::{
         catalog::{Column, Schema, Type},
-        table::tuple::{Comparand, Tuple, TupleBuilder, Value},
+        table::tuple::{compare, Comparand, Tuple, TupleBuilder, Value},
     };
 
     #[test]
@@ -732,4 +748,32 @@ mod test {
             assert_eq!(want, have);
         }
     }
+
+    #[test]
+    fn test_compare() {
+        let schema: Schema = [("a", Type::Int), ("b", Type::Varchar), ("c", Type::BigInt)].into();
+        let key = |a: i32, b: &str, c: i64| {
+            TupleBuilder::new()
+                .add(&Value::Int(a))
+                .add(&Value::Varchar(b.into()))
+                .add(&Value::BigInt(c))
+                .build()
+        };
+
+        let keys = [
+            key(i32::MIN, "b", 0),
+            key(-1, "a", 5),
+            key(-1, "b", -5),
+            key(-1, "b", 0),
+            key(0, "", 0),
+            key(1, "a", i64::MIN),
+            key(1, "ab", -1),
+            key(i32::MAX, "a", 0),
+        ];
+        for (i, a) in keys.iter().enumerate() {
+            for (j, b) in keys.iter().enumerate() {
+                assert!(compare(&schema, a, b) == i.cmp(&j), "{i} {j}");
+            }
+        }
+    }
 }